@@ -0,0 +1,406 @@
+//! Invoking conda.
+
+use std::env;
+use std::fs::copy;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use serde_json::{self, Value};
+use tempfile::{tempdir, NamedTempFile};
+
+use explicit::ExplicitSpec;
+use lockfile::{LockFormat, Lockfile};
+use platform::{Platform, VirtualPackage};
+use {Error, Result};
+
+/// Locate the conda executable from the environment of an activated conda install.
+pub fn find_conda() -> Result<String> {
+    match env::var("CONDA_EXE") {
+        Ok(conda) => Ok(conda),
+        Err(_) => match env::var("_CONDA_EXE") {
+            Ok(conda) => Ok(conda),
+            Err(_) => Err(Error::CondaNotFound),
+        },
+    }
+}
+
+/// An environment, by name or by prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvTarget {
+    /// An environment in one of conda's `envs_dirs`.
+    Name(String),
+    Prefix(PathBuf),
+}
+
+impl EnvTarget {
+    /// The directory the environment is in.
+    pub fn prefix(&self) -> Result<PathBuf> {
+        match *self {
+            EnvTarget::Name(ref name) => env_prefix(&find_conda()?, name),
+            EnvTarget::Prefix(ref prefix) => Ok(prefix.clone()),
+        }
+    }
+
+    /// Point a conda command at the environment.
+    fn add_args(&self, command: &mut Command) {
+        match *self {
+            EnvTarget::Name(ref name) => command.arg("--name").arg(name),
+            EnvTarget::Prefix(ref prefix) => command.arg("--prefix").arg(prefix),
+        };
+    }
+}
+
+/// The prefix of the environment named `name`, as conda would find it.
+pub fn env_prefix(conda_path: &str, name: &str) -> Result<PathBuf> {
+    let output = Command::new(conda_path).args(["info", "--json"]).output()?;
+    let output = check_output(output)?;
+    let info = String::from_utf8(output.stdout).map_err(|e| Error::Conda(e.to_string()))?;
+    prefix_from_info(&info, name)
+}
+
+/// Find the environment named `name` in the output of `conda info --json`.
+///
+/// Like conda, this looks in each of `envs_dirs` in turn.  An environment that doesn't exist
+/// yet goes in the first of them.
+fn prefix_from_info(info: &str, name: &str) -> Result<PathBuf> {
+    let info: Value = serde_json::from_str(info)
+        .map_err(|e| Error::Conda(format!("Unexpected conda info: {}", e)))?;
+    let paths = |key: &str| -> Vec<PathBuf> {
+        match info.get(key) {
+            Some(Value::Array(paths)) => {
+                paths.iter().filter_map(Value::as_str).map(PathBuf::from).collect()
+            }
+            _ => Vec::new(),
+        }
+    };
+    if name == "base" || name == "root" {
+        if let Some(root) = info.get("root_prefix").and_then(Value::as_str) {
+            return Ok(PathBuf::from(root));
+        }
+    }
+    let envs_dirs = paths("envs_dirs");
+    let envs = paths("envs");
+    let candidates: Vec<PathBuf> = envs_dirs.iter().map(|dir| dir.join(name)).collect();
+    if let Some(prefix) = candidates.iter().find(|prefix| envs.contains(prefix)) {
+        return Ok(prefix.clone());
+    }
+    candidates
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::Conda("conda info lists no envs_dirs".to_string()))
+}
+
+/// What conda and pip report when the requested packages can't be installed together, as
+/// opposed to failing for some other reason, like a network error.
+const UNSATISFIABLE_MARKERS: &[&str] = &[
+    "UnsatisfiableError",
+    "PackagesNotFoundError",
+    "ResolvePackageNotFound",
+    "ResolutionImpossible",
+    "No matching distribution found",
+];
+
+/// Does the output of a failed conda run say the request can't be satisfied?
+pub fn is_unsatisfiable(output: &str) -> bool {
+    UNSATISFIABLE_MARKERS.iter().any(|marker| output.contains(marker))
+}
+
+/// Turn an unsuccessful conda invocation into an error.
+fn check_output(output: Output) -> Result<Output> {
+    if output.status.success() {
+        return Ok(output);
+    }
+    let msg = format!(
+        "{}\n{}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout).trim(),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    let msg = msg.trim().to_string();
+    if is_unsatisfiable(&msg) {
+        return Err(Error::Unsatisfiable(msg));
+    }
+    Err(Error::Conda(msg))
+}
+
+/// Point conda's virtual package detection at `virtual_packages` instead of this machine.
+fn override_virtual_packages(command: &mut Command, virtual_packages: &[VirtualPackage]) {
+    for package in virtual_packages {
+        if let Some(var) = package.override_var() {
+            command.env(var, &package.version);
+        }
+    }
+}
+
+/// `conda env create` the environment described by `env_file` at `target`, replacing any
+/// existing environment there.
+pub fn env_create(
+    conda_path: &str,
+    env_file: &Path,
+    target: &EnvTarget,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    let mut command = Command::new(conda_path);
+    override_virtual_packages(&mut command, virtual_packages);
+    command
+        .arg("env")
+        .arg("create")
+        .arg("--force")
+        .arg("-q")
+        .arg("--json");
+    target.add_args(&mut command);
+    let output = command.arg("-f").arg(env_file).output()?;
+    debug!("{:?}", output);
+    check_output(output)?;
+    Ok(())
+}
+
+/// `conda env export` the environment named `name`.
+pub fn env_export(conda_path: &str, name: &str) -> Result<String> {
+    let output = Command::new(conda_path)
+        .args(["env", "export", "-n", name])
+        .output()?;
+    let output = check_output(output)?;
+    String::from_utf8(output.stdout).map_err(|e| Error::Conda(e.to_string()))
+}
+
+/// `conda list --explicit --md5` the environment named `name`.
+pub fn list_explicit(conda_path: &str, name: &str) -> Result<String> {
+    let output = Command::new(conda_path)
+        .args(["list", "--explicit", "--md5", "-n", name])
+        .output()?;
+    let output = check_output(output)?;
+    String::from_utf8(output.stdout).map_err(|e| Error::Conda(e.to_string()))
+}
+
+/// `conda create` an environment from an explicit file, replacing any existing environment
+/// there.  Conda installs the listed packages as they are, without solving.
+pub fn create_explicit(conda_path: &str, explicit_file: &Path, target: &EnvTarget) -> Result<()> {
+    let mut command = Command::new(conda_path);
+    command.arg("create").arg("--yes").arg("-q").arg("--json");
+    target.add_args(&mut command);
+    let output = command.arg("--file").arg(explicit_file).output()?;
+    debug!("{:?}", output);
+    check_output(output)?;
+    Ok(())
+}
+
+/// Reinstall `packages` in `prefix` as they are, leaving their dependencies alone.
+///
+/// Packages are package URLs or `name=version=build` specs; conda won't take a mix.
+pub fn force_reinstall(conda_path: &str, prefix: &Path, packages: &[String]) -> Result<()> {
+    let output = Command::new(conda_path)
+        .args(["install", "--yes", "-q", "--json", "--force-reinstall", "--no-deps"])
+        .arg("--prefix")
+        .arg(prefix)
+        .args(packages)
+        .output()?;
+    debug!("{:?}", output);
+    check_output(output)?;
+    Ok(())
+}
+
+/// Solve for `specs` on `platform` without installing anything, and return conda's JSON report.
+///
+/// This is `CONDA_SUBDIR=<platform> conda create --dry-run --json`, so it works for any target
+/// platform.
+pub fn create_dry_run(
+    conda_path: &str,
+    channels: &[String],
+    specs: &[String],
+    platform: Platform,
+    virtual_packages: &[VirtualPackage],
+) -> Result<String> {
+    // A prefix that doesn't exist, so the solve starts from nothing.
+    let tmpdir = tempdir()?;
+    let mut command = Command::new(conda_path);
+    override_virtual_packages(&mut command, virtual_packages);
+    command
+        .env("CONDA_SUBDIR", platform.subdir())
+        .args(["create", "--dry-run", "--json", "--yes", "--prefix"])
+        .arg(tmpdir.path().join("env"));
+    for channel in channels {
+        command.arg("--channel").arg(channel);
+    }
+    let output = command.args(specs).output()?;
+    debug!("{:?}", output);
+    let output = check_output(output)?;
+    String::from_utf8(output.stdout).map_err(|e| Error::Conda(e.to_string()))
+}
+
+/// A package conda's solver decided to link into an environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkAction {
+    pub name: String,
+    pub version: String,
+    pub build: String,
+    pub channel: String,
+}
+
+impl LinkAction {
+    /// The package as a lockfile entry, `name=version=build`.
+    pub fn spec(&self) -> String {
+        format!("{}={}={}", self.name, self.version, self.build)
+    }
+}
+
+/// Read the packages to link out of the JSON report of `conda create --dry-run --json`.
+pub fn parse_link_actions(json: &str) -> Result<Vec<LinkAction>> {
+    let err = |msg: &str| Error::Conda(format!("Unexpected solver output: {}", msg));
+    let report: Value = serde_json::from_str(json).map_err(|e| err(&e.to_string()))?;
+    if let Some(message) = report.get("message").and_then(Value::as_str) {
+        if is_unsatisfiable(json) {
+            return Err(Error::Unsatisfiable(message.to_string()));
+        }
+        return Err(Error::Conda(message.to_string()));
+    }
+    let links = match report.pointer("/actions/LINK") {
+        Some(Value::Array(links)) => links,
+        // Nothing to do.
+        None => return Ok(Vec::new()),
+        Some(_) => return Err(err("LINK is not a list")),
+    };
+
+    let mut actions = Vec::new();
+    for link in links {
+        let field = |name: &str| {
+            link.get(name)
+                .and_then(Value::as_str)
+                .map(|value| value.to_string())
+                .ok_or_else(|| err(&format!("link action without {}", name)))
+        };
+        actions.push(LinkAction {
+            name: field("name")?,
+            version: field("version")?,
+            build: link
+                .get("build_string")
+                .or_else(|| link.get("build"))
+                .and_then(Value::as_str)
+                .map(|value| value.to_string())
+                .ok_or_else(|| err("link action without build_string"))?,
+            channel: field("channel").unwrap_or_default(),
+        });
+    }
+    actions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(actions)
+}
+
+/// Create the environment described by a lockfile, and embed the lockfile in it so
+/// `checkenv` can later tell which depfile it was built from.
+///
+/// The environment goes at `target`, or is named after the lockfile's environment.  Explicit
+/// and conda-lock lockfiles are installed as listed, with no solve; conda-lock lockfiles
+/// install the packages of `platform`.
+pub fn create_from_lockfile(
+    lockfile_path: &Path,
+    platform: Platform,
+    target: Option<EnvTarget>,
+) -> Result<()> {
+    let lockfile = Lockfile::open_platform(lockfile_path, platform)?;
+    let target = match target {
+        Some(target) => target,
+        None if !lockfile.spec.name.is_empty() => EnvTarget::Name(lockfile.spec.name.clone()),
+        None => {
+            let msg = format!("{}: no environment name", lockfile_path.display());
+            return Err(Error::BadLockfile(msg));
+        }
+    };
+
+    let conda_path = find_conda()?;
+    info!("conda_path {}", conda_path);
+    match lockfile.format {
+        LockFormat::Env => env_create(&conda_path, lockfile_path, &target, &[])?,
+        LockFormat::Explicit => create_explicit(&conda_path, lockfile_path, &target)?,
+        LockFormat::CondaLock => {
+            if !lockfile.spec.pip_deps.is_empty() {
+                let msg = format!(
+                    "{}: installing pip packages from a conda-lock lockfile is not supported",
+                    lockfile_path.display()
+                );
+                return Err(Error::BadLockfile(msg));
+            }
+            // Hand conda this platform's packages as an explicit file.
+            let explicit = ExplicitSpec {
+                name: Some(lockfile.spec.name.clone()),
+                platform: None,
+                packages: lockfile.packages,
+            };
+            let mut explicit_file = NamedTempFile::new()?;
+            explicit_file.write_all(explicit.emit().as_bytes())?;
+            create_explicit(&conda_path, explicit_file.path(), &target)?
+        }
+    }
+
+    // Copy lockfile to constructed env
+    let mut embeded_lockfile = target.prefix()?;
+    embeded_lockfile.push("deps.yml.lock");
+    copy(lockfile_path, embeded_lockfile)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_actions() {
+        let json = r#"{
+          "actions": {
+            "FETCH": [],
+            "LINK": [
+              {"base_url": "https://conda.anaconda.org/conda-forge", "build_number": 0,
+               "build_string": "py37_0", "channel": "conda-forge",
+               "dist_name": "numpy-1.15.4-py37_0", "name": "numpy", "platform": "osx-arm64",
+               "version": "1.15.4"},
+              {"base_url": "https://repo.anaconda.com/pkgs/main", "build_number": 0,
+               "build_string": "h1de35cc_0", "channel": "pkgs/main",
+               "dist_name": "libcxx-4.0.1-h1de35cc_0", "name": "libcxx", "platform": "osx-arm64",
+               "version": "4.0.1"}
+            ],
+            "PREFIX": "/tmp/env"
+          },
+          "dry_run": true,
+          "success": true
+        }"#;
+        let actions = parse_link_actions(json).unwrap();
+        let specs: Vec<String> = actions.iter().map(LinkAction::spec).collect();
+        assert_eq!(specs, ["libcxx=4.0.1=h1de35cc_0", "numpy=1.15.4=py37_0"]);
+        assert_eq!(actions[1].channel, "conda-forge");
+    }
+
+    #[test]
+    fn env_prefixes() {
+        let info = r#"{
+          "root_prefix": "/opt/conda",
+          "envs_dirs": ["/home/me/.conda/envs", "/opt/conda/envs"],
+          "envs": ["/opt/conda", "/opt/conda/envs/foo", "/opt/app/env"]
+        }"#;
+        let prefix = |name| prefix_from_info(info, name).unwrap();
+        assert_eq!(prefix("foo"), Path::new("/opt/conda/envs/foo"));
+        assert_eq!(prefix("bar"), Path::new("/home/me/.conda/envs/bar"));
+        assert_eq!(prefix("base"), Path::new("/opt/conda"));
+        assert!(prefix_from_info("{}", "foo").is_err());
+    }
+
+    #[test]
+    fn solver_errors() {
+        let json = r#"{
+          "error": "PackagesNotFoundError",
+          "message": "The following packages are not available from current channels"
+        }"#;
+        match parse_link_actions(json) {
+            Err(Error::Unsatisfiable(msg)) => assert!(msg.contains("not available")),
+            other => panic!("unexpected {:?}", other),
+        }
+        let json = r#"{
+          "exception_name": "CondaHTTPError",
+          "message": "HTTP 000 CONNECTION FAILED"
+        }"#;
+        match parse_link_actions(json) {
+            Err(Error::Conda(msg)) => assert!(msg.contains("CONNECTION FAILED")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(parse_link_actions("not json").is_err());
+    }
+}
@@ -0,0 +1,493 @@
+//! Conda version ordering and version specs.
+//!
+//! This follows conda's `VersionOrder` and `VersionSpec`.  Versions are split into
+//! `.`-separated components, and components into runs of digits and letters, so that
+//!
+//! ```text
+//! 0.4 < 0.4.1.rc == 0.4.1.RC < 0.4.1 < 0.5a1 < 0.5b3 < 0.5
+//! 1.1dev1 < 1.1_ < 1.1a1 < 1.1.0dev1 < 1.1.0rc1 < 1.1.0 == 1.1 < 1.1.0post1 < 1.1post1
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// One run of digits or letters within a version component.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Part {
+    // Declaration order is sort order: letters < numbers < `post`.
+    Str(String),
+    Num(u64),
+    Post,
+}
+
+/// A conda version, ordered the way conda orders them.
+#[derive(Debug, Clone)]
+pub struct CondaVersion {
+    source: String,
+    // The epoch is the first component.
+    version: Vec<Vec<Part>>,
+    local: Vec<Vec<Part>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionError {
+    pub version: String,
+    pub msg: String,
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid version {:?}: {}", self.version, self.msg)
+    }
+}
+
+fn split_parts(component: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut digits = false;
+    for c in component.chars() {
+        if !current.is_empty() && c.is_ascii_digit() != digits {
+            parts.push(make_part(&current, digits));
+            current.clear();
+        }
+        digits = c.is_ascii_digit();
+        current.push(c);
+    }
+    if !current.is_empty() {
+        parts.push(make_part(&current, digits));
+    }
+    // Components starting with a letter get an implicit leading 0, so 1.1.a1 == 1.1.0a1.
+    if parts.first().is_some_and(|part| !matches!(part, Part::Num(_))) {
+        parts.insert(0, Part::Num(0));
+    }
+    parts
+}
+
+fn make_part(run: &str, digits: bool) -> Part {
+    if digits {
+        // Absurdly long numbers saturate rather than failing.
+        return Part::Num(run.parse().unwrap_or(u64::MAX));
+    }
+    match run {
+        "post" => Part::Post,
+        // Upper case sorts before every other (lower case) string.
+        "dev" => Part::Str("DEV".to_string()),
+        _ => Part::Str(run.to_string()),
+    }
+}
+
+fn split_components(version: &str) -> Result<Vec<Vec<Part>>, &'static str> {
+    let mut components = Vec::new();
+    for component in version.split('.') {
+        if component.is_empty() {
+            return Err("empty version component");
+        }
+        components.push(split_parts(component));
+    }
+    Ok(components)
+}
+
+impl CondaVersion {
+    pub fn parse(version: &str) -> Result<CondaVersion, VersionError> {
+        let err = |msg: &str| VersionError {
+            version: version.to_string(),
+            msg: msg.to_string(),
+        };
+        let lowered = version.trim().to_lowercase();
+        if lowered.is_empty() {
+            return Err(err("empty version"));
+        }
+        let valid_chars = lowered
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._+!-".contains(c));
+        if !valid_chars {
+            return Err(err("invalid character"));
+        }
+
+        let (epoch, rest) = match lowered.find('!') {
+            Some(idx) => {
+                let epoch: u64 = lowered[..idx].parse().map_err(|_| err("epoch must be an integer"))?;
+                (epoch, &lowered[idx + 1..])
+            }
+            None => (0, &lowered[..]),
+        };
+        let (public, local) = match rest.find('+') {
+            Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+            None => (rest, None),
+        };
+
+        // An appended underscore is special cased for openssl-like versions: 1.1dev1 < 1.1_ < 1.1a1
+        let (public, underscore) = match public.strip_suffix('_') {
+            Some(public) => (public, true),
+            None => (public, false),
+        };
+        let public = if public.contains('-') && !public.contains('_') {
+            public.replace('-', "_")
+        } else {
+            public.to_string()
+        };
+        let public = public.replace('_', ".");
+
+        let mut components = vec![vec![Part::Num(epoch)]];
+        components.extend(split_components(&public).map_err(err)?);
+        if underscore {
+            if let Some(last) = components.last_mut() {
+                last.push(Part::Str("_".to_string()));
+            }
+        }
+        let local = match local {
+            Some(local) => split_components(&local.replace('_', ".")).map_err(err)?,
+            None => Vec::new(),
+        };
+        Ok(CondaVersion {
+            source: version.to_string(),
+            version: components,
+            local,
+        })
+    }
+
+    /// Does this version start with `prefix`, component by component?
+    ///
+    /// `1.15.2` and `1.15` start with `1.15`; `1.150` does not.
+    pub fn starts_with(&self, prefix: &CondaVersion) -> bool {
+        let n = prefix.version.len();
+        for (i, prefix_component) in prefix.version.iter().enumerate() {
+            let component = self.version.get(i).map(|c| &c[..]).unwrap_or(&[]);
+            if i + 1 < n {
+                if cmp_component(component, prefix_component) != Ordering::Equal {
+                    return false;
+                }
+                continue;
+            }
+            // The last component of the prefix may itself be a prefix, e.g. 1.1a starts 1.1a1.
+            let m = prefix_component.len();
+            for (j, prefix_part) in prefix_component.iter().enumerate() {
+                let part = component.get(j).unwrap_or(&Part::Num(0));
+                let matches = match (part, prefix_part) {
+                    (Part::Str(s), Part::Str(p)) if j + 1 == m => s.starts_with(p.as_str()),
+                    _ => part == prefix_part,
+                };
+                if !matches {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+fn cmp_component(a: &[Part], b: &[Part]) -> Ordering {
+    let zero = Part::Num(0);
+    for i in 0..a.len().max(b.len()) {
+        let ordering = a.get(i).unwrap_or(&zero).cmp(b.get(i).unwrap_or(&zero));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+fn cmp_components(a: &[Vec<Part>], b: &[Vec<Part>]) -> Ordering {
+    let zero = vec![Part::Num(0)];
+    for i in 0..a.len().max(b.len()) {
+        let ordering = cmp_component(a.get(i).unwrap_or(&zero), b.get(i).unwrap_or(&zero));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+impl Ord for CondaVersion {
+    fn cmp(&self, other: &CondaVersion) -> Ordering {
+        cmp_components(&self.version, &other.version)
+            .then_with(|| cmp_components(&self.local, &other.local))
+    }
+}
+
+impl PartialOrd for CondaVersion {
+    fn partial_cmp(&self, other: &CondaVersion) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for CondaVersion {
+    fn eq(&self, other: &CondaVersion) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CondaVersion {}
+
+impl fmt::Display for CondaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for CondaVersion {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<CondaVersion, VersionError> {
+        CondaVersion::parse(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Constraint {
+    Any,
+    Eq(CondaVersion),
+    Ne(CondaVersion),
+    Lt(CondaVersion),
+    Le(CondaVersion),
+    Gt(CondaVersion),
+    Ge(CondaVersion),
+    StartsWith(CondaVersion),
+    NotStartsWith(CondaVersion),
+    Compatible(CondaVersion, CondaVersion),
+    And(Vec<Constraint>),
+    Or(Vec<Constraint>),
+}
+
+/// A conda version constraint, e.g. `>=1.15,<2`, `1.15.*`, `2.7|>=3.6`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionSpec {
+    source: String,
+    constraint: Constraint,
+}
+
+impl VersionSpec {
+    pub fn parse(spec: &str) -> Result<VersionSpec, VersionError> {
+        let compact: String = spec.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut parser = SpecParser {
+            spec: &compact,
+            pos: 0,
+        };
+        let constraint = parser.parse_or().map_err(|msg| VersionError {
+            version: spec.to_string(),
+            msg,
+        })?;
+        if parser.pos != compact.len() {
+            return Err(VersionError {
+                version: spec.to_string(),
+                msg: "unbalanced parentheses".to_string(),
+            });
+        }
+        Ok(VersionSpec {
+            source: spec.to_string(),
+            constraint,
+        })
+    }
+
+    pub fn matches(&self, version: &CondaVersion) -> bool {
+        self.constraint.matches(version)
+    }
+}
+
+impl fmt::Display for VersionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for VersionSpec {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<VersionSpec, VersionError> {
+        VersionSpec::parse(s)
+    }
+}
+
+impl Constraint {
+    fn matches(&self, v: &CondaVersion) -> bool {
+        match *self {
+            Constraint::Any => true,
+            Constraint::Eq(ref other) => v == other,
+            Constraint::Ne(ref other) => v != other,
+            Constraint::Lt(ref other) => v < other,
+            Constraint::Le(ref other) => v <= other,
+            Constraint::Gt(ref other) => v > other,
+            Constraint::Ge(ref other) => v >= other,
+            Constraint::StartsWith(ref other) => v.starts_with(other),
+            Constraint::NotStartsWith(ref other) => !v.starts_with(other),
+            Constraint::Compatible(ref min, ref prefix) => v >= min && v.starts_with(prefix),
+            Constraint::And(ref all) => all.iter().all(|c| c.matches(v)),
+            Constraint::Or(ref any) => any.iter().any(|c| c.matches(v)),
+        }
+    }
+}
+
+/// Recursive descent over `|` (lowest precedence), `,` and parentheses.
+struct SpecParser<'a> {
+    spec: &'a str,
+    pos: usize,
+}
+
+impl SpecParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.spec[self.pos..].chars().next()
+    }
+
+    fn parse_or(&mut self) -> Result<Constraint, String> {
+        let mut any = vec![self.parse_and()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            any.push(self.parse_and()?);
+        }
+        Ok(if any.len() == 1 { any.remove(0) } else { Constraint::Or(any) })
+    }
+
+    fn parse_and(&mut self) -> Result<Constraint, String> {
+        let mut all = vec![self.parse_term()?];
+        while self.peek() == Some(',') {
+            self.pos += 1;
+            all.push(self.parse_term()?);
+        }
+        Ok(if all.len() == 1 { all.remove(0) } else { Constraint::And(all) })
+    }
+
+    fn parse_term(&mut self) -> Result<Constraint, String> {
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            let constraint = self.parse_or()?;
+            if self.peek() != Some(')') {
+                return Err("unbalanced parentheses".to_string());
+            }
+            self.pos += 1;
+            return Ok(constraint);
+        }
+        let rest = &self.spec[self.pos..];
+        let end = rest.find([',', '|', ')']).unwrap_or(rest.len());
+        self.pos += end;
+        parse_constraint(&rest[..end])
+    }
+}
+
+fn version(s: &str) -> Result<CondaVersion, String> {
+    CondaVersion::parse(s).map_err(|e| e.msg)
+}
+
+fn parse_constraint(term: &str) -> Result<Constraint, String> {
+    if term.is_empty() {
+        return Err("empty constraint".to_string());
+    }
+    if term == "*" || term == "*.*" {
+        return Ok(Constraint::Any);
+    }
+    let operators = ["==", "!=", "<=", ">=", "~=", "<", ">", "="];
+    let op = operators.iter().find(|op| term.starts_with(**op)).cloned();
+    let operand = &term[op.map_or(0, |op| op.len())..];
+    // A trailing `.*` or `*` means "starts with".
+    let (operand, glob) = match operand.strip_suffix(".*").or_else(|| operand.strip_suffix('*')) {
+        Some(operand) => (operand, true),
+        None => (operand, false),
+    };
+    if operand.contains('*') {
+        return Err("unsupported wildcard".to_string());
+    }
+    let v = version(operand)?;
+    Ok(match (op, glob) {
+        (None, false) | (Some("=="), false) => Constraint::Eq(v),
+        (None, true) | (Some("=="), true) | (Some("="), _) => Constraint::StartsWith(v),
+        (Some("!="), false) => Constraint::Ne(v),
+        (Some("!="), true) => Constraint::NotStartsWith(v),
+        // Comparisons ignore a trailing `.*`, as conda does.
+        (Some("<"), _) => Constraint::Lt(v),
+        (Some("<="), _) => Constraint::Le(v),
+        (Some(">"), _) => Constraint::Gt(v),
+        (Some(">="), _) => Constraint::Ge(v),
+        (Some("~="), _) => {
+            // ~=1.4.2 means >=1.4.2 and 1.4.*
+            let prefix = match operand.rfind('.') {
+                Some(idx) => version(&operand[..idx])?,
+                None => return Err("~= needs at least two components".to_string()),
+            };
+            Constraint::Compatible(v, prefix)
+        }
+        (Some(op), _) => return Err(format!("unknown operator {}", op)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> CondaVersion {
+        CondaVersion::parse(s).unwrap()
+    }
+
+    fn matches(spec: &str, version: &str) -> bool {
+        VersionSpec::parse(spec).unwrap().matches(&v(version))
+    }
+
+    #[test]
+    fn ordering() {
+        let ordered = [
+            "0.4",
+            "0.4.1.rc",
+            "0.4.1",
+            "0.5a1",
+            "0.5b3",
+            "0.5C1",
+            "0.5",
+            "0.9.6",
+            "0.960923",
+            "1.0",
+            "1.1dev1",
+            "1.1_",
+            "1.1a1",
+            "1.1.0dev1",
+            "1.1.a1",
+            "1.1.0rc1",
+            "1.1.0",
+            "1.1.0post1",
+            "1.1post1",
+            "1996.07.12",
+            "1!0.4.1",
+            "1!3.1.1.6",
+            "2!0.4.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(v("0.4"), v("0.4.0"));
+        assert_eq!(v("0.4.1.rc"), v("0.4.1.RC"));
+        assert_eq!(v("1.1.0dev1"), v("1.1.dev1"));
+        assert_eq!(v("1.1.0post1"), v("1.1.post1"));
+        assert!(v("1.0.2l") > v("1.0.2k"));
+    }
+
+    #[test]
+    fn specs() {
+        assert!(matches("*", "1.0"));
+        assert!(matches(">=1.15", "1.15.4"));
+        assert!(!matches(">=1.15", "1.10"));
+        assert!(matches(">=1.15,<2", "1.16"));
+        assert!(!matches(">=1.15,<2", "2.0"));
+        assert!(matches("1.15.*", "1.15.4"));
+        assert!(matches("1.15.*", "1.15"));
+        assert!(!matches("1.15.*", "1.150"));
+        assert!(matches("=1.15", "1.15.0"));
+        assert!(matches("1.15", "1.15.0"));
+        assert!(!matches("1.15", "1.15.1"));
+        assert!(matches("!=1.15.*", "1.16"));
+        assert!(matches("2.7|>=3.6", "3.7.1"));
+        assert!(!matches("2.7|>=3.6", "3.5"));
+        assert!(matches("(>=1,<2)|>3", "4"));
+        assert!(matches("~=1.4.2", "1.4.5"));
+        assert!(!matches("~=1.4.2", "1.5"));
+        assert!(matches("<3.8", "3.7.3"));
+        assert!(!matches("<3.8", "3.8.0"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(VersionSpec::parse("").is_err());
+        assert!(VersionSpec::parse(">=1.15,").is_err());
+        assert!(VersionSpec::parse("(>=1").is_err());
+        assert!(VersionSpec::parse("1.*.2").is_err());
+        assert!(CondaVersion::parse("1..2").is_err());
+        assert!(CondaVersion::parse("1.0 beta").is_err());
+    }
+}
@@ -0,0 +1,467 @@
+//! Unified lockfiles in conda-lock's `conda-lock.yml` format, holding every platform in one
+//! file.
+//!
+//! ```yaml
+//! version: 1
+//! metadata:
+//!   content_hash:
+//!     linux-64: sha256:9f86d0...
+//!   channels:
+//!   - url: conda-forge
+//!     used_env_vars: []
+//!   platforms:
+//!   - linux-64
+//!   sources:
+//!   - deps.yml
+//!   custom_metadata:
+//!     name: myenv
+//! package:
+//! - name: numpy
+//!   version: 1.15.4
+//!   manager: conda
+//!   platform: linux-64
+//!   dependencies: {}
+//!   url: https://conda.anaconda.org/conda-forge/linux-64/numpy-1.15.4-py37_0.tar.bz2
+//!   hash:
+//!     md5: a8b8f2d3...
+//!   category: main
+//!   optional: false
+//! ```
+//!
+//! The content hash of each platform is the depfile hash that would go on the `# ENVHASH:`
+//! line of a single-platform lockfile.
+
+use std::collections::BTreeMap;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use explicit::{ExplicitPackage, ExplicitSpec};
+use hash::EnvHash;
+use platform::Platform;
+use spec::{quote, LockSpec};
+
+/// The conventional name of a unified lockfile.
+pub const CONDA_LOCK_FILE: &str = "conda-lock.yml";
+
+/// A locked package of one platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CondaLockPackage {
+    pub name: String,
+    pub version: String,
+    /// `conda` or `pip`.
+    pub manager: String,
+    pub platform: Platform,
+    /// The names and match specs of the package's dependencies.
+    pub dependencies: Vec<(String, String)>,
+    pub url: String,
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    pub category: String,
+    pub optional: bool,
+}
+
+/// A parsed `conda-lock.yml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CondaLock {
+    /// The environment name, from the custom metadata.  conda-lock doesn't write one.
+    pub name: Option<String>,
+    pub content_hash: BTreeMap<Platform, EnvHash>,
+    /// Whether a content hash is bare hex, as conda-lock itself writes.  Those hash conda-lock's
+    /// inputs rather than the depfile, so they can't be checked against one.
+    pub foreign_hashes: bool,
+    pub channels: Vec<String>,
+    pub platforms: Vec<Platform>,
+    /// The files the lockfile was frozen from.
+    pub sources: Vec<String>,
+    pub packages: Vec<CondaLockPackage>,
+}
+
+/// Is `data` a unified lockfile rather than an environment file?
+pub fn is_conda_lock(data: &str) -> bool {
+    let mut lines = data.lines();
+    lines.clone().any(|line| line.starts_with("package:"))
+        && lines.any(|line| line.starts_with("metadata:"))
+}
+
+/// Replace every platform's content hash in the text of a unified lockfile.
+///
+/// Everything else is left as written, including platforms and metadata this tool doesn't
+/// model and would drop on a round trip through `CondaLock`.
+pub fn rewrite_content_hashes(data: &str, env_hash: &EnvHash) -> Result<String, String> {
+    let mut out = String::with_capacity(data.len());
+    // The indentation of the `content_hash:` key while reading its entries.
+    let mut section_indent = None;
+    let mut found = false;
+    for line in data.split_inclusive('\n') {
+        let content = line.trim_start();
+        let indent = line.len() - content.len();
+        if let Some(section_indent) = section_indent {
+            if indent > section_indent || content.trim().is_empty() {
+                match content.find(':') {
+                    Some(idx) if !content.starts_with('#') => {
+                        let key = &line[..indent + idx];
+                        let eol = if line.ends_with('\n') { "\n" } else { "" };
+                        out.push_str(&format!("{}: {}{}", key, quote(&env_hash.to_string()), eol));
+                        found = true;
+                    }
+                    _ => out.push_str(line),
+                }
+                continue;
+            }
+        }
+        section_indent = if content.trim_end() == "content_hash:" {
+            Some(indent)
+        } else {
+            None
+        };
+        out.push_str(line);
+    }
+    if !found {
+        return Err("no content hashes".to_string());
+    }
+    Ok(out)
+}
+
+/// A YAML scalar as written, so an unquoted version like `1.10` isn't read as a number.
+pub fn scalar(yaml: &Yaml) -> Option<String> {
+    match *yaml {
+        Yaml::String(ref s) | Yaml::Real(ref s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+/// The platform named by `yaml`, or `None` for a subdir this tool doesn't know.
+fn platform(yaml: &Yaml) -> Result<Option<Platform>, String> {
+    let name = scalar(yaml).ok_or_else(|| "expected a platform".to_string())?;
+    Ok(name.parse().ok())
+}
+
+impl CondaLock {
+    pub fn parse(data: &str) -> Result<CondaLock, String> {
+        let docs = YamlLoader::load_from_str(data).map_err(|e| e.to_string())?;
+        let doc = match docs.first() {
+            Some(doc) => doc,
+            None => return Err("empty lockfile".to_string()),
+        };
+        match doc["version"].as_i64() {
+            Some(1) => {}
+            Some(version) => return Err(format!("unsupported version {}", version)),
+            None => return Err("no version".to_string()),
+        }
+        let metadata = &doc["metadata"];
+        let mut lock = CondaLock {
+            name: scalar(&metadata["custom_metadata"]["name"]),
+            ..CondaLock::default()
+        };
+        if let Some(hashes) = metadata["content_hash"].as_hash() {
+            for (platform_name, hash) in hashes {
+                let hash = scalar(hash).ok_or_else(|| "expected a content hash".to_string())?;
+                lock.foreign_hashes |= !hash.contains(':');
+                if let Some(platform) = platform(platform_name)? {
+                    lock.content_hash.insert(platform, EnvHash::parse(&hash));
+                }
+            }
+        }
+        for channel in metadata["channels"].as_vec().map(Vec::as_slice).unwrap_or(&[]) {
+            let url = scalar(&channel["url"]).or_else(|| scalar(channel));
+            lock.channels.push(url.ok_or_else(|| "expected a channel url".to_string())?);
+        }
+        // Lockfiles for platforms this tool doesn't know are still usable for the rest.
+        for name in metadata["platforms"].as_vec().map(Vec::as_slice).unwrap_or(&[]) {
+            match platform(name)? {
+                Some(platform) => lock.platforms.push(platform),
+                None => {
+                    let name = scalar(name).unwrap_or_default();
+                    warn!("Skipping {}: not a known platform", name);
+                }
+            }
+        }
+        for source in metadata["sources"].as_vec().map(Vec::as_slice).unwrap_or(&[]) {
+            lock.sources.extend(scalar(source));
+        }
+
+        for package in doc["package"].as_vec().map(Vec::as_slice).unwrap_or(&[]) {
+            let package_platform = match platform(&package["platform"])? {
+                Some(platform) => platform,
+                None => continue,
+            };
+            let field = |key: &str| {
+                scalar(&package[key]).ok_or_else(|| format!("package without {}", key))
+            };
+            let mut dependencies = Vec::new();
+            if let Some(deps) = package["dependencies"].as_hash() {
+                for (name, spec) in deps {
+                    if let Some(name) = scalar(name) {
+                        dependencies.push((name, scalar(spec).unwrap_or_default()));
+                    }
+                }
+            }
+            lock.packages.push(CondaLockPackage {
+                name: field("name")?,
+                version: field("version")?,
+                manager: field("manager")?,
+                platform: package_platform,
+                dependencies,
+                url: field("url")?,
+                md5: scalar(&package["hash"]["md5"]),
+                sha256: scalar(&package["hash"]["sha256"]),
+                category: scalar(&package["category"]).unwrap_or_else(|| "main".to_string()),
+                optional: package["optional"].as_bool().unwrap_or(false),
+            });
+        }
+        Ok(lock)
+    }
+
+    pub fn emit(&self) -> String {
+        let mut out = String::from("version: 1\nmetadata:\n  content_hash:\n");
+        for (platform, hash) in &self.content_hash {
+            out.push_str(&format!("    {}: {}\n", platform, quote(&hash.to_string())));
+        }
+        out.push_str("  channels:\n");
+        for channel in &self.channels {
+            out.push_str(&format!("  - url: {}\n    used_env_vars: []\n", quote(channel)));
+        }
+        out.push_str("  platforms:\n");
+        for platform in &self.platforms {
+            out.push_str(&format!("  - {}\n", platform));
+        }
+        out.push_str("  sources:\n");
+        for source in &self.sources {
+            out.push_str(&format!("  - {}\n", quote(source)));
+        }
+        if let Some(ref name) = self.name {
+            out.push_str(&format!("  custom_metadata:\n    name: {}\n", quote(name)));
+        }
+        out.push_str("package:\n");
+        for package in &self.packages {
+            out.push_str(&format!("- name: {}\n", quote(&package.name)));
+            out.push_str(&format!("  version: {}\n", quote(&package.version)));
+            out.push_str(&format!("  manager: {}\n", quote(&package.manager)));
+            out.push_str(&format!("  platform: {}\n", package.platform));
+            if package.dependencies.is_empty() {
+                out.push_str("  dependencies: {}\n");
+            } else {
+                out.push_str("  dependencies:\n");
+                for (name, spec) in &package.dependencies {
+                    out.push_str(&format!("    {}: {}\n", quote(name), quote(spec)));
+                }
+            }
+            out.push_str(&format!("  url: {}\n", quote(&package.url)));
+            out.push_str("  hash:\n");
+            if let Some(ref md5) = package.md5 {
+                out.push_str(&format!("    md5: {}\n", quote(md5)));
+            }
+            if let Some(ref sha256) = package.sha256 {
+                out.push_str(&format!("    sha256: {}\n", quote(sha256)));
+            }
+            out.push_str(&format!("  category: {}\n", quote(&package.category)));
+            out.push_str(&format!("  optional: {}\n", package.optional));
+        }
+        out
+    }
+
+    /// Add the conda packages frozen for `platform`, replacing any it already had.
+    pub fn add_platform(
+        &mut self,
+        platform: Platform,
+        hash: EnvHash,
+        packages: &[ExplicitPackage],
+    ) {
+        self.packages.retain(|package| package.platform != platform);
+        if !self.platforms.contains(&platform) {
+            self.platforms.push(platform);
+        }
+        self.content_hash.insert(platform, hash);
+        for package in packages {
+            let (name, version, _) = match package.parts() {
+                Some(parts) => parts,
+                None => continue,
+            };
+            self.packages.push(CondaLockPackage {
+                name,
+                version,
+                manager: "conda".to_string(),
+                platform,
+                dependencies: Vec::new(),
+                url: package.url.clone(),
+                md5: package.md5.clone(),
+                sha256: package.sha256.clone(),
+                category: "main".to_string(),
+                optional: false,
+            });
+        }
+    }
+
+    fn platform_packages(&self, platform: Platform) -> impl Iterator<Item = &CondaLockPackage> {
+        self.packages.iter().filter(move |package| package.platform == platform)
+    }
+
+    /// The conda packages of `platform`, which conda can install without solving.
+    pub fn explicit(&self, platform: Platform) -> ExplicitSpec {
+        ExplicitSpec {
+            name: self.name.clone(),
+            platform: Some(platform),
+            packages: self
+                .platform_packages(platform)
+                .filter(|package| package.manager == "conda")
+                .map(|package| ExplicitPackage {
+                    url: package.url.clone(),
+                    md5: package.md5.clone(),
+                    sha256: package.sha256.clone(),
+                }).collect(),
+        }
+    }
+
+    /// The environment of `platform`, with conda packages as `name=version=build` and pip
+    /// packages pinned with `==`.
+    pub fn lock_spec(&self, platform: Platform) -> LockSpec {
+        let mut lock_spec = self.explicit(platform).lock_spec();
+        lock_spec.pip_deps = self
+            .platform_packages(platform)
+            .filter(|package| package.manager == "pip")
+            .map(|package| format!("{}=={}", package.name, package.version))
+            .collect();
+        lock_spec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONDA_LOCK: &str = "\
+version: 1
+metadata:
+  content_hash:
+    linux-64: sha256:abc123
+    osx-arm64: sha256:abc123
+  channels:
+  - url: conda-forge
+    used_env_vars: []
+  platforms:
+  - linux-64
+  - osx-arm64
+  sources:
+  - deps.yml
+  custom_metadata:
+    name: foo
+package:
+- name: python
+  version: 3.7.3
+  manager: conda
+  platform: linux-64
+  dependencies: {}
+  url: https://conda.anaconda.org/conda-forge/linux-64/python-3.7.3-h0_0.tar.bz2
+  hash:
+    md5: d1e2
+  category: main
+  optional: false
+- name: numpy
+  version: 1.16.0
+  manager: conda
+  platform: linux-64
+  dependencies:
+    python: \">=3.7,<3.8.0a0\"
+  url: https://conda.anaconda.org/conda-forge/linux-64/numpy-1.16.0-py37_0.tar.bz2
+  hash:
+    md5: f00d
+    sha256: beef
+  category: main
+  optional: false
+- name: requests
+  version: 2.21.0
+  manager: pip
+  platform: linux-64
+  dependencies: {}
+  url: https://files.pythonhosted.org/packages/requests-2.21.0-py2.py3-none-any.whl
+  hash:
+    sha256: cafe
+  category: main
+  optional: false
+- name: python
+  version: 3.7.3
+  manager: conda
+  platform: osx-arm64
+  dependencies: {}
+  url: https://conda.anaconda.org/conda-forge/osx-arm64/python-3.7.3-h1_0.conda
+  hash:
+    sha256: f1f1
+  category: main
+  optional: false
+";
+
+    #[test]
+    fn parse() {
+        assert!(is_conda_lock(CONDA_LOCK));
+        assert!(!is_conda_lock("name: foo\ndependencies: []\n"));
+        let lock = CondaLock::parse(CONDA_LOCK).unwrap();
+        assert_eq!(lock.name.as_deref(), Some("foo"));
+        assert_eq!(lock.platforms, [Platform::Linux64, Platform::OsxArm64]);
+        assert_eq!(lock.content_hash[&Platform::Linux64], EnvHash::parse("sha256:abc123"));
+        assert!(!lock.foreign_hashes);
+        assert_eq!(lock.channels, ["conda-forge"]);
+        assert_eq!(lock.packages.len(), 4);
+        assert_eq!(
+            lock.packages[1].dependencies,
+            [("python".to_string(), ">=3.7,<3.8.0a0".to_string())]
+        );
+
+        let lock_spec = lock.lock_spec(Platform::Linux64);
+        assert_eq!(lock_spec.name, "foo");
+        assert_eq!(lock_spec.conda_deps, ["python=3.7.3=h0_0", "numpy=1.16.0=py37_0"]);
+        assert_eq!(lock_spec.pip_deps, ["requests==2.21.0"]);
+        let explicit = lock.explicit(Platform::OsxArm64);
+        assert_eq!(explicit.packages.len(), 1);
+        assert_eq!(explicit.packages[0].sha256.as_deref(), Some("f1f1"));
+    }
+
+    #[test]
+    fn unknown_platforms() {
+        let data = CONDA_LOCK
+            .replace("  - osx-arm64\n", "  - osx-arm64\n  - win-arm64\n")
+            .replace("  content_hash:\n", "  content_hash:\n    win-arm64: sha256:abc123\n")
+            .replace("  platform: osx-arm64\n", "  platform: win-arm64\n");
+        let lock = CondaLock::parse(&data).unwrap();
+        assert_eq!(lock.platforms, [Platform::Linux64, Platform::OsxArm64]);
+        assert_eq!(lock.content_hash.len(), 2);
+        assert_eq!(lock.packages.len(), 3);
+    }
+
+    #[test]
+    fn rewrite_keeps_what_it_does_not_model() {
+        let data = CONDA_LOCK
+            .replace("  platforms:\n", "  platforms:\n  - linux-ppc64le\n")
+            .replace("  content_hash:\n", "  content_hash:\n    linux-ppc64le: 0123abcd\n")
+            .replace("  sources:\n", "  time_metadata:\n    created_at: 1\n  sources:\n");
+        let hash = EnvHash::parse("sha256:def456");
+        let rewritten = rewrite_content_hashes(&data, &hash).unwrap();
+        assert!(rewritten.contains("    linux-ppc64le: sha256:def456\n"), "{}", rewritten);
+        assert!(rewritten.contains("  - linux-ppc64le\n"), "{}", rewritten);
+        assert!(rewritten.contains("    created_at: 1\n"), "{}", rewritten);
+        let lock = CondaLock::parse(&rewritten).unwrap();
+        assert_eq!(lock.content_hash[&Platform::Linux64], hash);
+        assert_eq!(lock.content_hash[&Platform::OsxArm64], hash);
+        assert_eq!(rewritten.matches("sha256:def456").count(), 3);
+        assert_eq!(rewritten.lines().count(), data.lines().count());
+    }
+
+    #[test]
+    fn round_trip() {
+        let lock = CondaLock::parse(CONDA_LOCK).unwrap();
+        assert_eq!(lock.emit(), CONDA_LOCK);
+    }
+
+    #[test]
+    fn add_platform() {
+        let mut lock = CondaLock::parse(CONDA_LOCK).unwrap();
+        let package = ExplicitPackage::parse(
+            "https://conda.anaconda.org/conda-forge/osx-arm64/python-3.8.0-h2_0.conda#0a0a",
+        ).unwrap();
+        lock.add_platform(Platform::OsxArm64, EnvHash::parse("sha256:def456"), &[package]);
+        assert_eq!(lock.platforms, [Platform::Linux64, Platform::OsxArm64]);
+        assert_eq!(lock.content_hash[&Platform::OsxArm64], EnvHash::parse("sha256:def456"));
+        assert_eq!(lock.lock_spec(Platform::OsxArm64).conda_deps, ["python=3.8.0=h2_0"]);
+        assert_eq!(lock.packages.len(), 4);
+    }
+}
@@ -0,0 +1,171 @@
+//! Project configuration, read from `conda-lockfile.yml` next to the depfile.
+//!
+//! ```yaml
+//! # The platforms `freeze` locks when no --platform is given.
+//! platforms:
+//!   - linux-64
+//!   - osx-arm64
+//! # The virtual packages the machines running each platform provide.  They live here rather
+//! # than in the depfile, which conda reads too.
+//! virtual_packages:
+//!   linux-64:
+//!     __glibc: "2.17"
+//!     __cuda: "11.8"
+//!   osx-arm64:
+//!     __osx: "12.0"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use platform::{Platform, VirtualPackage};
+use {Error, Result};
+
+/// The name of the config file, looked for in the depfile's directory.
+pub const CONFIG_FILE: &str = "conda-lockfile.yml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// The platforms to freeze by default.  Empty means just the current platform.
+    pub platforms: Vec<Platform>,
+    pub virtual_packages: BTreeMap<Platform, Vec<VirtualPackage>>,
+}
+
+impl Config {
+    pub fn parse(data: &str) -> ::std::result::Result<Config, String> {
+        let docs = YamlLoader::load_from_str(data).map_err(|e| e.to_string())?;
+        let mut config = Config::default();
+        let doc = match docs.into_iter().next() {
+            Some(Yaml::Hash(doc)) => doc,
+            None | Some(Yaml::Null) => return Ok(config),
+            Some(_) => return Err("expected a mapping".to_string()),
+        };
+        for (key, value) in doc {
+            match key.as_str() {
+                Some("platforms") => {
+                    let platforms = value
+                        .as_vec()
+                        .ok_or_else(|| "platforms must be a list".to_string())?;
+                    for platform in platforms {
+                        let platform = platform
+                            .as_str()
+                            .ok_or_else(|| "platforms must be strings".to_string())?;
+                        config.platforms.push(platform.parse().map_err(|e: Error| e.to_string())?);
+                    }
+                }
+                Some("virtual_packages") => {
+                    let platforms = value
+                        .as_hash()
+                        .ok_or_else(|| "virtual_packages must be a mapping".to_string())?;
+                    for (platform, packages) in platforms {
+                        let platform: Platform = scalar(platform)
+                            .ok_or_else(|| "platforms must be strings".to_string())?
+                            .parse()
+                            .map_err(|e: Error| e.to_string())?;
+                        let packages = packages.as_hash().ok_or_else(|| {
+                            format!("virtual_packages for {} must be a mapping", platform)
+                        })?;
+                        let mut virtual_packages = Vec::new();
+                        for (name, version) in packages {
+                            let name = name
+                                .as_str()
+                                .ok_or_else(|| "names must be strings".to_string())?;
+                            let version = scalar(version)
+                                .ok_or_else(|| format!("{} must have a scalar version", name))?;
+                            virtual_packages.push(VirtualPackage::new(name, &version, platform)?);
+                        }
+                        config.virtual_packages.insert(platform, virtual_packages);
+                    }
+                }
+                _ => return Err(format!("unexpected key {:?}", key)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Read the config at `path`.  A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Config> {
+        let mut data = String::new();
+        match File::open(path) {
+            Ok(mut f) => f.read_to_string(&mut data)?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        Config::parse(&data).map_err(|e| Error::BadConfig(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The virtual package overrides for `platform`.
+    pub fn virtual_packages(&self, platform: Platform) -> &[VirtualPackage] {
+        self.virtual_packages
+            .get(&platform)
+            .map(|packages| &packages[..])
+            .unwrap_or(&[])
+    }
+
+    /// The config that applies to the depfile at `depfile_path`.
+    pub fn for_depfile(depfile_path: &Path) -> Result<Config> {
+        Config::load(&config_path(depfile_path))
+    }
+}
+
+/// A scalar as written; YAML would read `2.17` as a float.
+fn scalar(yaml: &Yaml) -> Option<String> {
+    match *yaml {
+        Yaml::String(ref s) | Yaml::Real(ref s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Null => Some(String::new()),
+        _ => None,
+    }
+}
+
+/// Where the config for the depfile at `depfile_path` lives.
+pub fn config_path(depfile_path: &Path) -> PathBuf {
+    depfile_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(CONFIG_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platforms() {
+        let config = Config::parse("platforms: [linux-64, osx-arm64]").unwrap();
+        assert_eq!(config.platforms, [Platform::Linux64, Platform::OsxArm64]);
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn virtual_packages() {
+        let config = Config::parse("
+virtual_packages:
+  linux-64:
+    __glibc: 2.17
+    __cuda: \"11.8\"
+  osx-arm64:
+    __osx: 12.0
+    __archspec: m1
+").unwrap();
+        let linux: Vec<String> =
+            config.virtual_packages(Platform::Linux64).iter().map(|p| p.to_string()).collect();
+        assert_eq!(linux, ["__glibc=2.17", "__cuda=11.8"]);
+        assert_eq!(config.virtual_packages(Platform::OsxArm64).len(), 2);
+        assert!(config.virtual_packages(Platform::Win64).is_empty());
+        assert!(Config::parse("virtual_packages: {osx-64: {__glibc: 2.17}}").is_err());
+        assert!(Config::parse("virtual_packages: {linux-64: {__unix: \"\"}}").is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Config::parse("platforms: linux-64").is_err());
+        assert!(Config::parse("platforms: [beos]").is_err());
+        assert!(Config::parse("platfroms: [linux-64]").is_err());
+    }
+}
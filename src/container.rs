@@ -0,0 +1,134 @@
+//! Building lockfiles inside a docker container.
+//!
+//! This is what allows us to build Linux lockfiles on a mac, or for the other Linux
+//! architecture.
+
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use conda::is_unsatisfiable;
+use platform::{Platform, VirtualPackage};
+use {Error, Result};
+
+const DOCKERFILE: &str = "
+FROM debian:stretch
+
+RUN mkdir /app
+WORKDIR /app
+ENV CONDA_ROOT /var/lib/conda
+
+RUN apt-get update && \
+    apt-get install --yes bzip2 curl libc6 libc6-dev libc-dev gcc net-tools && \
+    apt-get autoclean
+
+RUN curl -L INSTALLER_URL > miniconda.sh
+RUN bash miniconda.sh -b -f -p $CONDA_ROOT
+RUN echo 'ONE_LINE_COMMAND' > build_lockfile.sh
+
+ENTRYPOINT [\"/bin/bash\", \"./build_lockfile.sh\"]
+";
+
+const BUILD_LOCKFILE: &str = "set -e
+cd artifacts
+# We need the name of the environment for exporting the environment.
+# Unfortunately, `conda env create` doesn't return any information identifying
+# the name of the environment it created. As a workaround, provide an explicit
+# name to `conda env create` so there is no ambiguity when calling `conda env
+# export`.  This name *ought* be what is specified in `env.yml` itself.
+ENV_NAME=$(cat env_name)
+$CONDA_ROOT/bin/conda env create -f deps.yml -n $ENV_NAME
+# The prefix line includes an absolute path from inside this container.
+# Remove it to avoid confusion.
+$CONDA_ROOT/bin/conda env export -n $ENV_NAME | grep -v \"^prefix:\" > deps.yml.lock
+$CONDA_ROOT/bin/conda list --explicit --md5 -n $ENV_NAME > deps.yml.explicit
+";
+
+/// The docker platform and conda installer for a Linux platform.
+///
+/// Installers are pinned to a release so images built at different times hold the same conda.
+/// It must be conda 4.8 or later, which is when `CONDA_OVERRIDE_*` variables were introduced.
+fn docker_platform(platform: Platform) -> Result<(&'static str, &'static str)> {
+    match platform {
+        Platform::Linux64 => Ok((
+            "linux/amd64",
+            "https://repo.anaconda.com/miniconda/Miniconda3-py39_4.10.3-Linux-x86_64.sh",
+        )),
+        Platform::LinuxAarch64 => Ok((
+            "linux/arm64",
+            "https://github.com/conda-forge/miniforge/releases/download/4.10.3-10/Miniforge3-4.10.3-10-Linux-aarch64.sh",
+        )),
+        _ => Err(Error::UnsupportedPlatform(format!("Unable to build {} in docker", platform))),
+    }
+}
+
+fn interpolate_dockerfile(installer_url: &str) -> String {
+    let one_line_command: Vec<&str> = BUILD_LOCKFILE
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    let olc = one_line_command.join(";");
+    DOCKERFILE
+        .replace("ONE_LINE_COMMAND", &olc)
+        .replace("INSTALLER_URL", installer_url)
+}
+
+/// Build the image used to freeze environments for `platform` and return its name.
+pub fn build_container(platform: Platform) -> Result<String> {
+    info!("Building container");
+    let (docker_platform, installer_url) = docker_platform(platform)?;
+    let image_name = match platform {
+        Platform::Linux64 => "lock_file_maker".to_string(),
+        _ => format!("lock_file_maker-{}", platform),
+    };
+    let dockerfile = interpolate_dockerfile(installer_url);
+    let mut docker_build = Command::new("docker")
+        .args(["build", "--platform", docker_platform, "-t", &image_name, "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Container(format!("Unable to run docker: {}", e)))?;
+
+    if let Some(mut stdin) = docker_build.stdin.take() {
+        stdin.write_all(dockerfile.as_bytes())?;
+    }
+    let status = docker_build.wait()?;
+    if !status.success() {
+        return Err(Error::Container(format!("docker build {}", status)));
+    }
+    Ok(image_name)
+}
+
+/// Run the lockfile building image for `platform` with `dir` mounted as its artifacts directory.
+///
+/// `dir` must contain `deps.yml` and `env_name`; the image writes `deps.yml.lock`, and the
+/// explicit package list `deps.yml.explicit`, next to them.
+pub fn run_container(
+    dir: &Path,
+    img_name: &str,
+    platform: Platform,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    let (docker_platform, _) = docker_platform(platform)?;
+    let vol_mount = format!("{}:/app/artifacts", dir.display());
+    let mut command = Command::new("docker");
+    command.args(["run", "--platform", docker_platform, "-v", &vol_mount]);
+    for package in virtual_packages {
+        if let Some(var) = package.override_var() {
+            command.arg("-e").arg(format!("{}={}", var, package.version));
+        }
+    }
+    let output = command
+        .args(["-t", img_name])
+        .output()
+        .map_err(|e| Error::Container(format!("Unable to run docker: {}", e)))?;
+    let msg = String::from_utf8_lossy(&output.stdout);
+    debug!("{}", msg);
+    if !output.status.success() {
+        let msg = format!("docker run {}\n{}", output.status, msg.trim());
+        if is_unsatisfiable(&msg) {
+            return Err(Error::Unsatisfiable(msg));
+        }
+        return Err(Error::Container(msg));
+    }
+    Ok(())
+}
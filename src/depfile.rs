@@ -0,0 +1,291 @@
+//! Reading depfiles, the conda environment files that lockfiles are frozen from.
+
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+
+use hash::{canonical_form, EnvHash, HashAlgorithm, HashMode};
+use lockfile::LockHeader;
+use pip::{PipLine, Requirement};
+use spec::DepSpec;
+use {Error, Result};
+
+/// The files of a local package that say what it depends on.
+const PACKAGE_METADATA_FILES: &[&str] =
+    &["pyproject.toml", "setup.cfg", "setup.py", "requirements.txt"];
+
+/// A local file referenced from the pip section of a depfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludedFile {
+    /// The path relative to the depfile's directory.
+    pub path: PathBuf,
+    /// What the file holds, or why it couldn't be read.
+    pub contents: ::std::result::Result<Vec<u8>, String>,
+}
+
+/// A depfile along with the pieces of it every subcommand needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Depfile {
+    pub path: PathBuf,
+    pub spec: DepSpec,
+    /// The file exactly as written, for `raw` mode hashes.
+    pub contents: String,
+    /// Files pulled in by `-r`, `-c` and local path requirements, in the order they are found.
+    pub includes: Vec<IncludedFile>,
+}
+
+impl Depfile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Depfile> {
+        let path = path.as_ref();
+        let mut depfile_data = String::new();
+        File::open(path)?.read_to_string(&mut depfile_data)?;
+        let spec = DepSpec::parse(&depfile_data)
+            .map_err(|e| Error::BadDepfile(format!("{}: {}", path.display(), e)))?;
+        let pip_lines = spec
+            .pip_lines()
+            .map_err(|e| Error::BadDepfile(format!("{}: {}", path.display(), e)))?;
+
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut includes = Vec::new();
+        collect_includes(root, Path::new(""), &pip_lines, &mut includes);
+
+        Ok(Depfile {
+            path: path.to_path_buf(),
+            spec,
+            contents: depfile_data,
+            includes,
+        })
+    }
+
+    /// The hash recorded in lockfiles frozen from this depfile.
+    ///
+    /// The contents of included files are folded in after the depfile itself, so a depfile
+    /// without includes hashes the same as it always has.  A missing include is folded in as
+    /// missing, so a lockfile frozen while it existed reads as stale rather than an error.
+    pub fn hash(&self, mode: HashMode, algorithm: HashAlgorithm) -> Result<EnvHash> {
+        let mut data = match mode {
+            HashMode::Raw => self.contents.clone().into_bytes(),
+            HashMode::Canonical => canonical_form(&self.spec).into_bytes(),
+        };
+        for include in &self.includes {
+            match include.contents {
+                Ok(ref contents) => {
+                    let header = format!("\0{}\0{}\0", include.path.display(), contents.len());
+                    data.extend_from_slice(header.as_bytes());
+                    data.extend_from_slice(contents);
+                }
+                Err(_) => {
+                    let header = format!("\0{}\0missing\0", include.path.display());
+                    data.extend_from_slice(header.as_bytes());
+                }
+            }
+        }
+        Ok(EnvHash {
+            mode,
+            algorithm,
+            digest: algorithm.digest(&data),
+        })
+    }
+
+    /// Hash this depfile the same way `found` was computed, to check whether it is stale.
+    pub fn hash_like(&self, found: &EnvHash) -> Result<EnvHash> {
+        self.hash(found.mode, found.algorithm)
+    }
+
+    /// The header of a lockfile frozen from this depfile today.
+    ///
+    /// Nothing can be frozen while an included file is missing.
+    pub fn lock_header(&self, mode: HashMode) -> Result<LockHeader> {
+        for include in &self.includes {
+            if let Err(ref e) = include.contents {
+                return Err(Error::BadDepfile(format!("{}: {}", self.path.display(), e)));
+            }
+        }
+        Ok(LockHeader {
+            hash: self.hash(mode, HashAlgorithm::default())?,
+            includes: self.includes.iter().map(|i| i.path.clone()).collect(),
+            virtual_packages: Vec::new(),
+        })
+    }
+}
+
+/// Resolve `.` and `..` components, so `./requirements.txt` and `reqs/../requirements.txt`
+/// are the same include as `requirements.txt`.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        let after_name = matches!(normalized.components().next_back(), Some(Component::Normal(_)));
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if after_name => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The local path a requirement installs from, if it is one.
+fn local_path(req: &Requirement) -> Option<&str> {
+    let url = req.url.as_ref()?;
+    let path = url.split('#').next().unwrap_or(url);
+    if let Some(path) = path.strip_prefix("file://") {
+        return Some(path);
+    }
+    if let Some(path) = path.strip_prefix("file:") {
+        return Some(path);
+    }
+    if path.contains("://") {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Find the files referenced by `lines`, which were read from `dir` (relative to `root`).
+///
+/// Files that can't be read are recorded with the reason, for `Depfile::lock_header` to report.
+fn collect_includes(root: &Path, dir: &Path, lines: &[PipLine], includes: &mut Vec<IncludedFile>) {
+    for line in lines {
+        match *line {
+            PipLine::Include(ref file) | PipLine::Constraint(ref file) => {
+                let path = normalize(&dir.join(file));
+                if includes.iter().any(|i| i.path == path) {
+                    // Already covered, and requirements files may include each other.
+                    continue;
+                }
+                let contents = read_include(root, &path);
+                let nested: Vec<PipLine> = match contents {
+                    Ok(ref contents) => String::from_utf8_lossy(contents)
+                        .lines()
+                        // Anything pip can't make sense of is pip's problem to report.
+                        .filter_map(|l| PipLine::parse(l).ok())
+                        .collect(),
+                    Err(_) => Vec::new(),
+                };
+                includes.push(IncludedFile {
+                    path: path.clone(),
+                    contents,
+                });
+                let nested_dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+                collect_includes(root, &nested_dir, &nested, includes);
+            }
+            PipLine::Requirement(ref req) => {
+                let package = match local_path(req) {
+                    Some(package) => normalize(&dir.join(package)),
+                    None => continue,
+                };
+                let full_path = root.join(&package);
+                let files = if full_path.is_file() {
+                    // A wheel or sdist.
+                    vec![package]
+                } else if full_path.is_dir() {
+                    // Hashing a whole source tree would be slow and catch build artifacts.  Its
+                    // metadata is what decides the dependencies.
+                    PACKAGE_METADATA_FILES
+                        .iter()
+                        .map(|name| package.join(name))
+                        .filter(|path| root.join(path).is_file())
+                        .collect()
+                } else {
+                    let contents = Err(format!("{} does not exist", package.display()));
+                    if !includes.iter().any(|i| i.path == package) {
+                        includes.push(IncludedFile {
+                            path: package,
+                            contents,
+                        });
+                    }
+                    continue;
+                };
+                for path in files {
+                    if includes.iter().any(|i| i.path == path) {
+                        continue;
+                    }
+                    let contents = read_include(root, &path);
+                    includes.push(IncludedFile { path, contents });
+                }
+            }
+            PipLine::Option(_) => {}
+        }
+    }
+}
+
+fn read_include(root: &Path, path: &Path) -> ::std::result::Result<Vec<u8>, String> {
+    fs::read(root.join(path)).map_err(|e| format!("unable to read {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write(path: &Path, contents: &str) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn referenced_files_are_hashed() {
+        let dir = tempdir().unwrap();
+        let depfile_path = dir.path().join("deps.yml");
+        write(
+            &depfile_path,
+            "name: foo
+dependencies:
+  - pip:
+    - -r ./requirements.txt
+    - --constraint constraints.txt
+    - -e ./mypkg
+",
+        );
+        write(&dir.path().join("requirements.txt"), "requests\n-r reqs/dev.txt\n");
+        write(&dir.path().join("reqs/dev.txt"), "pytest\n-r ../requirements.txt\n");
+        write(&dir.path().join("constraints.txt"), "requests<3\n");
+        write(&dir.path().join("mypkg/setup.py"), "setup(install_requires=['six'])\n");
+        write(&dir.path().join("mypkg/mypkg/__init__.py"), "");
+
+        let depfile = Depfile::open(&depfile_path).unwrap();
+        let paths: Vec<PathBuf> = depfile.includes.iter().map(|i| i.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("requirements.txt"),
+                PathBuf::from("reqs/dev.txt"),
+                PathBuf::from("constraints.txt"),
+                PathBuf::from("mypkg/setup.py"),
+            ]
+        );
+
+        let before = depfile.hash(HashMode::Raw, HashAlgorithm::Sha256).unwrap();
+        write(&dir.path().join("reqs/dev.txt"), "pytest>=4\n");
+        let after = Depfile::open(&depfile_path)
+            .unwrap()
+            .hash(HashMode::Raw, HashAlgorithm::Sha256)
+            .unwrap();
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn missing_referenced_file() {
+        let dir = tempdir().unwrap();
+        let depfile_path = dir.path().join("deps.yml");
+        write(&depfile_path, "name: foo\ndependencies:\n  - pip:\n    - -r missing.txt\n");
+        // Only freezing needs the file.
+        let depfile = Depfile::open(&depfile_path).unwrap();
+        let missing = depfile.hash(HashMode::Raw, HashAlgorithm::Sha256).unwrap();
+        match depfile.lock_header(HashMode::Raw) {
+            Err(Error::BadDepfile(msg)) => assert!(msg.contains("missing.txt"), "{}", msg),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        write(&dir.path().join("missing.txt"), "");
+        let found = Depfile::open(&depfile_path)
+            .unwrap()
+            .hash(HashMode::Raw, HashAlgorithm::Sha256)
+            .unwrap();
+        assert_ne!(missing, found);
+    }
+}
@@ -0,0 +1,389 @@
+//! Comparing two lockfiles package by package.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use conda_version::CondaVersion;
+use lockfile::Lockfile;
+use pep440::Version;
+use platform::Platform;
+use spec::LockSpec;
+use {Error, Result};
+
+/// How a package differs between two lockfiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Upgraded,
+    Downgraded,
+    /// Same version, different build.
+    Rebuilt,
+    /// A different version that can't be ordered, e.g. a pip package installed from a URL.
+    Changed,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match *self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Upgraded => "upgraded",
+            ChangeKind::Downgraded => "downgraded",
+            ChangeKind::Rebuilt => "rebuilt",
+            ChangeKind::Changed => "changed",
+        };
+        f.pad(kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageChange {
+    pub name: String,
+    pub kind: ChangeKind,
+    /// The old version and build, e.g. `1.15.4 py37_0`.
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// The differences between two lockfiles, sorted by package name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockDiff {
+    pub conda: Vec<PackageChange>,
+    pub pip: Vec<PackageChange>,
+}
+
+/// How `diff` prints its report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    Text,
+    Json,
+    Markdown,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<DiffFormat, String> {
+        match s {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            "markdown" => Ok(DiffFormat::Markdown),
+            _ => Err(format!("unknown diff format {:?}", s)),
+        }
+    }
+}
+
+/// A locked package: its version, parsed if possible, and build.
+struct Locked<V> {
+    version: Option<V>,
+    version_str: String,
+    build: String,
+}
+
+impl<V> Locked<V> {
+    fn describe(&self) -> String {
+        if self.build.is_empty() {
+            self.version_str.clone()
+        } else {
+            format!("{} {}", self.version_str, self.build)
+        }
+    }
+}
+
+fn conda_packages(
+    spec: &LockSpec,
+) -> ::std::result::Result<BTreeMap<String, Locked<CondaVersion>>, String> {
+    let mut packages = BTreeMap::new();
+    for ms in spec.conda_specs().map_err(|e| e.to_string())? {
+        let version_str = ms.locked_version().unwrap_or("").to_string();
+        let locked = Locked {
+            version: CondaVersion::parse(&version_str).ok(),
+            version_str,
+            build: ms.build.unwrap_or_default(),
+        };
+        packages.insert(ms.name, locked);
+    }
+    Ok(packages)
+}
+
+fn pip_packages(
+    spec: &LockSpec,
+) -> ::std::result::Result<BTreeMap<String, Locked<Version>>, String> {
+    let mut packages = BTreeMap::new();
+    for line in spec.pip_lines().map_err(|e| e.to_string())? {
+        let req = match line.requirement() {
+            Some(req) => req,
+            None => continue,
+        };
+        let name = match req.normalized_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        let pinned = req.specifier.as_ref().and_then(|s| s.strip_prefix("=="));
+        let version_str = match (pinned, &req.url, &req.specifier) {
+            (Some(version), _, _) => version.to_string(),
+            (None, Some(url), _) => url.clone(),
+            (None, None, Some(specifier)) => specifier.clone(),
+            (None, None, None) => String::new(),
+        };
+        let locked = Locked {
+            version: pinned.and_then(|v| Version::parse(v).ok()),
+            version_str,
+            build: String::new(),
+        };
+        packages.insert(name, locked);
+    }
+    Ok(packages)
+}
+
+fn diff_packages<V: Ord>(
+    old: &BTreeMap<String, Locked<V>>,
+    new: &BTreeMap<String, Locked<V>>,
+) -> Vec<PackageChange> {
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    let mut changes = Vec::new();
+    for name in names {
+        let (old, new) = (old.get(name), new.get(name));
+        let kind = match (old, new) {
+            (None, Some(_)) => ChangeKind::Added,
+            (Some(_), None) => ChangeKind::Removed,
+            (Some(old), Some(new)) => {
+                let ordering = match (&old.version, &new.version) {
+                    (Some(old_version), Some(new_version)) => Some(old_version.cmp(new_version)),
+                    _ if old.version_str == new.version_str => Some(Ordering::Equal),
+                    _ => None,
+                };
+                match ordering {
+                    Some(Ordering::Less) => ChangeKind::Upgraded,
+                    Some(Ordering::Greater) => ChangeKind::Downgraded,
+                    Some(Ordering::Equal) if old.build != new.build => ChangeKind::Rebuilt,
+                    Some(Ordering::Equal) => continue,
+                    None => ChangeKind::Changed,
+                }
+            }
+            (None, None) => continue,
+        };
+        changes.push(PackageChange {
+            name: name.clone(),
+            kind,
+            old: old.map(Locked::describe),
+            new: new.map(Locked::describe),
+        });
+    }
+    changes
+}
+
+/// Compare the packages locked by two lockfiles.
+pub fn diff_specs(old: &LockSpec, new: &LockSpec) -> Result<LockDiff> {
+    let conda = |spec: &LockSpec| conda_packages(spec).map_err(Error::BadLockfile);
+    let pip = |spec: &LockSpec| pip_packages(spec).map_err(Error::BadLockfile);
+    Ok(LockDiff {
+        conda: diff_packages(&conda(old)?, &conda(new)?),
+        pip: diff_packages(&pip(old)?, &pip(new)?),
+    })
+}
+
+/// Read a lockfile from a path, or from git as `git:<rev>:<path>`.
+///
+/// The git path is relative to the top of the repository, or to the current directory if it
+/// starts with `./`, as in `git show`.  Conda-lock lockfiles are read for `platform`.
+pub fn load_lockfile(reference: &str, platform: Platform) -> Result<LockSpec> {
+    let git_ref = reference.strip_prefix("git:").map(|rest| rest.splitn(2, ':'));
+    match git_ref.map(|mut parts| (parts.next(), parts.next())) {
+        Some((Some(rev), Some(path))) => {
+            let data = git_show(rev, path)?;
+            Ok(Lockfile::parse(Path::new(path), &data, platform)?.spec)
+        }
+        Some(_) => {
+            let msg = format!("{}: expected git:<rev>:<path>", reference);
+            Err(Error::BadLockfile(msg))
+        }
+        None => Ok(Lockfile::open_platform(reference, platform)?.spec),
+    }
+}
+
+fn git_show(rev: &str, path: &str) -> Result<String> {
+    let output = Command::new("git")
+        .arg("show")
+        .arg(format!("{}:{}", rev, path))
+        .output()?;
+    if !output.status.success() {
+        let msg = format!(
+            "git show {}:{}: {}",
+            rev,
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(Error::BadLockfile(msg));
+    }
+    String::from_utf8(output.stdout).map_err(|e| Error::BadLockfile(e.to_string()))
+}
+
+impl LockDiff {
+    pub fn is_empty(&self) -> bool {
+        self.conda.is_empty() && self.pip.is_empty()
+    }
+
+    fn sections(&self) -> [(&'static str, &[PackageChange]); 2] {
+        [("conda", &self.conda), ("pip", &self.pip)]
+    }
+
+    pub fn render(&self, format: DiffFormat) -> String {
+        match format {
+            DiffFormat::Text => self.to_text(),
+            DiffFormat::Json => format!("{}\n", self.to_json()),
+            DiffFormat::Markdown => self.to_markdown(),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        if self.is_empty() {
+            return "No changes\n".to_string();
+        }
+        let mut out = String::new();
+        for (section, changes) in self.sections().iter() {
+            if changes.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("{}:\n", section));
+            for change in changes.iter() {
+                let versions = match (&change.old, &change.new) {
+                    (Some(old), Some(new)) => format!("{} -> {}", old, new),
+                    (Some(version), None) | (None, Some(version)) => version.clone(),
+                    (None, None) => String::new(),
+                };
+                out.push_str(&format!("  {:<10} {} {}\n", change.kind, change.name, versions));
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut json = Map::new();
+        for (section, changes) in self.sections().iter() {
+            let changes = changes
+                .iter()
+                .map(|change| {
+                    let mut entry = Map::new();
+                    entry.insert("name".to_string(), Value::from(change.name.as_str()));
+                    entry.insert("change".to_string(), Value::from(change.kind.to_string()));
+                    entry.insert("old".to_string(), Value::from(change.old.clone()));
+                    entry.insert("new".to_string(), Value::from(change.new.clone()));
+                    Value::Object(entry)
+                }).collect();
+            json.insert(section.to_string(), Value::Array(changes));
+        }
+        Value::Object(json)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        if self.is_empty() {
+            return "No changes\n".to_string();
+        }
+        let mut out = String::new();
+        for (section, changes) in self.sections().iter() {
+            if changes.is_empty() {
+                continue;
+            }
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(&format!("### {}\n\n", section));
+            out.push_str("| Package | Change | Old | New |\n|---|---|---|---|\n");
+            for change in changes.iter() {
+                out.push_str(&format!(
+                    "| {} | {} | {} | {} |\n",
+                    change.name,
+                    change.kind,
+                    change.old.as_deref().unwrap_or(""),
+                    change.new.as_deref().unwrap_or("")
+                ));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "
+name: foo
+dependencies:
+  - numpy=1.15.4=py37_0
+  - openssl=1.1.1a=h7b6447c_0
+  - python=3.7.1=h0371630_7
+  - scipy=1.1.0=py37hfa4b5c9_1
+  - pip:
+    - requests==2.20.0
+    - six==1.12.0
+";
+
+    const NEW: &str = "
+name: foo
+dependencies:
+  - numpy=1.16.0=py37_0
+  - openssl=1.1.1a=h7b6447c_1
+  - pandas=0.24.0=py37_0
+  - python=3.6.8=h0371630_0
+  - pip:
+    - requests==2.21.0
+    - six==1.12.0
+";
+
+    fn diff() -> LockDiff {
+        diff_specs(&LockSpec::parse(OLD).unwrap(), &LockSpec::parse(NEW).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn changes() {
+        let diff = diff();
+        let kinds: Vec<(&str, ChangeKind)> =
+            diff.conda.iter().map(|c| (c.name.as_str(), c.kind)).collect();
+        assert_eq!(
+            kinds,
+            [
+                ("numpy", ChangeKind::Upgraded),
+                ("openssl", ChangeKind::Rebuilt),
+                ("pandas", ChangeKind::Added),
+                ("python", ChangeKind::Downgraded),
+                ("scipy", ChangeKind::Removed),
+            ]
+        );
+        assert_eq!(diff.conda[0].old.as_deref(), Some("1.15.4 py37_0"));
+        assert_eq!(diff.conda[2].old, None);
+        assert_eq!(diff.pip.len(), 1);
+        assert_eq!(diff.pip[0].kind, ChangeKind::Upgraded);
+        assert!(diff_specs(&LockSpec::parse(OLD).unwrap(), &LockSpec::parse(OLD).unwrap())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rendering() {
+        let diff = diff();
+        let text = diff.to_text();
+        assert!(text.starts_with("conda:\n  upgraded   numpy 1.15.4 py37_0 -> 1.16.0 py37_0\n"));
+        assert!(text.ends_with("pip:\n  upgraded   requests 2.20.0 -> 2.21.0\n"));
+
+        let json = diff.to_json();
+        assert_eq!(json["conda"][2]["change"], "added");
+        assert_eq!(json["conda"][2]["old"], Value::Null);
+        assert_eq!(json["pip"][0]["new"], "2.21.0");
+
+        let markdown = diff.to_markdown();
+        assert!(markdown.starts_with("### conda\n\n| Package | Change | Old | New |\n"));
+        assert!(markdown.contains("| scipy | removed | 1.1.0 py37hfa4b5c9_1 |  |\n"));
+        assert!(markdown.contains("\n### pip\n"));
+    }
+}
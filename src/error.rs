@@ -0,0 +1,153 @@
+//! Errors, and the process exit codes they map to.
+//!
+//! Exit codes are part of the command line interface; scripts rely on them, so never renumber
+//! an existing variant.
+//!
+//! | code | meaning                                                        |
+//! |------|----------------------------------------------------------------|
+//! | 0    | success                                                        |
+//! | 1    | anything not listed below, including bad command line usage    |
+//! | 2    | a lockfile is stale: its hash does not match the depfile       |
+//! | 3    | a lockfile does not exist                                      |
+//! | 4    | a lockfile could not be read                                   |
+//! | 5    | the depfile could not be read                                  |
+//! | 6    | a freshly frozen lockfile does not satisfy the depfile         |
+//! | 7    | conda could not be found                                       |
+//! | 8    | conda failed                                                   |
+//! | 9    | docker failed                                                  |
+//! | 10   | the requested platform cannot be targeted from this one        |
+//! | 11   | the config file could not be read                              |
+//! | 12   | channel repodata could not be read                             |
+//! | 13   | the requested packages can't be installed together             |
+//! | 14   | an installed environment does not match its lockfile           |
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use validate::ValidationReport;
+use verify::VerifyReport;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The hash recorded in these lockfiles does not match their depfile.
+    StaleLock(Vec<PathBuf>),
+    MissingLockfile(PathBuf),
+    BadLockfile(String),
+    BadDepfile(String),
+    /// A lockfile does not provide the packages requested by its depfile.
+    InvalidLockfile(PathBuf, ValidationReport),
+    CondaNotFound,
+    Conda(String),
+    Container(String),
+    UnsupportedPlatform(String),
+    BadConfig(String),
+    BadRepodata(String),
+    /// The solver found no set of packages satisfying the request.
+    Unsatisfiable(String),
+    /// The packages installed in this prefix differ from its lockfile.
+    EnvMismatch(PathBuf, Box<VerifyReport>),
+    /// Arguments that parse but can't be used together, reported like clap's usage errors.
+    Usage(String),
+}
+
+impl Error {
+    /// The process exit code reported for this error.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Io(_) => 1,
+            Error::StaleLock(_) => 2,
+            Error::MissingLockfile(_) => 3,
+            Error::BadLockfile(_) => 4,
+            Error::BadDepfile(_) => 5,
+            Error::InvalidLockfile(..) => 6,
+            Error::CondaNotFound => 7,
+            Error::Conda(_) => 8,
+            Error::Container(_) => 9,
+            Error::UnsupportedPlatform(_) => 10,
+            Error::BadConfig(_) => 11,
+            Error::BadRepodata(_) => 12,
+            Error::Unsatisfiable(_) => 13,
+            Error::EnvMismatch(..) => 14,
+            Error::Usage(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::StaleLock(ref paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "Hashes do not match: {}", paths.join(", "))
+            }
+            Error::MissingLockfile(ref path) => write!(f, "No lockfile at {}", path.display()),
+            Error::BadLockfile(ref msg) => write!(f, "Bad lockfile: {}", msg),
+            Error::BadDepfile(ref msg) => write!(f, "Bad depfile: {}", msg),
+            Error::InvalidLockfile(ref path, ref report) => {
+                write!(f, "Invalid lockfile {}:\n{}", path.display(), report)
+            }
+            Error::CondaNotFound => write!(f, "Unable to find conda"),
+            Error::Conda(ref msg) => write!(f, "conda failed: {}", msg),
+            Error::Container(ref msg) => write!(f, "docker failed: {}", msg),
+            Error::UnsupportedPlatform(ref msg) => write!(f, "Unsupported platform: {}", msg),
+            Error::BadConfig(ref msg) => write!(f, "Bad config: {}", msg),
+            Error::BadRepodata(ref msg) => write!(f, "Bad repodata: {}", msg),
+            Error::Unsatisfiable(ref msg) => write!(f, "Unsatisfiable: {}", msg),
+            Error::EnvMismatch(ref prefix, ref report) => {
+                write!(f, "{} does not match its lockfile:\n{}", prefix.display(), report)
+            }
+            Error::Usage(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = vec![
+            Error::Io(io::Error::from(io::ErrorKind::NotFound)),
+            Error::StaleLock(vec![]),
+            Error::MissingLockfile(PathBuf::from("deps.yml.Linux.lock")),
+            Error::BadLockfile("".to_string()),
+            Error::BadDepfile("".to_string()),
+            Error::InvalidLockfile(PathBuf::new(), ValidationReport::default()),
+            Error::CondaNotFound,
+            Error::Conda("".to_string()),
+            Error::Container("".to_string()),
+            Error::UnsupportedPlatform("".to_string()),
+            Error::BadConfig("".to_string()),
+            Error::BadRepodata("".to_string()),
+            Error::Unsatisfiable("".to_string()),
+            Error::EnvMismatch(PathBuf::new(), Box::default()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0));
+        // The same as clap's own usage errors.
+        assert_eq!(Error::Usage("".to_string()).exit_code(), 1);
+    }
+}
@@ -0,0 +1,217 @@
+//! Explicit lockfiles: the package list written by `conda list --explicit --md5`.
+//!
+//! ```text
+//! # platform: linux-64
+//! @EXPLICIT
+//! https://repo.anaconda.com/pkgs/main/linux-64/numpy-1.15.4-py37_0.tar.bz2#a8b8f2d3...
+//! ```
+//!
+//! Every package is an exact URL, so conda installs the list without solving.  Comments
+//! before `@EXPLICIT` carry the environment name and platform.
+
+use std::fmt;
+
+use matchspec::split_package_filename;
+use platform::Platform;
+use spec::LockSpec;
+
+/// The line separating the comments of an explicit file from its packages.
+pub const EXPLICIT_MARKER: &str = "@EXPLICIT";
+
+const NAME_COMMENT: &str = "# name:";
+const PLATFORM_COMMENT: &str = "# platform:";
+
+/// A package to install, by URL, with the checksums to verify it against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplicitPackage {
+    pub url: String,
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl ExplicitPackage {
+    /// Parse `<url>`, `<url>#<md5>` or `<url>#sha256:<hex>`.
+    pub fn parse(line: &str) -> Result<ExplicitPackage, String> {
+        let line = line.trim();
+        let (url, checksum) = match line.find('#') {
+            Some(idx) => (&line[..idx], Some(&line[idx + 1..])),
+            None => (line, None),
+        };
+        let mut package = ExplicitPackage {
+            url: url.to_string(),
+            md5: None,
+            sha256: None,
+        };
+        match checksum {
+            Some(hash) if hash.starts_with("sha256:") => {
+                package.sha256 = Some(hash["sha256:".len()..].to_string())
+            }
+            Some(hash) => package.md5 = Some(hash.to_string()),
+            None => {}
+        }
+        if package.parts().is_none() {
+            return Err(format!("{:?} is not a package URL", line));
+        }
+        Ok(package)
+    }
+
+    /// The file name at the end of the URL.
+    pub fn filename(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or(&self.url)
+    }
+
+    /// The channel URL, without subdir and file name.
+    pub fn channel(&self) -> Option<&str> {
+        let mut parts = self.url.rsplitn(3, '/');
+        parts.next()?;
+        parts.next()?;
+        parts.next()
+    }
+
+    /// The name, version and build in the file name.
+    pub fn parts(&self) -> Option<(String, String, String)> {
+        split_package_filename(self.filename())
+    }
+
+    /// The package as an environment file entry, `name=version=build`.
+    pub fn spec(&self) -> String {
+        match self.parts() {
+            Some((name, version, build)) => format!("{}={}={}", name, version, build),
+            None => self.url.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ExplicitPackage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.md5, &self.sha256) {
+            (Some(md5), _) => write!(f, "{}#{}", self.url, md5),
+            (None, Some(sha256)) => write!(f, "{}#sha256:{}", self.url, sha256),
+            (None, None) => write!(f, "{}", self.url),
+        }
+    }
+}
+
+/// A parsed explicit file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExplicitSpec {
+    /// The environment name, from a `# name:` comment.  Conda doesn't write one.
+    pub name: Option<String>,
+    pub platform: Option<Platform>,
+    pub packages: Vec<ExplicitPackage>,
+}
+
+/// Is `data` an explicit file rather than an environment file?
+pub fn is_explicit(data: &str) -> bool {
+    data.lines().any(|line| line.trim() == EXPLICIT_MARKER)
+}
+
+impl ExplicitSpec {
+    pub fn parse(data: &str) -> Result<ExplicitSpec, String> {
+        let mut spec = ExplicitSpec::default();
+        let mut in_packages = false;
+        for line in data.lines() {
+            let line = line.trim();
+            if line == EXPLICIT_MARKER {
+                in_packages = true;
+            } else if let Some(name) = line.strip_prefix(NAME_COMMENT) {
+                spec.name = Some(name.trim().to_string());
+            } else if let Some(platform) = line.strip_prefix(PLATFORM_COMMENT) {
+                spec.platform = platform.trim().parse().ok();
+            } else if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else if in_packages {
+                spec.packages.push(ExplicitPackage::parse(line)?);
+            } else {
+                return Err(format!("{:?} before {}", line, EXPLICIT_MARKER));
+            }
+        }
+        if !in_packages {
+            return Err(format!("no {} line", EXPLICIT_MARKER));
+        }
+        Ok(spec)
+    }
+
+    /// Serialize like `conda list --explicit`, with the name as a comment.
+    pub fn emit(&self) -> String {
+        let mut out = String::from(
+            "# This file may be used to create an environment using:\n\
+             # $ conda create --name <env> --file <this file>\n",
+        );
+        if let Some(ref name) = self.name {
+            out.push_str(&format!("{} {}\n", NAME_COMMENT, name));
+        }
+        if let Some(platform) = self.platform {
+            out.push_str(&format!("{} {}\n", PLATFORM_COMMENT, platform));
+        }
+        out.push_str(EXPLICIT_MARKER);
+        out.push('\n');
+        for package in &self.packages {
+            out.push_str(&format!("{}\n", package));
+        }
+        out
+    }
+
+    /// The same environment as an environment file, for validating against a depfile.
+    pub fn lock_spec(&self) -> LockSpec {
+        let mut channels: Vec<String> = Vec::new();
+        for channel in self.packages.iter().filter_map(ExplicitPackage::channel) {
+            if !channels.iter().any(|c| c == channel) {
+                channels.push(channel.to_string());
+            }
+        }
+        LockSpec {
+            name: self.name.clone().unwrap_or_default(),
+            channels,
+            conda_deps: self.packages.iter().map(ExplicitPackage::spec).collect(),
+            ..LockSpec::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPLICIT: &str = "\
+# This file may be used to create an environment using:
+# $ conda create --name <env> --file <this file>
+# platform: linux-64
+@EXPLICIT
+https://repo.anaconda.com/pkgs/main/linux-64/numpy-1.15.4-py37_0.tar.bz2#a8b8f2d3
+https://conda.anaconda.org/conda-forge/noarch/six-1.12.0-py_0.conda#sha256:0123abcd
+";
+
+    #[test]
+    fn parse() {
+        let spec = ExplicitSpec::parse(EXPLICIT).unwrap();
+        assert_eq!(spec.name, None);
+        assert_eq!(spec.platform, Some(Platform::Linux64));
+        assert_eq!(spec.packages.len(), 2);
+        assert_eq!(spec.packages[0].md5.as_deref(), Some("a8b8f2d3"));
+        assert_eq!(spec.packages[1].sha256.as_deref(), Some("0123abcd"));
+        assert_eq!(spec.packages[1].filename(), "six-1.12.0-py_0.conda");
+
+        let lock_spec = spec.lock_spec();
+        assert_eq!(lock_spec.conda_deps, ["numpy=1.15.4=py37_0", "six=1.12.0=py_0"]);
+        assert_eq!(
+            lock_spec.channels,
+            ["https://repo.anaconda.com/pkgs/main", "https://conda.anaconda.org/conda-forge"]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut spec = ExplicitSpec::parse(EXPLICIT).unwrap();
+        assert_eq!(spec.emit(), EXPLICIT);
+        spec.name = Some("foo".to_string());
+        assert_eq!(ExplicitSpec::parse(&spec.emit()).unwrap(), spec);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(!is_explicit("name: foo\n"));
+        assert!(ExplicitSpec::parse("name: foo\n").is_err());
+        assert!(ExplicitSpec::parse("@EXPLICIT\nnot-a-package\n").is_err());
+    }
+}
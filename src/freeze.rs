@@ -0,0 +1,504 @@
+//! Freezing depfiles into lockfiles.
+
+use std::collections::BTreeMap;
+use std::fs::{copy, File};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use tempfile::{tempdir_in, NamedTempFile};
+
+use conda::{
+    create_dry_run, env_create, env_export, find_conda, list_explicit, parse_link_actions,
+    EnvTarget, LinkAction,
+};
+use condalock::CondaLock;
+use container::{build_container, run_container};
+use depfile::Depfile;
+use explicit::{ExplicitPackage, ExplicitSpec};
+use hash::HashMode;
+use lockfile::{write_explicit_lockfile, write_lockfile, LockFormat, Lockfile, StagedLockfile};
+use platform::{get_platform, Platform, VirtualPackage};
+use repodata::{default_cache_dir, Index, PackageRecord};
+use solver::solve;
+use spec::LockSpec;
+use validate::validate_lockfile;
+use {Error, Result};
+
+/// Freeze a depfile by building it with the local conda install.
+pub fn freeze_same_platform(
+    depfile_path: &Path,
+    lockfile_path: &Path,
+    hash_mode: HashMode,
+    format: LockFormat,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    debug!("Freezing");
+    let depfile = Depfile::open(depfile_path)?;
+
+    let conda_path = find_conda()?;
+    // Create the environment, but use a name that is unlikely to clobber anything pre-existing.
+    let tmp_name = "___conda_lockfile_temp".to_string();
+    let target = EnvTarget::Name(tmp_name.clone());
+    env_create(&conda_path, depfile_path, &target, virtual_packages)?;
+    info!("Made new env new env");
+
+    // Read the env create by `conda create`.
+    debug!("Reading env");
+    let frozen = match format {
+        LockFormat::Env => {
+            let lock_data = env_export(&conda_path, &tmp_name)?;
+            debug!("Env data:\n{}", lock_data);
+
+            // Replace the temporary env name with the real one.
+            // Also drop the prefix field.  It is irrelevant.
+            let mut lock_spec = LockSpec::parse(&lock_data)
+                .map_err(|e| Error::Conda(format!("Unexpected env export: {}", e)))?;
+            lock_spec.name = depfile.spec.name.clone();
+            lock_spec.prefix = None;
+            Frozen::Env(lock_spec)
+        }
+        LockFormat::Explicit | LockFormat::CondaLock => {
+            let lock_data = list_explicit(&conda_path, &tmp_name)?;
+            debug!("Package list:\n{}", lock_data);
+            let explicit = parse_explicit(&lock_data, &depfile, get_platform()?)
+                .map_err(|e| Error::Conda(format!("Unexpected package list: {}", e)))?;
+            Frozen::Explicit(explicit)
+        }
+    };
+
+    write_valid_lockfile(&depfile, &frozen, lockfile_path, hash_mode, virtual_packages)
+}
+
+/// Freeze a depfile for a Linux platform by building it inside a docker container.
+pub fn freeze_in_container(
+    depfile_path: &Path,
+    lockfile_path: &Path,
+    platform: Platform,
+    hash_mode: HashMode,
+    format: LockFormat,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    info!("Freezing {} in a container", platform);
+    let depfile = Depfile::open(depfile_path)?;
+
+    // The only way to know what should be in an environment is to build it and document what
+    // dependencies showed up.  We do this in a docker container to ensure isolation, and to allow
+    // us to build lockfiles on mac.
+    let img_name = build_container(platform)?;
+    info!("Make container {}", img_name);
+    let tmpdir = tempdir_in("/tmp/")?;
+    let tmpdir_path = tmpdir.path();
+
+    // put depfile into tmpdir
+    {
+        info!("Copying depsfile");
+        let dest = tmpdir_path.join("deps.yml");
+        copy(depfile_path, dest)?;
+        let mut envname_file = File::create(tmpdir_path.join("env_name"))?;
+        envname_file.write_all(depfile.spec.name.as_bytes())?;
+    }
+
+    // run container
+    info!("Running container");
+    run_container(tmpdir_path, &img_name, platform, virtual_packages)?;
+    info!("Container completed");
+
+    // Read the generated lockfile.
+    let frozen = match format {
+        LockFormat::Env => {
+            let mut tmp_lockfile = File::open(tmpdir_path.join("deps.yml.lock"))?;
+            let mut tmp_lockfile_data = String::new();
+            tmp_lockfile.read_to_string(&mut tmp_lockfile_data)?;
+            let lock_spec = LockSpec::parse(&tmp_lockfile_data)
+                .map_err(|e| Error::Container(format!("Unexpected env export: {}", e)))?;
+            Frozen::Env(lock_spec)
+        }
+        LockFormat::Explicit | LockFormat::CondaLock => {
+            let mut explicit_data = String::new();
+            File::open(tmpdir_path.join("deps.yml.explicit"))?
+                .read_to_string(&mut explicit_data)?;
+            let explicit = parse_explicit(&explicit_data, &depfile, platform)
+                .map_err(|e| Error::Container(format!("Unexpected package list: {}", e)))?;
+            Frozen::Explicit(explicit)
+        }
+    };
+
+    write_valid_lockfile(&depfile, &frozen, lockfile_path, hash_mode, virtual_packages)
+}
+
+/// Parse `conda list --explicit` output, naming it after the depfile's environment.
+fn parse_explicit(
+    data: &str,
+    depfile: &Depfile,
+    platform: Platform,
+) -> ::std::result::Result<ExplicitSpec, String> {
+    let mut explicit = ExplicitSpec::parse(data)?;
+    explicit.name = Some(depfile.spec.name.clone());
+    explicit.platform = Some(platform);
+    Ok(explicit)
+}
+
+/// Freeze a depfile for any platform by asking conda's solver what it would install.
+///
+/// Nothing is downloaded or installed.  Pip packages can't be solved this way, so they are
+/// copied into the lockfile as requested.
+pub fn freeze_solve(
+    depfile_path: &Path,
+    lockfile_path: &Path,
+    platform: Platform,
+    hash_mode: HashMode,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    info!("Solving {}", platform);
+    let depfile = Depfile::open(depfile_path)?;
+    let conda_path = find_conda()?;
+    let report = create_dry_run(
+        &conda_path,
+        &depfile.spec.channels,
+        &depfile.spec.conda_deps,
+        platform,
+        virtual_packages,
+    )?;
+    debug!("Solver report:\n{}", report);
+    let links = parse_link_actions(&report)?;
+
+    let conda_deps = links.iter().map(LinkAction::spec).collect();
+    let frozen = Frozen::Env(solved_lock_spec(&depfile, conda_deps, platform));
+    write_valid_lockfile(&depfile, &frozen, lockfile_path, hash_mode, virtual_packages)
+}
+
+/// Freeze a depfile for any platform with the built-in solver, without conda.
+///
+/// Every channel must be local, a directory or `file://` URL holding `repodata.json` files.
+pub fn freeze_native(
+    depfile_path: &Path,
+    lockfile_path: &Path,
+    platform: Platform,
+    hash_mode: HashMode,
+    format: LockFormat,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    info!("Solving {} natively", platform);
+    let depfile = Depfile::open(depfile_path)?;
+    if depfile.spec.channels.is_empty() {
+        let msg = "the native solver needs the depfile to list its channels".to_string();
+        return Err(Error::BadRepodata(msg));
+    }
+    let cache_dir = default_cache_dir();
+    let index = Index::load(&depfile.spec.channels, platform, cache_dir.as_deref())?;
+    let specs = depfile.spec.conda_specs().map_err(|e| Error::BadDepfile(e.to_string()))?;
+    let solution = solve(&index, &specs, platform, virtual_packages)?;
+
+    let frozen = match format {
+        LockFormat::Env => {
+            let conda_deps = solution.iter().map(PackageRecord::spec).collect();
+            Frozen::Env(solved_lock_spec(&depfile, conda_deps, platform))
+        }
+        LockFormat::Explicit | LockFormat::CondaLock => Frozen::Explicit(ExplicitSpec {
+            name: Some(depfile.spec.name.clone()),
+            platform: Some(platform),
+            packages: solution
+                .iter()
+                .map(|record| ExplicitPackage {
+                    url: record.url(),
+                    md5: record.md5.clone(),
+                    sha256: record.sha256.clone(),
+                }).collect(),
+        }),
+    };
+    write_valid_lockfile(&depfile, &frozen, lockfile_path, hash_mode, virtual_packages)
+}
+
+/// The lockfile for a solved environment.  Pip packages can't be solved, so they are copied
+/// as requested.
+fn solved_lock_spec(depfile: &Depfile, conda_deps: Vec<String>, platform: Platform) -> LockSpec {
+    if !depfile.spec.pip_deps.is_empty() {
+        warn!("pip packages are not solved for {}; locking them as requested", platform);
+    }
+    LockSpec {
+        name: depfile.spec.name.clone(),
+        channels: depfile.spec.channels.clone(),
+        conda_deps,
+        pip_deps: depfile.spec.pip_deps.clone(),
+        variables: depfile.spec.variables.clone(),
+        prefix: None,
+    }
+}
+
+/// A frozen environment, in the format it is written in.
+enum Frozen {
+    Env(LockSpec),
+    Explicit(ExplicitSpec),
+}
+
+/// Check a frozen environment against its depfile, then write it with its header.
+fn write_valid_lockfile(
+    depfile: &Depfile,
+    frozen: &Frozen,
+    lockfile_path: &Path,
+    hash_mode: HashMode,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    // Validation
+    let report = match *frozen {
+        Frozen::Env(ref lock_spec) => validate_lockfile(&depfile.spec, lock_spec)?,
+        Frozen::Explicit(ref explicit) => validate_lockfile(&depfile.spec, &explicit.lock_spec())?,
+    };
+    if !report.is_valid() {
+        return Err(Error::InvalidLockfile(lockfile_path.to_path_buf(), report));
+    }
+
+    // Write valid lockfile & include hash
+    write_frozen(depfile, frozen, lockfile_path, hash_mode, virtual_packages)
+}
+
+fn write_frozen(
+    depfile: &Depfile,
+    frozen: &Frozen,
+    lockfile_path: &Path,
+    hash_mode: HashMode,
+    virtual_packages: &[VirtualPackage],
+) -> Result<()> {
+    info!("Writing lockfile {}", lockfile_path.display());
+    let mut header = depfile.lock_header(hash_mode)?;
+    header.virtual_packages = virtual_packages.to_vec();
+    let lockfile = File::create(lockfile_path)?;
+    match *frozen {
+        Frozen::Env(ref lock_spec) => write_lockfile(lockfile, lock_spec, &header),
+        Frozen::Explicit(ref explicit) => write_explicit_lockfile(lockfile, explicit, &header),
+    }
+}
+
+/// How `freeze` finds out what goes in an environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreezeMethod {
+    /// Build the environment, locally or in a container, and export it.
+    Install,
+    /// Ask the solver, without installing anything.  Works for every platform.
+    Solve,
+    /// Solve with the built-in solver over local channels.  Needs neither conda nor a network.
+    Native,
+}
+
+impl FromStr for FreezeMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<FreezeMethod, String> {
+        match s {
+            "install" => Ok(FreezeMethod::Install),
+            "solve" => Ok(FreezeMethod::Solve),
+            "native" => Ok(FreezeMethod::Native),
+            _ => Err(format!("unknown freeze method {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreezeOptions {
+    pub hash_mode: HashMode,
+    pub method: FreezeMethod,
+    pub format: LockFormat,
+    /// Virtual package overrides for each platform, recorded in its lockfile's header.
+    pub virtual_packages: BTreeMap<Platform, Vec<VirtualPackage>>,
+}
+
+/// Freeze a depfile for `platform`, by whichever means can target it from here.
+pub fn freeze_platform(
+    depfile_path: &Path,
+    lockfile_path: &Path,
+    platform: Platform,
+    options: &FreezeOptions,
+) -> Result<()> {
+    let hash_mode = options.hash_mode;
+    let format = options.format;
+    let virtual_packages = options
+        .virtual_packages
+        .get(&platform)
+        .map(|packages| &packages[..])
+        .unwrap_or(&[]);
+    if format == LockFormat::CondaLock {
+        return freeze_conda_lock(depfile_path, &[platform], lockfile_path, options);
+    }
+    if format == LockFormat::Explicit {
+        // Explicit files list conda packages only.
+        if !Depfile::open(depfile_path)?.spec.pip_deps.is_empty() {
+            let msg = "pip packages can't be locked with package checksums".to_string();
+            return Err(Error::BadDepfile(msg));
+        }
+        if options.method == FreezeMethod::Solve {
+            let msg = "conda's solver doesn't report package checksums; \
+                       try --method native or install"
+                .to_string();
+            return Err(Error::Conda(msg));
+        }
+    }
+    match options.method {
+        FreezeMethod::Solve => {
+            return freeze_solve(depfile_path, lockfile_path, platform, hash_mode, virtual_packages)
+        }
+        FreezeMethod::Native => {
+            return freeze_native(
+                depfile_path,
+                lockfile_path,
+                platform,
+                hash_mode,
+                format,
+                virtual_packages,
+            )
+        }
+        FreezeMethod::Install => {}
+    }
+    let execution_platform = get_platform()?;
+    if execution_platform == platform {
+        info!("Execution & target platform match");
+        freeze_same_platform(depfile_path, lockfile_path, hash_mode, format, virtual_packages)
+    } else if platform.is_linux() {
+        freeze_in_container(
+            depfile_path,
+            lockfile_path,
+            platform,
+            hash_mode,
+            format,
+            virtual_packages,
+        )
+    } else {
+        let msg = format!(
+            "Unable to target {} from {}; try --method solve",
+            platform, execution_platform
+        );
+        Err(Error::UnsupportedPlatform(msg))
+    }
+}
+
+/// Freeze a depfile for several platforms, writing each lockfile only if every one succeeds.
+///
+/// Lockfiles are frozen into temporary files next to their destination and moved into place
+/// at the end, so a failed freeze leaves the existing lockfiles untouched.  Moving them is not
+/// atomic as a whole: if a rename fails, say for lack of permission, the lockfiles before it
+/// have already been replaced.
+pub fn freeze_all(
+    depfile_path: &Path,
+    targets: &[(Platform, PathBuf)],
+    options: &FreezeOptions,
+) -> Result<()> {
+    let mut staged = Vec::new();
+    for (platform, lockfile_path) in targets {
+        info!("Freezing {} into {}", platform, lockfile_path.display());
+        let staged_lockfile = StagedLockfile::new(lockfile_path)?;
+        freeze_platform(depfile_path, &staged_lockfile.path(), *platform, options)?;
+        staged.push((staged_lockfile, lockfile_path));
+    }
+    for (staged_lockfile, lockfile_path) in staged {
+        staged_lockfile.persist()?;
+        info!("Wrote {}", lockfile_path.display());
+    }
+    Ok(())
+}
+
+/// Freeze a depfile for several platforms into one conda-lock lockfile, written only if every
+/// platform succeeds.
+///
+/// Each platform is frozen as an explicit lockfile, and its packages and depfile hash are
+/// gathered into the unified file.
+pub fn freeze_conda_lock(
+    depfile_path: &Path,
+    platforms: &[Platform],
+    lockfile_path: &Path,
+    options: &FreezeOptions,
+) -> Result<()> {
+    let depfile = Depfile::open(depfile_path)?;
+    let dir = match lockfile_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let explicit_options = FreezeOptions {
+        format: LockFormat::Explicit,
+        ..options.clone()
+    };
+    let mut conda_lock = CondaLock {
+        name: Some(depfile.spec.name.clone()),
+        channels: depfile.spec.channels.clone(),
+        sources: vec![depfile_path.display().to_string()],
+        ..CondaLock::default()
+    };
+    for platform in platforms {
+        info!("Freezing {} into {}", platform, lockfile_path.display());
+        let tmp_lockfile = NamedTempFile::new_in(dir)?;
+        freeze_platform(depfile_path, tmp_lockfile.path(), *platform, &explicit_options)?;
+        let lockfile = Lockfile::open_platform(tmp_lockfile.path(), *platform)?;
+        conda_lock.add_platform(*platform, lockfile.header.hash, &lockfile.packages);
+    }
+    let staged_lockfile = StagedLockfile::new(lockfile_path)?;
+    File::create(staged_lockfile.path())?.write_all(conda_lock.emit().as_bytes())?;
+    staged_lockfile.persist()?;
+    info!("Wrote {}", lockfile_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir, read_to_string, write};
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn native_freeze_from_local_channel() {
+        let dir = tempdir().unwrap();
+        let channel = dir.path().join("channel");
+        create_dir(&channel).unwrap();
+        create_dir(channel.join("linux-64")).unwrap();
+        write(
+            channel.join("linux-64").join("repodata.json"),
+            r#"{"packages": {
+              "python-3.7.3-h0_0.tar.bz2":
+                {"name": "python", "version": "3.7.3", "build": "h0_0", "build_number": 0},
+              "numpy-1.16.0-py37_0.tar.bz2":
+                {"name": "numpy", "version": "1.16.0", "build": "py37_0", "build_number": 0,
+                 "md5": "abcd",
+                 "depends": ["python >=3.7,<3.8.0a0"]}
+            }}"#,
+        ).unwrap();
+        let depfile = dir.path().join("deps.yml");
+        write(
+            &depfile,
+            format!(
+                "name: foo\nchannels:\n  - file://{}\ndependencies:\n  - numpy\n",
+                channel.display()
+            ),
+        ).unwrap();
+
+        let lockfile = dir.path().join("deps.yml.linux-64.lock");
+        let freeze = |format| {
+            freeze_native(&depfile, &lockfile, Platform::Linux64, HashMode::Raw, format, &[])
+                .unwrap();
+            read_to_string(&lockfile).unwrap()
+        };
+        let locked = freeze(LockFormat::Env);
+        assert!(locked.contains("numpy=1.16.0=py37_0"), "{}", locked);
+        assert!(locked.contains("python=3.7.3=h0_0"), "{}", locked);
+
+        let locked = freeze(LockFormat::Explicit);
+        assert!(locked.starts_with("# ENVHASH: "), "{}", locked);
+        let numpy_url = format!(
+            "file://{}/linux-64/numpy-1.16.0-py37_0.tar.bz2#abcd",
+            channel.display()
+        );
+        assert!(locked.contains(&numpy_url), "{}", locked);
+
+        let options = FreezeOptions {
+            hash_mode: HashMode::Raw,
+            method: FreezeMethod::Native,
+            format: LockFormat::CondaLock,
+            virtual_packages: BTreeMap::new(),
+        };
+        let conda_lock_path = dir.path().join("conda-lock.yml");
+        freeze_conda_lock(&depfile, &[Platform::Linux64], &conda_lock_path, &options).unwrap();
+        let conda_lock = CondaLock::parse(&read_to_string(&conda_lock_path).unwrap()).unwrap();
+        assert_eq!(conda_lock.name.as_deref(), Some("foo"));
+        assert_eq!(conda_lock.platforms, [Platform::Linux64]);
+        assert_eq!(conda_lock.packages.len(), 2);
+        assert_eq!(conda_lock.packages[0].md5.as_deref(), Some("abcd"));
+    }
+}
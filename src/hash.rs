@@ -0,0 +1,328 @@
+//! Hashing of depfiles.
+//!
+//! A depfile can be hashed in one of two modes:
+//!
+//! * `raw` hashes the bytes of the file, so any edit at all makes its lockfiles stale.
+//! * `canonical` hashes the parsed environment, normalized so that formatting, comments, line
+//!   endings and the order of dependencies don't matter.
+//!
+//! Either way the bytes are digested with SHA-256, or with SHA-1 for lockfiles written before
+//! hashes were tagged with their algorithm.
+//!
+//! The mode and algorithm are recorded alongside the digest in the lockfile, so checking a
+//! lockfile always recomputes the depfile hash the same way it was computed at freeze time.
+
+use std::fmt;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use sha1;
+use sha2::{Digest, Sha256};
+
+use pip::PipLine;
+use spec::EnvSpec;
+use Result;
+
+/// How a depfile is turned into the bytes that get hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashMode {
+    /// The file exactly as written.
+    Raw,
+    /// The normalized environment spec.
+    Canonical,
+}
+
+impl fmt::Display for HashMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HashMode::Raw => write!(f, "raw"),
+            HashMode::Canonical => write!(f, "canonical"),
+        }
+    }
+}
+
+impl FromStr for HashMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<HashMode, String> {
+        match s {
+            "raw" => Ok(HashMode::Raw),
+            "canonical" => Ok(HashMode::Canonical),
+            _ => Err(format!("unknown hash mode {:?}", s)),
+        }
+    }
+}
+
+/// The digest algorithm of a depfile hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    /// Only used to verify old lockfiles.
+    Sha1,
+    /// Used for every new lockfile.
+    #[default]
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn digest(self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha1 => {
+                let mut m = sha1::Sha1::new();
+                m.update(data);
+                m.digest().to_string()
+            }
+            HashAlgorithm::Sha256 => Sha256::digest(data)
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HashAlgorithm::Sha1 => write!(f, "sha1"),
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<HashAlgorithm, String> {
+        match s {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(format!("unknown hash algorithm {:?}", s)),
+        }
+    }
+}
+
+/// A depfile hash as recorded on the `# ENVHASH:` line of a lockfile.
+///
+/// Hashes are written as `[canonical:]<algorithm>:<hex>`, e.g. `sha256:9f86d0...`.  An
+/// untagged digest is a raw SHA-1 hash from an older lockfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvHash {
+    pub mode: HashMode,
+    pub algorithm: HashAlgorithm,
+    pub digest: String,
+}
+
+impl EnvHash {
+    pub fn parse(s: &str) -> EnvHash {
+        let (mode, rest) = match s.strip_prefix("canonical:") {
+            Some(rest) => (HashMode::Canonical, rest),
+            None => (HashMode::Raw, s),
+        };
+        let (algorithm, digest) = match rest.find(':') {
+            Some(idx) => match rest[..idx].parse() {
+                Ok(algorithm) => (algorithm, &rest[idx + 1..]),
+                // Leave it whole; it won't match anything.
+                Err(_) => (HashAlgorithm::Sha1, rest),
+            },
+            None => (HashAlgorithm::Sha1, rest),
+        };
+        EnvHash {
+            mode,
+            algorithm,
+            digest: digest.to_string(),
+        }
+    }
+
+    /// Is this hash written the way new lockfiles are?
+    pub fn is_current(&self) -> bool {
+        self.algorithm == HashAlgorithm::default()
+    }
+}
+
+impl fmt::Display for EnvHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.mode == HashMode::Canonical {
+            write!(f, "canonical:")?;
+        }
+        write!(f, "{}:{}", self.algorithm, self.digest)
+    }
+}
+
+/// Hash the full contents of `f`.
+///
+/// This is the `raw` mode hash.
+pub fn compute_file_hash<R: Read>(mut f: R, algorithm: HashAlgorithm) -> Result<String> {
+    let mut depfile_data = String::new();
+    f.read_to_string(&mut depfile_data)?;
+
+    // Hash the contents of the file
+    Ok(algorithm.digest(depfile_data.as_bytes()))
+}
+
+/// Hash the normalized form of `spec`.
+///
+/// This is the `canonical` mode hash.
+pub fn compute_canonical_hash(spec: &EnvSpec, algorithm: HashAlgorithm) -> String {
+    algorithm.digest(canonical_form(spec).as_bytes())
+}
+
+/// Render `spec` one fact per line, normalized and in a fixed order.
+///
+/// Channel order is kept, since it sets channel priority.  Dependencies and variables are
+/// sorted.  The prefix is left out; it says where an environment was built, not what is in it.
+pub fn canonical_form(spec: &EnvSpec) -> String {
+    let mut lines = vec![format!("name: {}", spec.name)];
+    for channel in &spec.channels {
+        lines.push(format!("channel: {}", channel));
+    }
+
+    let mut conda_deps: Vec<String> = spec
+        .conda_specs()
+        .map(|specs| {
+            specs
+                .iter()
+                .map(|ms| {
+                    let mut dep = ms.url.clone().unwrap_or_else(|| ms.to_string());
+                    if let Some(ref md5) = ms.md5 {
+                        dep.push_str(&format!(" md5={}", md5));
+                    }
+                    if let Some(ref sha256) = ms.sha256 {
+                        dep.push_str(&format!(" sha256={}", sha256));
+                    }
+                    dep
+                }).collect()
+        })
+        // Specs are checked when the depfile is parsed; fall back to the text just in case.
+        .unwrap_or_else(|_| spec.conda_deps.iter().map(|d| d.trim().to_string()).collect());
+    conda_deps.sort();
+    conda_deps.dedup();
+    lines.extend(conda_deps.into_iter().map(|dep| format!("conda: {}", dep)));
+
+    let mut pip_deps: Vec<String> = spec
+        .pip_lines()
+        .map(|pip_lines| pip_lines.iter().map(canonical_pip_line).collect())
+        .unwrap_or_else(|_| spec.pip_deps.iter().map(|d| d.trim().to_string()).collect());
+    pip_deps.sort();
+    pip_deps.dedup();
+    lines.extend(pip_deps.into_iter().map(|dep| format!("pip: {}", dep)));
+
+    let mut variables: Vec<String> = spec
+        .variables
+        .iter()
+        .map(|(key, value)| format!("variable: {}={}", key, value))
+        .collect();
+    variables.sort();
+    lines.extend(variables);
+
+    let mut form = lines.join("\n");
+    form.push('\n');
+    form
+}
+
+fn canonical_pip_line(line: &PipLine) -> String {
+    let req = match *line {
+        PipLine::Requirement(ref req) => req,
+        PipLine::Include(ref path) => return format!("-r {}", path),
+        PipLine::Constraint(ref path) => return format!("-c {}", path),
+        PipLine::Option(ref option) => return option.split_whitespace().collect::<Vec<_>>().join(" "),
+    };
+
+    let mut canonical = String::new();
+    if req.editable {
+        canonical.push_str("-e ");
+    }
+    if let Some(name) = req.normalized_name() {
+        canonical.push_str(&name);
+    }
+    if !req.extras.is_empty() {
+        let mut extras: Vec<String> = req.extras.iter().map(|e| e.to_lowercase()).collect();
+        extras.sort();
+        canonical.push_str(&format!("[{}]", extras.join(",")));
+    }
+    if let Some(ref specifier) = req.specifier {
+        let mut clauses: Vec<&str> = specifier.split(',').collect();
+        clauses.sort();
+        canonical.push_str(&clauses.join(","));
+    }
+    if let Some(ref url) = req.url {
+        canonical.push_str(&format!(" @ {}", url));
+    }
+    if let Some(ref marker) = req.marker {
+        canonical.push_str(&format!("; {}", marker));
+    }
+    canonical
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_hashes() {
+        let sha1 = compute_file_hash("name: foo\n".as_bytes(), HashAlgorithm::Sha1).unwrap();
+        assert_eq!(sha1.len(), 40);
+        assert_ne!(sha1, compute_file_hash("name: bar\n".as_bytes(), HashAlgorithm::Sha1).unwrap());
+        assert_eq!(
+            compute_file_hash("abc".as_bytes(), HashAlgorithm::Sha256).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn canonical_hash_ignores_formatting() {
+        let original = EnvSpec::parse("
+name: foo
+channels:
+  - conda-forge
+  - defaults
+dependencies:
+  - python=3.6
+  - numpy>=1.15
+  - pip:
+    - requests>=2,<3
+    - Foo_Bar[b,a]
+").unwrap();
+        let reformatted = EnvSpec::parse("# My environment\r
+name: foo\r
+channels: [conda-forge, defaults]\r
+dependencies:\r
+    - numpy >=1.15  # needs the new API\r
+    - python =3.6\r
+    - pip:\r
+        - foo-bar[a, b]\r
+        - requests <3, >=2\r
+").unwrap();
+        assert_eq!(canonical_form(&original), canonical_form(&reformatted));
+        assert_eq!(
+            compute_canonical_hash(&original, HashAlgorithm::Sha256),
+            compute_canonical_hash(&reformatted, HashAlgorithm::Sha256)
+        );
+
+        let reordered_channels = EnvSpec::parse("
+name: foo
+channels: [defaults, conda-forge]
+dependencies: [python=3.6, numpy>=1.15]
+").unwrap();
+        assert_ne!(
+            compute_canonical_hash(&original, HashAlgorithm::Sha256),
+            compute_canonical_hash(&reordered_channels, HashAlgorithm::Sha256)
+        );
+    }
+
+    #[test]
+    fn env_hash_round_trip() {
+        let legacy = EnvHash::parse("0123abcd");
+        assert_eq!(legacy.mode, HashMode::Raw);
+        assert_eq!(legacy.algorithm, HashAlgorithm::Sha1);
+        assert_eq!(legacy.digest, "0123abcd");
+        assert!(!legacy.is_current());
+
+        for tagged in &["sha256:0123abcd", "canonical:sha256:0123abcd", "sha1:0123abcd"] {
+            assert_eq!(EnvHash::parse(tagged).to_string(), *tagged);
+        }
+        let canonical = EnvHash::parse("canonical:sha256:0123abcd");
+        assert_eq!(canonical.mode, HashMode::Canonical);
+        assert_eq!(canonical.algorithm, HashAlgorithm::Sha256);
+        assert_eq!(canonical.digest, "0123abcd");
+        assert!(canonical.is_current());
+    }
+}
@@ -0,0 +1,342 @@
+//! Converting lockfiles written by other tools into conda-lockfile lockfiles.
+//!
+//! Supported are `conda list --explicit` files, conda-lock's `conda-lock.yml` and pixi's
+//! `pixi.lock`.  Each platform of the foreign lockfile becomes a `deps.yml.<platform>.lock`,
+//! hashed against the depfile and validated like a freshly frozen lockfile.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use condalock::{scalar, CondaLock};
+use depfile::Depfile;
+use explicit::{ExplicitPackage, ExplicitSpec};
+use hash::HashMode;
+use lockfile::{
+    default_lockfile, write_explicit_lockfile, write_lockfile, LockFormat, StagedLockfile,
+};
+use platform::Platform;
+use validate::validate_lockfile;
+use {Error, Result};
+
+/// The format of a foreign lockfile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// `conda list --explicit` output, for a single platform.
+    Explicit,
+    CondaLock,
+    Pixi,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<ImportFormat, String> {
+        match s {
+            "explicit" => Ok(ImportFormat::Explicit),
+            "conda-lock" => Ok(ImportFormat::CondaLock),
+            "pixi" | "pixi.lock" => Ok(ImportFormat::Pixi),
+            _ => Err(format!("unknown lockfile format {:?}", s)),
+        }
+    }
+}
+
+/// The environment of one platform of a foreign lockfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedPlatform {
+    pub platform: Platform,
+    pub channels: Vec<String>,
+    pub packages: Vec<ExplicitPackage>,
+    /// Pip packages, pinned with `==`.
+    pub pip_deps: Vec<String>,
+}
+
+/// Read every platform of a foreign lockfile.
+///
+/// Explicit files are for `platform` unless they say otherwise in a `# platform:` comment.
+pub fn read_foreign(
+    data: &str,
+    format: ImportFormat,
+    platform: Platform,
+) -> ::std::result::Result<Vec<ImportedPlatform>, String> {
+    match format {
+        ImportFormat::Explicit => {
+            let explicit = ExplicitSpec::parse(data)?;
+            Ok(vec![ImportedPlatform {
+                platform: explicit.platform.unwrap_or(platform),
+                channels: explicit.lock_spec().channels,
+                packages: explicit.packages,
+                pip_deps: Vec::new(),
+            }])
+        }
+        ImportFormat::CondaLock => {
+            let conda_lock = CondaLock::parse(data)?;
+            Ok(conda_lock
+                .platforms
+                .iter()
+                .map(|&platform| ImportedPlatform {
+                    platform,
+                    channels: conda_lock.channels.clone(),
+                    packages: conda_lock.explicit(platform).packages,
+                    pip_deps: conda_lock.lock_spec(platform).pip_deps,
+                }).collect())
+        }
+        ImportFormat::Pixi => read_pixi(data),
+    }
+}
+
+/// Read the default environment of a `pixi.lock`.
+///
+/// Package entries are `conda: <url>` or `pypi: <url>` since version 6 of the format, and
+/// `kind: conda` with `url: <url>` before.
+fn read_pixi(data: &str) -> ::std::result::Result<Vec<ImportedPlatform>, String> {
+    let docs = YamlLoader::load_from_str(data).map_err(|e| e.to_string())?;
+    let doc = docs.first().ok_or_else(|| "empty lockfile".to_string())?;
+    if doc["version"].as_i64().is_none_or(|version| version < 4) {
+        return Err("unsupported pixi.lock version".to_string());
+    }
+
+    // Every package, by URL.
+    let mut packages: HashMap<String, &Yaml> = HashMap::new();
+    for package in doc["packages"].as_vec().map(Vec::as_slice).unwrap_or(&[]) {
+        let url = scalar(&package["conda"])
+            .or_else(|| scalar(&package["pypi"]))
+            .or_else(|| scalar(&package["url"]));
+        if let Some(url) = url {
+            packages.insert(url, package);
+        }
+    }
+
+    let environment = &doc["environments"]["default"];
+    if environment.is_badvalue() {
+        return Err("no default environment".to_string());
+    }
+    let channels = environment["channels"]
+        .as_vec()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|channel| scalar(&channel["url"]))
+        .map(|url| url.trim_end_matches('/').to_string())
+        .collect::<Vec<_>>();
+    let mut imported = Vec::new();
+    for (platform, entries) in environment["packages"].as_hash().into_iter().flatten() {
+        let platform = scalar(platform).ok_or_else(|| "expected a platform".to_string())?;
+        let platform = match platform.parse() {
+            Ok(platform) => platform,
+            Err(_) => {
+                warn!("Skipping {}: not a known platform", platform);
+                continue;
+            }
+        };
+        let mut environment = ImportedPlatform {
+            platform,
+            channels: channels.clone(),
+            packages: Vec::new(),
+            pip_deps: Vec::new(),
+        };
+        for entry in entries.as_vec().map(Vec::as_slice).unwrap_or(&[]) {
+            let (kind, url) = match (scalar(&entry["conda"]), scalar(&entry["pypi"])) {
+                (Some(url), _) => ("conda", url),
+                (None, Some(url)) => ("pypi", url),
+                (None, None) => {
+                    let url = scalar(&entry["url"]);
+                    let url = url.ok_or_else(|| "package without a URL".to_string())?;
+                    match packages.get(&url).and_then(|package| package["kind"].as_str()) {
+                        Some("pypi") => ("pypi", url),
+                        _ => ("conda", url),
+                    }
+                }
+            };
+            let package = packages.get(&url).cloned().unwrap_or(&Yaml::BadValue);
+            if kind == "pypi" {
+                let name = scalar(&package["name"]);
+                let version = scalar(&package["version"]);
+                match (name, version) {
+                    (Some(name), Some(version)) => {
+                        environment.pip_deps.push(format!("{}=={}", name, version))
+                    }
+                    _ => return Err(format!("no name and version for {}", url)),
+                }
+            } else {
+                let mut explicit = ExplicitPackage::parse(&url)?;
+                explicit.md5 = scalar(&package["md5"]);
+                explicit.sha256 = scalar(&package["sha256"]);
+                environment.packages.push(explicit);
+            }
+        }
+        imported.push(environment);
+    }
+    Ok(imported)
+}
+
+/// Import the foreign lockfile at `source` for `depfile_path`, writing a lockfile in `format`
+/// for each of its platforms, or only those in `platforms` if given.
+///
+/// Every platform is validated against the depfile before any lockfile is written.  Returns
+/// the lockfiles written.  Platforms this tool doesn't know are skipped, unless asked for.
+pub fn import_lockfiles(
+    depfile_path: &Path,
+    source: &Path,
+    from: ImportFormat,
+    platforms: Option<&[Platform]>,
+    format: LockFormat,
+    hash_mode: HashMode,
+) -> Result<Vec<PathBuf>> {
+    let depfile = Depfile::open(depfile_path)?;
+    let data = fs::read_to_string(source)?;
+    let default_platform = match platforms {
+        Some([platform]) => *platform,
+        _ => Platform::current()?,
+    };
+    let imported = read_foreign(&data, from, default_platform)
+        .map_err(|e| Error::BadLockfile(format!("{}: {}", source.display(), e)))?;
+    for platform in platforms.unwrap_or(&[]) {
+        if !imported.iter().any(|environment| environment.platform == *platform) {
+            let msg = format!("{} has no environment for {}", source.display(), platform);
+            return Err(Error::BadLockfile(msg));
+        }
+    }
+
+    let mut staged = Vec::new();
+    for environment in imported {
+        if platforms.is_some_and(|platforms| !platforms.contains(&environment.platform)) {
+            continue;
+        }
+        let lockfile_path = PathBuf::from(default_lockfile(environment.platform));
+        let explicit = ExplicitSpec {
+            name: Some(depfile.spec.name.clone()),
+            platform: Some(environment.platform),
+            packages: environment.packages,
+        };
+        let mut lock_spec = explicit.lock_spec();
+        lock_spec.channels = environment.channels;
+        lock_spec.pip_deps = environment.pip_deps;
+
+        let report = validate_lockfile(&depfile.spec, &lock_spec)?;
+        if !report.is_valid() {
+            return Err(Error::InvalidLockfile(lockfile_path, report));
+        }
+
+        let header = depfile.lock_header(hash_mode)?;
+        let staged_lockfile = StagedLockfile::new(&lockfile_path)?;
+        let tmp_lockfile = File::create(staged_lockfile.path())?;
+        match format {
+            LockFormat::Env => write_lockfile(tmp_lockfile, &lock_spec, &header)?,
+            LockFormat::Explicit if lock_spec.pip_deps.is_empty() => {
+                write_explicit_lockfile(tmp_lockfile, &explicit, &header)?
+            }
+            LockFormat::Explicit => {
+                let msg = format!(
+                    "{} has pip packages for {}; import it with --format env",
+                    source.display(),
+                    environment.platform
+                );
+                return Err(Error::BadLockfile(msg));
+            }
+            LockFormat::CondaLock => {
+                let msg = "import writes one lockfile per platform".to_string();
+                return Err(Error::UnsupportedPlatform(msg));
+            }
+        }
+        staged.push((staged_lockfile, lockfile_path));
+    }
+    if staged.is_empty() {
+        let msg = format!("{} has no platforms to import", source.display());
+        return Err(Error::BadLockfile(msg));
+    }
+
+    let mut written = Vec::new();
+    for (staged_lockfile, lockfile_path) in staged {
+        staged_lockfile.persist()?;
+        written.push(lockfile_path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXI_LOCK: &str = "\
+version: 6
+environments:
+  default:
+    channels:
+    - url: https://conda.anaconda.org/conda-forge/
+    packages:
+      linux-64:
+      - conda: https://conda.anaconda.org/conda-forge/linux-64/python-3.12.0-h0_0.conda
+      - pypi: https://files.pythonhosted.org/packages/requests-2.31.0-py3-none-any.whl
+      osx-arm64:
+      - conda: https://conda.anaconda.org/conda-forge/osx-arm64/python-3.12.0-h1_0.conda
+      win-arm64:
+      - conda: https://conda.anaconda.org/conda-forge/win-arm64/python-3.12.0-h2_0.conda
+packages:
+- conda: https://conda.anaconda.org/conda-forge/linux-64/python-3.12.0-h0_0.conda
+  sha256: aaaa
+  md5: bbbb
+- conda: https://conda.anaconda.org/conda-forge/osx-arm64/python-3.12.0-h1_0.conda
+  sha256: cccc
+- pypi: https://files.pythonhosted.org/packages/requests-2.31.0-py3-none-any.whl
+  name: requests
+  version: 2.31.0
+";
+
+    const PIXI_LOCK_V4: &str = "\
+version: 4
+environments:
+  default:
+    channels:
+    - url: https://conda.anaconda.org/conda-forge/
+    packages:
+      linux-64:
+      - conda: https://conda.anaconda.org/conda-forge/linux-64/python-3.12.0-h0_0.conda
+packages:
+- kind: conda
+  name: python
+  version: 3.12.0
+  url: https://conda.anaconda.org/conda-forge/linux-64/python-3.12.0-h0_0.conda
+  md5: bbbb
+";
+
+    #[test]
+    fn pixi() {
+        let imported = read_foreign(PIXI_LOCK, ImportFormat::Pixi, Platform::Linux64).unwrap();
+        assert_eq!(imported.len(), 2);
+        let linux = &imported[0];
+        assert_eq!(linux.platform, Platform::Linux64);
+        assert_eq!(linux.channels, ["https://conda.anaconda.org/conda-forge"]);
+        assert_eq!(linux.packages[0].spec(), "python=3.12.0=h0_0");
+        assert_eq!(linux.packages[0].md5.as_deref(), Some("bbbb"));
+        assert_eq!(linux.pip_deps, ["requests==2.31.0"]);
+        assert_eq!(imported[1].packages[0].sha256.as_deref(), Some("cccc"));
+
+        let imported = read_foreign(PIXI_LOCK_V4, ImportFormat::Pixi, Platform::Linux64).unwrap();
+        assert_eq!(imported[0].packages[0].md5.as_deref(), Some("bbbb"));
+    }
+
+    #[test]
+    fn pixi_numeric_versions() {
+        let data = PIXI_LOCK.replace("version: 2.31.0", "version: 2.0");
+        let imported = read_foreign(&data, ImportFormat::Pixi, Platform::Linux64).unwrap();
+        assert_eq!(imported[0].pip_deps, ["requests==2.0"]);
+        let data = PIXI_LOCK.replace("version: 2.31.0", "version: 1.10");
+        let imported = read_foreign(&data, ImportFormat::Pixi, Platform::Linux64).unwrap();
+        assert_eq!(imported[0].pip_deps, ["requests==1.10"]);
+    }
+
+    #[test]
+    fn explicit_platform() {
+        let explicit = "@EXPLICIT\n\
+            https://repo.anaconda.com/pkgs/main/osx-64/python-3.6.8-h0_0.tar.bz2#abcd\n";
+        let imported = read_foreign(explicit, ImportFormat::Explicit, Platform::Osx64).unwrap();
+        assert_eq!(imported[0].platform, Platform::Osx64);
+        let explicit = format!("# platform: linux-64\n{}", explicit);
+        let imported = read_foreign(&explicit, ImportFormat::Explicit, Platform::Osx64).unwrap();
+        assert_eq!(imported[0].platform, Platform::Linux64);
+    }
+}
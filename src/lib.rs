@@ -0,0 +1,46 @@
+//! Library behind the `conda-lockfile` command line tool.
+//!
+//! A *depfile* (usually `deps.yml`) is a conda environment file listing the requested
+//! dependencies.  A *lockfile* (usually `deps.yml.<platform>.lock`) is the fully resolved
+//! environment, prefixed with a `# ENVHASH:` line recording the hash of the depfile it was
+//! frozen from.  A lockfile is stale when that hash no longer matches the depfile.
+
+extern crate glob;
+extern crate md5;
+extern crate serde_json;
+extern crate sha1;
+extern crate sha2;
+extern crate tempfile;
+extern crate yaml_rust;
+#[macro_use]
+extern crate log;
+
+pub mod conda;
+pub mod condalock;
+pub mod conda_version;
+pub mod config;
+pub mod container;
+pub mod depfile;
+pub mod diff;
+pub mod error;
+pub mod explicit;
+pub mod freeze;
+pub mod hash;
+pub mod import;
+pub mod lockfile;
+pub mod matchspec;
+pub mod outdated;
+pub mod pep440;
+pub mod pip;
+pub mod platform;
+pub mod prefix;
+pub mod repodata;
+pub mod solver;
+pub mod spec;
+pub mod update;
+pub mod validate;
+pub mod verify;
+
+pub use error::Error;
+
+pub type Result<T> = std::result::Result<T, Error>;
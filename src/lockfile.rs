@@ -0,0 +1,549 @@
+//! Reading and writing lockfiles.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glob::glob;
+use tempfile::{tempdir_in, TempDir};
+
+use condalock::{is_conda_lock, rewrite_content_hashes, CondaLock, CONDA_LOCK_FILE};
+use explicit::{is_explicit, ExplicitPackage, ExplicitSpec};
+use hash::EnvHash;
+use platform::{Platform, VirtualPackage};
+use spec::LockSpec;
+use {Error, Result};
+
+/// Marks the line of a lockfile holding the hash of the depfile it was frozen from.
+pub const SIGIL: &str = "# ENVHASH:";
+
+/// Marks the lines listing the files, besides the depfile itself, that went into the hash.
+pub const INCLUDE_SIGIL: &str = "# ENVHASH-INCLUDE:";
+
+/// Marks the lines listing the virtual packages the lockfile was frozen against.
+pub const VIRTUAL_PACKAGE_SIGIL: &str = "# VIRTUAL-PACKAGE:";
+
+/// The comment lines at the top of a lockfile, recording what it was frozen from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHeader {
+    pub hash: EnvHash,
+    /// Files referenced by the depfile, relative to its directory.
+    pub includes: Vec<PathBuf>,
+    /// The virtual packages assumed of the target machine, when they were overridden.
+    pub virtual_packages: Vec<VirtualPackage>,
+}
+
+impl LockHeader {
+    pub fn new(hash: EnvHash) -> LockHeader {
+        LockHeader {
+            hash,
+            includes: Vec::new(),
+            virtual_packages: Vec::new(),
+        }
+    }
+
+    pub fn parse(data: &str) -> Result<LockHeader> {
+        let hash = read_sigil_hash(data.as_bytes())?;
+        let includes = data
+            .lines()
+            .filter_map(|line| line.strip_prefix(INCLUDE_SIGIL))
+            .map(|line| PathBuf::from(line.trim()))
+            .collect();
+        let virtual_packages = data
+            .lines()
+            .filter_map(|line| line.strip_prefix(VIRTUAL_PACKAGE_SIGIL))
+            .filter_map(|line| VirtualPackage::parse(line.trim()))
+            .collect();
+        Ok(LockHeader {
+            hash,
+            includes,
+            virtual_packages,
+        })
+    }
+
+    pub fn emit(&self) -> String {
+        let mut header = format!("{} {}\n", SIGIL, self.hash);
+        for include in &self.includes {
+            header.push_str(&format!("{} {}\n", INCLUDE_SIGIL, include.display()));
+        }
+        for package in &self.virtual_packages {
+            header.push_str(&format!("{} {}\n", VIRTUAL_PACKAGE_SIGIL, package));
+        }
+        header
+    }
+}
+
+/// How the environment in a lockfile is written down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockFormat {
+    /// The environment file written by `conda env export`.
+    #[default]
+    Env,
+    /// The package URLs and checksums written by `conda list --explicit --md5`.
+    Explicit,
+    /// conda-lock's `conda-lock.yml`, with every platform in one file.
+    CondaLock,
+}
+
+impl fmt::Display for LockFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockFormat::Env => write!(f, "env"),
+            LockFormat::Explicit => write!(f, "explicit"),
+            LockFormat::CondaLock => write!(f, "conda-lock"),
+        }
+    }
+}
+
+impl FromStr for LockFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<LockFormat, String> {
+        match s {
+            "env" => Ok(LockFormat::Env),
+            "explicit" => Ok(LockFormat::Explicit),
+            "conda-lock" => Ok(LockFormat::CondaLock),
+            _ => Err(format!("unknown lockfile format {:?}", s)),
+        }
+    }
+}
+
+/// A lockfile: the frozen environment and the header recording the depfile it was frozen from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lockfile {
+    pub path: PathBuf,
+    pub format: LockFormat,
+    /// The environment.  For explicit lockfiles, the packages as `name=version=build`.
+    pub spec: LockSpec,
+    /// The exact conda packages of an explicit or conda-lock lockfile.  Empty for environment
+    /// files.
+    pub packages: Vec<ExplicitPackage>,
+    pub header: LockHeader,
+}
+
+impl Lockfile {
+    /// Open a lockfile.  A conda-lock lockfile is read for the current platform.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Lockfile> {
+        Lockfile::open_platform(path, Platform::current()?)
+    }
+
+    /// Open a lockfile, reading a conda-lock lockfile for `platform`.
+    pub fn open_platform<P: AsRef<Path>>(path: P, platform: Platform) -> Result<Lockfile> {
+        let path = path.as_ref();
+        let mut lock_data = String::new();
+        open_lockfile(path)?.read_to_string(&mut lock_data)?;
+        Lockfile::parse(path, &lock_data, platform)
+    }
+
+    /// Parse the contents of the lockfile at `path`, reading a conda-lock lockfile for
+    /// `platform`.
+    pub fn parse(path: &Path, lock_data: &str, platform: Platform) -> Result<Lockfile> {
+        let bad_lockfile = |e: String| Error::BadLockfile(format!("{}: {}", path.display(), e));
+        if is_conda_lock(lock_data) {
+            let conda_lock = CondaLock::parse(lock_data).map_err(bad_lockfile)?;
+            let hash = match conda_lock.content_hash.get(&platform) {
+                Some(hash) => hash.clone(),
+                None => return Err(bad_lockfile(format!("no content hash for {}", platform))),
+            };
+            return Ok(Lockfile {
+                path: path.to_path_buf(),
+                format: LockFormat::CondaLock,
+                spec: conda_lock.lock_spec(platform),
+                packages: conda_lock.explicit(platform).packages,
+                header: LockHeader::new(hash),
+            });
+        }
+        let header = LockHeader::parse(lock_data)?;
+        if is_explicit(lock_data) {
+            let explicit = ExplicitSpec::parse(lock_data).map_err(bad_lockfile)?;
+            return Ok(Lockfile {
+                path: path.to_path_buf(),
+                format: LockFormat::Explicit,
+                spec: explicit.lock_spec(),
+                packages: explicit.packages,
+                header,
+            });
+        }
+        // The sigil is a YAML comment, so the whole file parses as an environment.
+        let spec = LockSpec::parse(lock_data).map_err(|e| bad_lockfile(e.to_string()))?;
+        Ok(Lockfile {
+            path: path.to_path_buf(),
+            format: LockFormat::Env,
+            spec,
+            packages: Vec::new(),
+            header,
+        })
+    }
+}
+
+/// Write `lock_spec` to `lockfile`, preceded by its header.
+pub fn write_lockfile<W: Write>(
+    mut lockfile: W,
+    lock_spec: &LockSpec,
+    header: &LockHeader,
+) -> Result<()> {
+    info!("Writing lockfile");
+    lockfile.write_all(header.emit().as_bytes())?;
+    lockfile.write_all(lock_spec.emit().as_bytes())?;
+    info!("Successfully wrote");
+    Ok(())
+}
+
+/// Write an explicit lockfile, preceded by its header.
+pub fn write_explicit_lockfile<W: Write>(
+    mut lockfile: W,
+    explicit: &ExplicitSpec,
+    header: &LockHeader,
+) -> Result<()> {
+    lockfile.write_all(header.emit().as_bytes())?;
+    lockfile.write_all(explicit.emit().as_bytes())?;
+    Ok(())
+}
+
+/// Read the depfile hash recorded in a lockfile.
+pub fn read_sigil_hash<R: Read>(mut f: R) -> Result<EnvHash> {
+    let mut file_data = String::new();
+    f.read_to_string(&mut file_data)?;
+    let hash = file_data
+        .lines()
+        .filter_map(|line| line.strip_prefix(SIGIL))
+        .map(|line| line.trim())
+        .next();
+    match hash {
+        Some(hash) => Ok(EnvHash::parse(hash)),
+        None => Err(Error::BadLockfile("No Hashes in file".to_string())),
+    }
+}
+
+/// The depfile hashes recorded in a lockfile: one for each platform of a conda-lock lockfile.
+pub fn read_lock_hashes(path: &Path) -> Result<Vec<EnvHash>> {
+    let mut lock_data = String::new();
+    open_lockfile(path)?.read_to_string(&mut lock_data)?;
+    if is_conda_lock(&lock_data) {
+        let conda_lock = CondaLock::parse(&lock_data)
+            .map_err(|e| Error::BadLockfile(format!("{}: {}", path.display(), e)))?;
+        if conda_lock.foreign_hashes {
+            return Err(Error::BadLockfile(format!(
+                "{}: not frozen by conda-lockfile, so its hashes are conda-lock's own",
+                path.display()
+            )));
+        }
+        return Ok(conda_lock.content_hash.into_values().collect());
+    }
+    Ok(vec![LockHeader::parse(&lock_data)?.hash])
+}
+
+/// Replace the depfile hash recorded in the lockfile at `path`, leaving the rest untouched.
+///
+/// Every platform's content hash is replaced in a conda-lock lockfile.
+pub fn rewrite_sigil_hash(path: &Path, env_hash: &EnvHash) -> Result<()> {
+    let mut lock_data = String::new();
+    open_lockfile(path)?.read_to_string(&mut lock_data)?;
+    if is_conda_lock(&lock_data) {
+        let rewritten = rewrite_content_hashes(&lock_data, env_hash)
+            .map_err(|e| Error::BadLockfile(format!("{}: {}", path.display(), e)))?;
+        return replace_lockfile(path, &rewritten);
+    }
+    let mut rewritten = String::with_capacity(lock_data.len());
+    let mut found = false;
+    for line in lock_data.split_inclusive('\n') {
+        if !found && line.starts_with(SIGIL) {
+            found = true;
+            rewritten.push_str(&format!("{} {}\n", SIGIL, env_hash));
+        } else {
+            rewritten.push_str(line);
+        }
+    }
+    if !found {
+        return Err(Error::BadLockfile(format!("{}: No Hashes in file", path.display())));
+    }
+    replace_lockfile(path, &rewritten)
+}
+
+/// Replace the contents of the lockfile at `path` without ever leaving it half written.
+fn replace_lockfile(path: &Path, data: &str) -> Result<()> {
+    let staged_lockfile = StagedLockfile::new(path)?;
+    File::create(staged_lockfile.path())?.write_all(data.as_bytes())?;
+    staged_lockfile.persist()
+}
+
+/// Open a lockfile, distinguishing a missing lockfile from other I/O errors.
+pub fn open_lockfile(path: &Path) -> Result<File> {
+    File::open(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => Error::MissingLockfile(path.to_path_buf()),
+        _ => Error::Io(e),
+    })
+}
+
+/// A lockfile written next to its destination and moved into place once it is complete.
+///
+/// The staged file is created like any other, so it gets the permissions the umask allows; if
+/// it replaces an existing lockfile, it takes that file's permissions instead.
+pub struct StagedLockfile {
+    // Removed, along with anything left in it, when dropped.
+    dir: TempDir,
+    dest: PathBuf,
+}
+
+impl StagedLockfile {
+    pub fn new(dest: &Path) -> Result<StagedLockfile> {
+        let parent = match dest.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        // On the same filesystem, so moving the lockfile into place is a rename.
+        Ok(StagedLockfile {
+            dir: tempdir_in(parent)?,
+            dest: dest.to_path_buf(),
+        })
+    }
+
+    /// Where to write the lockfile.
+    pub fn path(&self) -> PathBuf {
+        let name = self.dest.file_name().unwrap_or_else(|| "lockfile".as_ref());
+        self.dir.path().join(name)
+    }
+
+    /// Move the lockfile to its destination, replacing whatever is there.
+    pub fn persist(self) -> Result<()> {
+        let path = self.path();
+        match fs::metadata(&self.dest) {
+            Ok(existing) => fs::set_permissions(&path, existing.permissions())?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+        fs::rename(&path, &self.dest)?;
+        Ok(())
+    }
+}
+
+/// The conventional lockfile name for `platform`.
+pub fn default_lockfile(platform: Platform) -> String {
+    format!("deps.yml.{}.lock", platform)
+}
+
+/// The name lockfiles for `platform` had before platforms were named by conda subdir.
+pub fn legacy_lockfile(platform: Platform) -> Option<String> {
+    platform.legacy_name().map(|name| format!("deps.yml.{}.lock", name))
+}
+
+/// The platform a conventionally named lockfile is for.
+pub fn lockfile_platform(path: &Path) -> Option<Platform> {
+    let name = path.file_name()?.to_str()?;
+    let platform = name.strip_prefix("deps.yml.")?.strip_suffix(".lock")?;
+    platform.parse().ok()
+}
+
+/// All lockfiles in the current directory, including a conda-lock lockfile.
+///
+/// Lockfiles with legacy names are included, with a warning.
+pub fn find_lockfiles() -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    if Path::new(CONDA_LOCK_FILE).exists() {
+        paths.push(PathBuf::from(CONDA_LOCK_FILE));
+    }
+    for path in glob("deps.yml.*.lock").expect("Failed to read glob pattern") {
+        let path = path.map_err(|e| io::Error::new(e.error().kind(), e.to_string()))?;
+        match lockfile_platform(&path) {
+            Some(platform) => {
+                let current = default_lockfile(platform);
+                if path != Path::new(&current) {
+                    warn!(
+                        "{} uses a legacy name; `conda-lockfile migrate` renames it to {}",
+                        path.display(),
+                        current
+                    );
+                }
+                paths.push(path);
+            }
+            None => warn!("Ignoring {}: not a known platform", path.display()),
+        }
+    }
+    Ok(paths)
+}
+
+/// Rename lockfiles in the current directory from their legacy names to subdir names.
+///
+/// Returns the renames made.  A legacy lockfile is left alone if its new name is taken.
+pub fn migrate_lockfiles() -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut renamed = Vec::new();
+    for platform in &Platform::ALL {
+        let legacy = match legacy_lockfile(*platform) {
+            Some(legacy) => PathBuf::from(legacy),
+            None => continue,
+        };
+        let current = PathBuf::from(default_lockfile(*platform));
+        if !legacy.exists() {
+            continue;
+        }
+        if current.exists() {
+            warn!("Not renaming {}: {} already exists", legacy.display(), current.display());
+            continue;
+        }
+        fs::rename(&legacy, &current)?;
+        renamed.push((legacy, current));
+    }
+    Ok(renamed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hash::{HashAlgorithm, HashMode};
+    use tempfile::tempdir;
+
+    #[test]
+    fn sigil_round_trip() {
+        let lock_spec = LockSpec::parse("name: foo\ndependencies:\n  - python=3.6").unwrap();
+        let mut data = Vec::new();
+        let header = LockHeader {
+            hash: EnvHash::parse("canonical:sha256:abc123"),
+            includes: vec![PathBuf::from("requirements.txt")],
+            virtual_packages: vec![VirtualPackage::parse("__glibc=2.17").unwrap()],
+        };
+        write_lockfile(&mut data, &lock_spec, &header).unwrap();
+        assert!(data.starts_with(
+            &b"# ENVHASH: canonical:sha256:abc123\n\
+               # ENVHASH-INCLUDE: requirements.txt\n\
+               # VIRTUAL-PACKAGE: __glibc=2.17\n"[..]
+        ));
+        assert_eq!(read_sigil_hash(&data[..]).unwrap(), header.hash);
+        let data = String::from_utf8(data).unwrap();
+        assert_eq!(LockHeader::parse(&data).unwrap(), header);
+        assert_eq!(LockSpec::parse(&data).unwrap(), lock_spec);
+    }
+
+    #[test]
+    fn missing_sigil() {
+        assert!(read_sigil_hash("name: foo\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn legacy_sigil_is_raw() {
+        let hash = read_sigil_hash("# ENVHASH: abc123\nname: foo\n".as_bytes()).unwrap();
+        assert_eq!(hash.mode, HashMode::Raw);
+        assert_eq!(hash.algorithm, HashAlgorithm::Sha1);
+        assert_eq!(hash.digest, "abc123");
+    }
+
+    #[test]
+    fn lockfile_names() {
+        assert_eq!(default_lockfile(Platform::OsxArm64), "deps.yml.osx-arm64.lock");
+        assert_eq!(legacy_lockfile(Platform::Linux64), Some("deps.yml.Linux.lock".to_string()));
+        assert_eq!(legacy_lockfile(Platform::LinuxAarch64), None);
+        let platform = |name: &str| lockfile_platform(Path::new(name));
+        assert_eq!(platform("deps.yml.linux-aarch64.lock"), Some(Platform::LinuxAarch64));
+        assert_eq!(platform("deps.yml.Darwin.lock"), Some(Platform::Osx64));
+        assert_eq!(platform("deps.yml.lock"), None);
+        assert_eq!(platform("deps.yml.solaris.lock"), None);
+    }
+
+    #[test]
+    fn rewrite_sigil() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("deps.yml.lock");
+        File::create(&path)
+            .unwrap()
+            .write_all(b"# ENVHASH: abc123\nname: foo\ndependencies:\n  - python=3.6\n")
+            .unwrap();
+        let hash = EnvHash::parse("sha256:def456");
+        rewrite_sigil_hash(&path, &hash).unwrap();
+        let lockfile = Lockfile::open(&path).unwrap();
+        assert_eq!(lockfile.header.hash, hash);
+        assert_eq!(lockfile.spec.conda_deps, ["python=3.6"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn staged_lockfile_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let stage = |dest: &Path| {
+            let staged = StagedLockfile::new(dest).unwrap();
+            File::create(staged.path()).unwrap().write_all(b"name: foo\n").unwrap();
+            staged.persist().unwrap();
+        };
+
+        // New lockfiles are created like any other file.
+        let reference = dir.path().join("reference");
+        File::create(&reference).unwrap();
+        let new = dir.path().join("deps.yml.linux-64.lock");
+        stage(&new);
+        assert_eq!(mode(&new), mode(&reference));
+
+        let existing = dir.path().join("deps.yml.osx-64.lock");
+        File::create(&existing).unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o640)).unwrap();
+        stage(&existing);
+        assert_eq!(mode(&existing), 0o640);
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "name: foo\n");
+        // Nothing is left behind.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn explicit_lockfile() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("deps.yml.linux-64.lock");
+        let explicit = ExplicitSpec {
+            name: Some("foo".to_string()),
+            platform: Some(Platform::Linux64),
+            packages: vec![ExplicitPackage::parse(
+                "https://repo.anaconda.com/pkgs/main/linux-64/python-3.6.8-h0371630_0.tar.bz2#d1e2",
+            ).unwrap()],
+        };
+        let header = LockHeader::new(EnvHash::parse("sha256:abc123"));
+        write_explicit_lockfile(File::create(&path).unwrap(), &explicit, &header).unwrap();
+
+        let lockfile = Lockfile::open(&path).unwrap();
+        assert_eq!(lockfile.format, LockFormat::Explicit);
+        assert_eq!(lockfile.header, header);
+        assert_eq!(lockfile.spec.name, "foo");
+        assert_eq!(lockfile.spec.conda_deps, ["python=3.6.8=h0371630_0"]);
+        assert_eq!(lockfile.packages, explicit.packages);
+    }
+
+    #[test]
+    fn conda_lock_hashes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONDA_LOCK_FILE);
+        let package = ExplicitPackage::parse(
+            "https://conda.anaconda.org/conda-forge/linux-64/python-3.7.3-h0_0.tar.bz2#d1e2",
+        ).unwrap();
+        let mut conda_lock = CondaLock::default();
+        conda_lock.add_platform(Platform::Linux64, EnvHash::parse("abc123"), &[package]);
+        conda_lock.add_platform(Platform::Osx64, EnvHash::parse("abc123"), &[]);
+        File::create(&path).unwrap().write_all(conda_lock.emit().as_bytes()).unwrap();
+
+        let lockfile = Lockfile::open_platform(&path, Platform::Linux64).unwrap();
+        assert_eq!(lockfile.format, LockFormat::CondaLock);
+        assert_eq!(lockfile.spec.conda_deps, ["python=3.7.3=h0_0"]);
+        assert!(Lockfile::open_platform(&path, Platform::Win64).is_err());
+
+        let hash = EnvHash::parse("sha256:def456");
+        rewrite_sigil_hash(&path, &hash).unwrap();
+        assert_eq!(read_lock_hashes(&path).unwrap(), [hash.clone(), hash]);
+    }
+
+    #[test]
+    fn conda_lock_foreign_hashes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONDA_LOCK_FILE);
+        let mut conda_lock = CondaLock::default();
+        conda_lock.add_platform(Platform::Linux64, EnvHash::parse("sha256:abc123"), &[]);
+        let data = conda_lock.emit().replace("sha256:abc123", "abc123");
+        File::create(&path).unwrap().write_all(data.as_bytes()).unwrap();
+
+        // conda-lock's own lockfiles still install.
+        assert!(Lockfile::open_platform(&path, Platform::Linux64).is_ok());
+        match read_lock_hashes(&path) {
+            Err(Error::BadLockfile(msg)) => assert!(msg.contains("not frozen by conda-lockfile")),
+            other => panic!("expected a bad lockfile, got {:?}", other),
+        }
+    }
+}
@@ -1,73 +1,33 @@
 extern crate clap;
-extern crate glob;
-extern crate sha1;
-extern crate tempfile;
-extern crate yaml_rust;
+extern crate conda_lockfile;
 #[macro_use] extern crate log;
 extern crate simplelog;
 
-use std::collections::HashSet;
-use std::env;
-use std::error::Error;
-use std::fs::{copy, File};
-use std::io::prelude::*;
-use std::io::{Error as ioError, ErrorKind as ioErrorKind};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::str;
+use std::process;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
-use glob::glob;
-use tempfile::tempdir_in;
-use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
 use simplelog::{TermLogger, Config, LogLevelFilter};
 
-const SIGIL: &str = "# ENVHASH:";
-
-type Result<T> = std::result::Result<T, Box<Error>>;
-
-const DOCKERFILE: &str = "
-FROM debian:stretch
-
-RUN mkdir /app
-WORKDIR /app
-ENV CONDA_ROOT /var/lib/conda
-
-RUN apt-get update && \
-    apt-get install --yes bzip2 curl libc6 libc6-dev libc-dev gcc net-tools && \
-    apt-get autoclean
-
-RUN curl https://repo.continuum.io/miniconda/Miniconda3-4.5.11-Linux-x86_64.sh > miniconda.sh
-RUN bash miniconda.sh -b -f -p $CONDA_ROOT
-RUN echo 'ONE_LINE_COMMAND' > build_lockfile.sh
-
-ENTRYPOINT [\"/bin/bash\", \"./build_lockfile.sh\"]
-";
-
-const BUILD_LOCKFILE: &str = "set -e
-cd artifacts
-# We need the name of the environment for exporting the environment.
-# Unfortunately, `conda env create` doesn't return any information identifying
-# the name of the environment it created. As a workaround, provide an explicit
-# name to `conda env create` so there is no ambiguity when calling `conda env
-# export`.  This name *ought* be what is specified in `env.yml` itself.
-ENV_NAME=$(cat env_name)
-$CONDA_ROOT/bin/conda env create -f deps.yml -n $ENV_NAME
-# The prefix line includes an absolute path from inside this container.
-# Remove it to avoid confusion.
-$CONDA_ROOT/bin/conda env export -n $ENV_NAME | grep -v \"^prefix:\" > deps.yml.lock
-";
-
-fn interpolate_dockerfile() -> String {
-    let one_line_command: Vec<&str> = BUILD_LOCKFILE
-        .lines()
-        .filter(|line| !line.starts_with("#"))
-        .collect();
-    let olc = one_line_command.join(";");
-    DOCKERFILE.replace("ONE_LINE_COMMAND", &olc)
-}
-
-fn get_app(default_platform: &str) -> App {
+use conda_lockfile::conda::{create_from_lockfile, EnvTarget};
+use conda_lockfile::depfile::Depfile;
+use conda_lockfile::diff::{diff_specs, load_lockfile, DiffFormat};
+use conda_lockfile::config;
+use conda_lockfile::condalock::CONDA_LOCK_FILE;
+use conda_lockfile::freeze::{freeze_all, freeze_conda_lock, FreezeMethod, FreezeOptions};
+use conda_lockfile::hash::{HashAlgorithm, HashMode};
+use conda_lockfile::import::{import_lockfiles, ImportFormat};
+use conda_lockfile::lockfile::{
+    default_lockfile, find_lockfiles, migrate_lockfiles, read_lock_hashes, rewrite_sigil_hash,
+    LockFormat, Lockfile,
+};
+use conda_lockfile::outdated::{outdated_packages, render_outdated, OutdatedFormat};
+use conda_lockfile::platform::{get_platform, Platform};
+use conda_lockfile::update::{update_lockfile, Hold};
+use conda_lockfile::verify::{reinstall_corrupted, verify_prefix};
+use conda_lockfile::{Error, Result};
+
+fn get_app(default_platform: &str) -> App<'_, '_> {
     App::new("conda-lockfile")
         .arg(
             Arg::with_name("v")
@@ -83,11 +43,40 @@ fn get_app(default_platform: &str) -> App {
                         .default_value("deps.yml"),
                 ).arg(
                     Arg::with_name("lockfile")
-                        .long("lockfile"),
+                        .long("lockfile")
+                        .takes_value(true),
                 ).arg(
                     Arg::with_name("platform")
                         .long("platform")
-                        .default_value(default_platform),
+                        .multiple(true)
+                        .number_of_values(1)
+                        .default_value(default_platform)
+                        .help("A conda subdir, e.g. linux-64 or osx-arm64.  May be repeated"),
+                ).arg(
+                    Arg::with_name("hash-mode")
+                        .long("hash-mode")
+                        .possible_values(&["raw", "canonical"])
+                        .default_value("raw")
+                        .help("Hash the depfile as written, or its normalized contents"),
+                ).arg(
+                    Arg::with_name("method")
+                        .long("method")
+                        .possible_values(&["install", "solve", "native"])
+                        .default_value("install")
+                        .help(
+                            "Build the environment and export it, only run conda's solver, \
+                             or solve over local channels without conda",
+                        ),
+                ).arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["env", "explicit", "conda-lock"])
+                        .default_value("env")
+                        .help(
+                            "Write an environment file, exact package URLs and checksums \
+                             that install without a solve, or one conda-lock.yml for all \
+                             platforms",
+                        ),
                 ),
         ).subcommand(
             SubCommand::with_name("create")
@@ -99,26 +88,191 @@ fn get_app(default_platform: &str) -> App {
                     Arg::with_name("platform")
                         .long("platform")
                         .default_value(default_platform),
-                ),
+                ).args(&env_args()),
         ).subcommand(
-            SubCommand::with_name("checkenv").arg(
-                Arg::with_name("depfile")
-                    .long("depfile")
-                    .default_value("deps.yml"),
-            ),
+            SubCommand::with_name("checkenv")
+                .arg(
+                    Arg::with_name("depfile")
+                        .long("depfile")
+                        .default_value("deps.yml"),
+                ).args(&env_args()),
+        ).subcommand(
+            SubCommand::with_name("verify")
+                .about("Check the packages installed in an environment against its lockfile")
+                .arg(
+                    Arg::with_name("depfile")
+                        .long("depfile")
+                        .default_value("deps.yml"),
+                ).args(&env_args())
+                .arg(
+                    Arg::with_name("files")
+                        .long("files")
+                        .help("Also check every installed file's size and checksum"),
+                ).arg(
+                    Arg::with_name("reinstall")
+                        .long("reinstall")
+                        .requires("files")
+                        .help("Reinstall the packages with damaged files"),
+                ),
         ).subcommand(
             SubCommand::with_name("checklocks")
                 .arg(
                     Arg::with_name("depfile")
                         .long("depfile")
                         .default_value("deps.yml"),
+                ).arg(
+                    Arg::with_name("rewrite-headers")
+                        .long("rewrite-headers")
+                        .help("Rewrite the hash of up-to-date lockfiles in the current format"),
                 ).arg(Arg::with_name("lockfiles").multiple(true)),
+        ).subcommand(
+            SubCommand::with_name("migrate")
+                .about("Rename lockfiles from OS names (deps.yml.Linux.lock) to conda subdirs"),
+        ).subcommand(
+            SubCommand::with_name("import")
+                .about("Convert another tool's lockfile into a lockfile for each of its platforms")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&["explicit", "conda-lock", "pixi"])
+                        .help("The format of the lockfile to import"),
+                ).arg(Arg::with_name("file").required(true))
+                .arg(
+                    Arg::with_name("depfile")
+                        .long("depfile")
+                        .default_value("deps.yml"),
+                ).arg(
+                    Arg::with_name("platform")
+                        .long("platform")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Only import these platforms; names an explicit file's platform"),
+                ).arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["env", "explicit"])
+                        .default_value("explicit"),
+                ).arg(
+                    Arg::with_name("hash-mode")
+                        .long("hash-mode")
+                        .possible_values(&["raw", "canonical"])
+                        .default_value("raw"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("update")
+                .about("Upgrade packages in a lockfile, holding the others where they are")
+                .arg(Arg::with_name("packages").required(true).multiple(true))
+                .arg(
+                    Arg::with_name("depfile")
+                        .long("depfile")
+                        .default_value("deps.yml"),
+                ).arg(
+                    Arg::with_name("lockfile")
+                        .long("lockfile")
+                        .takes_value(true),
+                ).arg(
+                    Arg::with_name("platform")
+                        .long("platform")
+                        .default_value(default_platform),
+                ).arg(
+                    Arg::with_name("method")
+                        .long("method")
+                        .possible_values(&["install", "solve", "native"])
+                        .default_value("install"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("outdated")
+                .about("Show the newest version in the lock's channels of each locked package")
+                .arg(
+                    Arg::with_name("depfile")
+                        .long("depfile")
+                        .default_value("deps.yml"),
+                ).arg(
+                    Arg::with_name("lockfile")
+                        .long("lockfile")
+                        .takes_value(true),
+                ).arg(
+                    Arg::with_name("platform")
+                        .long("platform")
+                        .default_value(default_platform),
+                ).arg(
+                    Arg::with_name("repodata")
+                        .long("repodata")
+                        .takes_value(true)
+                        .help(
+                            "A directory mirroring remote channels, one directory per channel \
+                             holding <subdir>/repodata.json",
+                        ),
+                ).arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["table", "json"])
+                        .default_value("table"),
+                ),
+        ).subcommand(
+            SubCommand::with_name("diff")
+                .about("Show the packages that differ between two lockfiles")
+                .arg(
+                    Arg::with_name("old")
+                        .required(true)
+                        .help("A lockfile, or git:<rev>:<path> for one in git"),
+                ).arg(Arg::with_name("new").required(true))
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["text", "json", "markdown"])
+                        .default_value("text"),
+                ).arg(
+                    Arg::with_name("platform")
+                        .long("platform")
+                        .default_value(default_platform)
+                        .help("The platform to compare in conda-lock lockfiles"),
+                ),
         )
 }
 
-fn main() -> Result<()> {
+/// `--name` and `--prefix`, choosing the environment to create or check.
+fn env_args<'a, 'b>() -> [Arg<'a, 'b>; 2] {
+    [
+        Arg::with_name("name")
+            .long("name")
+            .takes_value(true)
+            .help("The environment, by name; defaults to the name in the file"),
+        Arg::with_name("prefix")
+            .long("prefix")
+            .takes_value(true)
+            .conflicts_with("name")
+            .help("The environment, by path"),
+    ]
+}
+
+/// The environment chosen with `--name` or `--prefix`, if either was given.
+fn env_target(matches: &ArgMatches) -> Option<EnvTarget> {
+    match (matches.value_of("prefix"), matches.value_of("name")) {
+        (Some(prefix), _) => Some(EnvTarget::Prefix(PathBuf::from(prefix))),
+        (None, Some(name)) => Some(EnvTarget::Name(name.to_string())),
+        (None, None) => None,
+    }
+}
+
+/// The prefix of the chosen environment, or of the one named in the depfile.
+fn depfile_env_prefix(matches: &ArgMatches, depfile: &Depfile) -> Result<PathBuf> {
+    let target = env_target(matches).unwrap_or_else(|| EnvTarget::Name(depfile.spec.name.clone()));
+    target.prefix()
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+fn run() -> Result<()> {
     let execution_platform = get_platform()?;
-    let app_m = get_app(&execution_platform).get_matches();
+    let app_m = get_app(execution_platform.subdir()).get_matches();
 
     let log_level = match app_m.occurrences_of("v") {
         0 => LogLevelFilter::Error,
@@ -126,419 +280,245 @@ fn main() -> Result<()> {
         2 => LogLevelFilter::Debug,
         _ => LogLevelFilter::Debug,
     };
-    TermLogger::init(log_level, Config::default()).unwrap();
+    // Without a terminal (e.g. under CI) there is nowhere to log to; errors are still reported.
+    if TermLogger::init(log_level, Config::default()).is_err() {
+        eprintln!("Unable to initialize logging");
+    }
     debug!("Setting log level to {}", log_level);
 
-    let val = match app_m.subcommand() {
+    match app_m.subcommand() {
         ("freeze", Some(sub_m)) => handle_freeze(sub_m),
         ("create", Some(sub_m)) => handle_create(sub_m),
         ("checkenv", Some(sub_m)) => handle_checkenv(sub_m),
+        ("verify", Some(sub_m)) => handle_verify(sub_m),
         ("checklocks", Some(sub_m)) => handle_checklocks(sub_m),
+        ("migrate", Some(_)) => handle_migrate(),
+        ("import", Some(sub_m)) => handle_import(sub_m),
+        ("update", Some(sub_m)) => handle_update(sub_m),
+        ("outdated", Some(sub_m)) => handle_outdated(sub_m),
+        ("diff", Some(sub_m)) => handle_diff(sub_m),
         _ => Ok(()),
-    };
-    val
+    }
 }
 
 fn handle_freeze(matches: &ArgMatches) -> Result<()> {
     info!("Freezing");
-    let depfile_path = matches.value_of("depfile").unwrap();
-
-    let execution_platform = get_platform()?;
-    let target_platform = matches.value_of("platform").unwrap();
-
-    // TODO: this might not be the correct path when cross-building.
-    if execution_platform == target_platform {
-        info!("Execution & target platform match");
-        let lockfile_path = extract_lockfile_path(&matches);
-        return freeze_same_platform(&depfile_path, &lockfile_path);
-    }
+    let depfile_path = Path::new(matches.value_of("depfile").unwrap());
 
-    match (execution_platform.as_str(), target_platform) {
-        ("Darwin", "Linux") => {
-            let lockfile_path = match matches.value_of("lockfile") {
-                Some(path) => path.to_string(),
-                None => format!("deps.yml.{}.lock", target_platform),
-            };
-            freeze_linux_on_mac(&depfile_path, &lockfile_path)
-        }
-        _ => {
-            let msg = format!(
-                "Unable to target {} from {}",
-                target_platform, execution_platform
-            );
-            Err(ioError::new(ioErrorKind::Other, msg).into())
+    // Explicit platforms win over the config, which wins over the current platform.
+    let config = config::Config::for_depfile(depfile_path)?;
+    let explicit_platforms = matches.occurrences_of("platform") > 0;
+    let platforms: Vec<Platform> = if !explicit_platforms && !config.platforms.is_empty() {
+        config.platforms
+    } else {
+        let mut platforms = Vec::new();
+        for platform in matches.values_of("platform").unwrap() {
+            platforms.push(platform.parse()?);
         }
-    }
-}
+        platforms
+    };
 
-fn freeze_same_platform(depfile_path: &str, lockfile_path: &str) -> Result<()> {
-    debug!("Freezing");
-    let (env_name, env_hash) = read_env_name_and_hash(&depfile_path)?;
-
-    let conda_path = find_conda()?;
-    // Create the environment, but use a name that is unlikely to clobber anything pre-existing.
-    let tmp_name = "___conda_lockfile_temp".to_string();
-    Command::new(&conda_path)
-        .args(&[
-            "env",
-            "create",
-            "-f",
-            &depfile_path,
-            "-n",
-            &tmp_name,
-            "--force",
-        ]).output()?;
-    info!("Made new env new env");
-
-    // Read the env create by `conda create`.
-    debug!("Reading env");
-    let output = Command::new(&conda_path)
-        .args(&["env", "export", "-n", &tmp_name])
-        .output()?;
-    let lock_data = str::from_utf8(&output.stdout)?;
-    debug!("Env data:\n{}", lock_data);
-
-    // Replace the temporary env name with the real one.
-    // Also drop the prefix field.  It is irrelevant.
-    let mut docs = YamlLoader::load_from_str(lock_data)?;
-    let doc = docs.remove(0);
-    let mut data_hash = doc.into_hash().unwrap();
-    data_hash.insert(Yaml::from_str("name"), Yaml::from_str(&env_name));
-    data_hash.remove(&Yaml::from_str("prefix"));
-    let lock_spec = Yaml::Hash(data_hash);
-
-    info!("Writing to {}", lockfile_path);
-    let lockfile = File::create(lockfile_path)?;
-    write_lockfile(lockfile, &lock_spec, &env_hash)?;
-    Ok(())
-}
+    let options = FreezeOptions {
+        hash_mode: matches.value_of("hash-mode").unwrap().parse::<HashMode>().unwrap(),
+        method: matches.value_of("method").unwrap().parse::<FreezeMethod>().unwrap(),
+        format: matches.value_of("format").unwrap().parse::<LockFormat>().unwrap(),
+        virtual_packages: config.virtual_packages,
+    };
 
-fn write_lockfile<W: Write>(mut lockfile: W, lock_spec: &Yaml, env_hash: &str) -> Result<()> {
-    info!("Writing lockfile");
-    let mut serialized_data = String::new();
-    {
-        let mut emitter = YamlEmitter::new(&mut serialized_data);
-        emitter.dump(&lock_spec)?;
+    let lockfile = matches.value_of("lockfile");
+    if options.format == LockFormat::CondaLock {
+        // Every platform goes in the one file.
+        let lockfile_path = Path::new(lockfile.unwrap_or(CONDA_LOCK_FILE));
+        return freeze_conda_lock(depfile_path, &platforms, lockfile_path, &options);
     }
-
-    let env_hash_line = format!("{} {}\n", SIGIL, env_hash);
-    lockfile.write_all(env_hash_line.as_bytes())?;
-    lockfile.write_all(serialized_data.as_bytes())?;
-    info!("Successfully wrote");
-    Ok(())
-}
-
-fn read_env_name_and_hash(depfile_path: &str) -> Result<(String, String)> {
-    let depfile = File::open(&depfile_path)?;
-    let env_hash = compute_file_hash(depfile)?;
-
-    let depfile2 = File::open(depfile_path)?;
-    let env_spec = read_conda_yaml_data(depfile2)?;
-    let env_name = env_spec["name"].as_str().unwrap();
-    Ok((env_name.to_string(), env_hash))
+    if lockfile.is_some() && platforms.len() > 1 {
+        let msg = "--lockfile can only be used when freezing a single platform".to_string();
+        return Err(Error::Usage(msg));
+    }
+    let targets: Vec<(Platform, PathBuf)> = platforms
+        .into_iter()
+        .map(|platform| {
+            let lockfile_path = match lockfile {
+                Some(path) => PathBuf::from(path),
+                None => PathBuf::from(default_lockfile(platform)),
+            };
+            (platform, lockfile_path)
+        }).collect();
+    freeze_all(depfile_path, &targets, &options)
 }
 
-fn freeze_linux_on_mac(depfile_path: &str, lockfile_path: &str) -> Result<()> {
-    info!("Freezing Linux on mac");
-    let (env_name, env_hash) = read_env_name_and_hash(&depfile_path)?;
-
-    // The only way to know what should be in an environment is to build it and document what
-    // dependencies showed up.  We do this in a docker container to ensure isolation, and to allow
-    // us to build lockfiles on mac.
-    let img_name = build_container();
-    info!("Make container {}", img_name);
-    let tmpdir = tempdir_in("/tmp/")?;
-    let tmpdir_path = tmpdir.path();
-
-    // put depfile into tmpdir
-    {
-        info!("Copying depsfile");
-        let dest = tmpdir_path.join("deps.yml");
-        copy(depfile_path, dest)?;
-        let mut envname_file = File::create(tmpdir_path.join("env_name"))?;
-        envname_file.write_all(env_name.as_bytes())?;
+fn handle_create(matches: &ArgMatches) -> Result<()> {
+    if cfg!(target_os = "windows") {
+        return Err(Error::UnsupportedPlatform("Unsupported os".to_string()));
     }
 
-    // run container
-    info!("Running container");
-    run_container(&tmpdir_path, &img_name)?;
-    info!("Container completed");
-
-    let mut depsfile_data = String::new();
-    {
-        debug!("reading lockfile");
-        let dest = tmpdir_path.join("deps.yml.lock");
-        let mut depsfile = File::open(dest)?;
-        depsfile.read_to_string(&mut depsfile_data)?;
-    }
+    let lockfile_path = Path::new(matches.value_of("lockfile").unwrap());
+    let platform = matches.value_of("platform").unwrap().parse::<Platform>()?;
+    create_from_lockfile(lockfile_path, platform, env_target(matches))
+}
 
-    // Read the generated lockfile.
-    let mut tmp_lockfile = File::open(tmpdir_path.join("deps.yml.lock"))?;
-    let mut tmp_lockfile_data = String::new();
-    tmp_lockfile.read_to_string(&mut tmp_lockfile_data)?;
+fn handle_checkenv(matches: &ArgMatches) -> Result<()> {
+    // Get the data from the depfile.
+    let depfile = Depfile::open(matches.value_of("depfile").unwrap())?;
+    info!("env name: {}", depfile.spec.name);
 
-    // Validation
-    if !lockfile_is_valid(&depsfile_data, &tmp_lockfile_data) {
-        return Err(ioError::new(ioErrorKind::Other, "Invalid lockfile").into());
-    }
+    let lockfile_path = depfile_env_prefix(matches, &depfile)?.join("deps.yml.lock");
+    info!("lockfile_path: {}", lockfile_path.display());
 
-    // Write valid lockfile & include hash
-    info!("Writing lockfile {}", lockfile_path);
-    {
-        let mut lockfile = File::create(lockfile_path)?;
-        let env_hash_line = format!("{} {}\n", SIGIL, env_hash);
-        lockfile.write_all(env_hash_line.as_bytes())?;
-        lockfile.write_all(tmp_lockfile_data.as_bytes())?;
+    // Hash the depfile the same way the lockfile's hashes were computed.
+    for found_hash in read_lock_hashes(&lockfile_path)? {
+        let expected_hash = depfile.hash_like(&found_hash)?;
+        if found_hash != expected_hash {
+            error!("Hashes do not match (expected, found): {} {}", expected_hash, found_hash);
+            return Err(Error::StaleLock(vec![lockfile_path]));
+        }
     }
     Ok(())
 }
 
-fn build_container() -> String {
-    info!("Building container");
-    let image_name = "lock_file_maker".to_string();
-    let dockerfile = interpolate_dockerfile();
-    let mut docker_build = Command::new("docker")
-        .args(&["build", "-t", &image_name, "-"])
-        .stdin(Stdio::piped())
-        .spawn()
-        .unwrap();
-
-    let _ = docker_build.stdin.take().unwrap().write_all(dockerfile.as_bytes());
-    docker_build.wait().unwrap();
-    image_name
-}
-
-fn run_container(dir: &Path, img_name: &str) -> Result<()> {
-    let vol_mount = format!("{}:/app/artifacts", dir.to_str().unwrap());
-    let output = Command::new("docker")
-        .args(&["run", "-v", &vol_mount, "-t", img_name])
-        .output()?;
-    let msg = std::str::from_utf8(&(output.stdout))?;
-    debug!("{}", msg);
-    Ok(())
-}
-
-fn lockfile_is_valid(depsfile_data: &str, lockfile_data: &str) -> bool {
-    let deps_docs = YamlLoader::load_from_str(depsfile_data).unwrap();
-    let deps_yaml = &deps_docs[0];
-    let (requested_conda, requested_pip) = get_deps(&deps_yaml);
-
-    let lock_docs = YamlLoader::load_from_str(lockfile_data).unwrap();
-    let lock_yaml = &lock_docs[0];
-    let (found_conda, found_pip) = get_deps(&lock_yaml);
-
-    // Should probaby do some error reporting if this fails.
-    found_conda.is_superset(&requested_conda) && found_pip.is_superset(&requested_pip)
-}
-
-fn get_deps(doc: &Yaml) -> (HashSet<&str>, HashSet<&str>) {
-    let mut pip_deps = HashSet::new();
-    let mut conda_deps = HashSet::new();
-    let deps = doc["dependencies"].as_vec().unwrap();
-    for d in deps.iter() {
-        match d.as_str() {
-            Some(conda_dep) => {
-                conda_deps.insert(conda_dep);
-                continue;
-            }
-            None => {}
-        };
-        match d.as_vec() {
-            Some(pips) => {
-                pip_deps.extend(pips.iter().filter_map(|pip| pip.as_str()));
-                continue;
-            }
-            None => {}
-        };
+fn handle_verify(matches: &ArgMatches) -> Result<()> {
+    let depfile = Depfile::open(matches.value_of("depfile").unwrap())?;
+    let prefix = depfile_env_prefix(matches, &depfile)?;
+    info!("prefix: {}", prefix.display());
+
+    let check_files = matches.is_present("files");
+    let mut report = verify_prefix(&prefix, check_files)?;
+    if matches.is_present("reinstall") && !report.corrupted.is_empty() {
+        reinstall_corrupted(&prefix, &report.corrupted)?;
+        for package in &report.corrupted {
+            println!("Reinstalled {}", package.record.describe());
+        }
+        report = verify_prefix(&prefix, check_files)?;
+    }
+    if report.is_clean() {
+        Ok(())
+    } else {
+        Err(Error::EnvMismatch(prefix, Box::new(report)))
     }
-    let conda_deps = only_pkg_names(conda_deps);
-    let pip_deps = only_pkg_names(pip_deps);
-
-    (conda_deps, pip_deps)
 }
 
-// TODO: make this iterable
-fn only_pkg_names(deps: HashSet<&str>) -> HashSet<&str> {
-    deps.iter()
-        .filter_map(|dep| dep.split("=").nth(0))
-        .collect()
-}
+fn handle_checklocks(matches: &ArgMatches) -> Result<()> {
+    let depfile_path = matches.value_of("depfile").unwrap();
+    let depfile = Depfile::open(depfile_path)?;
+    let rewrite_headers = matches.is_present("rewrite-headers");
 
-fn extract_lockfile_path(matches: &ArgMatches) -> String {
-    match matches.value_of("lockfile") {
-        Some(path) => path.to_string(),
-        None => default_lockfile(),
-    }
-}
+    let lockfiles = match matches.values_of("lockfiles") {
+        Some(files) => files.map(PathBuf::from).collect(),
+        None => find_lockfiles()?,
+    };
 
-fn default_lockfile() -> String {
-    match get_platform() {
-        Ok(platform) => format!("deps.yml.{}.lock", platform),
-        Err(_) => "".to_string(),
+    let mut stale = Vec::new();
+    for lockfile_path in lockfiles {
+        // A conda-lock lockfile has a hash for each platform.
+        let found_hashes = read_lock_hashes(&lockfile_path)?;
+        let mut lockfile_stale = false;
+        for found_hash in &found_hashes {
+            let expected_hash = depfile.hash_like(found_hash)?;
+            if *found_hash != expected_hash {
+                error!(
+                    "Hashes do not match {:?}, {:?}",
+                    depfile_path, lockfile_path
+                );
+                error!("lock    hash: {}", found_hash);
+                error!("depfile hash: {}", expected_hash);
+                lockfile_stale = true;
+            }
+        }
+        if lockfile_stale {
+            stale.push(lockfile_path);
+        } else if rewrite_headers && found_hashes.iter().any(|hash| !hash.is_current()) {
+            let current_hash = depfile.hash(found_hashes[0].mode, HashAlgorithm::default())?;
+            info!("Rewriting {} hash to {}", lockfile_path.display(), current_hash);
+            rewrite_sigil_hash(&lockfile_path, &current_hash)?;
+        }
     }
-}
-
-fn conda_prefix(name: &str) -> Result<PathBuf> {
-    let root = env::var("CONDA_ROOT")?;
-    let path: PathBuf = [&root, "envs", name].iter().collect();
-    Ok(path)
-}
 
-fn get_platform() -> Result<String> {
-    if cfg!(target_os = "linux") {
-        Ok("Linux".to_string())
-    } else if cfg!(target_os = "macos") {
-        Ok("Darwin".to_string())
+    if stale.is_empty() {
+        Ok(())
     } else {
-        Err(ioError::new(ioErrorKind::Other, "Unknown platform").into())
+        Err(Error::StaleLock(stale))
     }
 }
 
-fn find_conda() -> Result<String> {
-    match env::var("CONDA_EXE") {
-        Ok(conda) => Ok(conda),
-        Err(_) => match env::var("_CONDA_EXE") {
-            Ok(conda) => Ok(conda),
-            Err(_) => Err(ioError::new(ioErrorKind::Other, "Unable to find conda").into()),
-        },
+fn handle_migrate() -> Result<()> {
+    for (legacy, current) in migrate_lockfiles()? {
+        println!("Renamed {} to {}", legacy.display(), current.display());
     }
+    Ok(())
 }
 
-fn handle_create(matches: &ArgMatches) -> Result<()> {
-    if cfg!(target_os = "windows") {
-        return Err(ioError::new(ioErrorKind::Other, "Unsupported os").into());
+fn handle_import(matches: &ArgMatches) -> Result<()> {
+    let platforms = match matches.values_of("platform") {
+        Some(values) => Some(values.map(str::parse).collect::<Result<Vec<Platform>>>()?),
+        None => None,
+    };
+    let written = import_lockfiles(
+        Path::new(matches.value_of("depfile").unwrap()),
+        Path::new(matches.value_of("file").unwrap()),
+        matches.value_of("from").unwrap().parse::<ImportFormat>().unwrap(),
+        platforms.as_deref(),
+        matches.value_of("format").unwrap().parse::<LockFormat>().unwrap(),
+        matches.value_of("hash-mode").unwrap().parse::<HashMode>().unwrap(),
+    )?;
+    for path in written {
+        println!("Wrote {}", path.display());
     }
-
-    let lockfile_path = extract_lockfile_path(&matches);
-    let lockfile = File::open(&lockfile_path)?;
-    let doc = read_conda_yaml_data(lockfile)?;
-    let env_name = doc["name"].as_str().unwrap();
-
-    let conda_path = find_conda()?;
-    info!("conda_path {}", conda_path);
-    let output = Command::new(conda_path)
-        .args(&[
-            "env",
-            "create",
-            "--force",
-            "-q",
-            "--json",
-            "--name",
-            &env_name,
-            "-f",
-            &lockfile_path,
-        ]).output()?;
-    debug!("{:?}", output);
-
-    // Copy lockfile to constructed env
-    let mut embeded_lockfile = conda_prefix(&env_name)?;
-    embeded_lockfile.push("deps.yml.lock");
-    copy(lockfile_path, embeded_lockfile)?;
     Ok(())
 }
 
-fn read_sigil_hash<R: Read>(mut f: R) -> Result<String> {
-    let mut file_data = String::new();
-    f.read_to_string(&mut file_data)?;
-    let hash = file_data
-        .lines()
-        .filter_map(|line| {
-            if line.starts_with(SIGIL) {
-                Some(&line[10..])
-            } else {
-                None
-            }
-        }).map(|line| line.trim())
-        .nth(0);
-    match hash {
-        Some(hash) => Ok(hash.to_string()),
-        None => Err(ioError::new(ioErrorKind::Other, "No Hashes in file").into()),
+fn handle_update(matches: &ArgMatches) -> Result<()> {
+    let platform = matches.value_of("platform").unwrap().parse::<Platform>()?;
+    let lockfile_path = match matches.value_of("lockfile") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(default_lockfile(platform)),
+    };
+    let packages: Vec<String> = matches.values_of("packages").unwrap().map(String::from).collect();
+    let report = update_lockfile(
+        Path::new(matches.value_of("depfile").unwrap()),
+        &lockfile_path,
+        platform,
+        &packages,
+        matches.value_of("method").unwrap().parse::<FreezeMethod>().unwrap(),
+    )?;
+    for (hold, reason) in &report.fallbacks {
+        println!("Could not hold other packages at their {}: {}", hold, reason);
     }
-}
-
-fn compute_file_hash<R: Read>(mut f: R) -> Result<String> {
-    let mut depfile_data = String::new();
-    f.read_to_string(&mut depfile_data)?;
-
-    // Hash the contents of the file
-    let mut m = sha1::Sha1::new();
-    m.update(depfile_data.as_bytes());
-    Ok(m.digest().to_string())
-}
-
-fn read_conda_yaml_data<R: Read>(mut f: R) -> Result<Yaml> {
-    let mut depfile_data = String::new();
-    f.read_to_string(&mut depfile_data)?;
-    let mut docs = YamlLoader::load_from_str(&depfile_data).unwrap();
-    let doc = docs.remove(0); // YamlLoader loads multiple documents.  We only want the first.
-    Ok(doc)
-}
-
-fn handle_checkenv(matches: &ArgMatches) -> Result<()> {
-    // Get the data from the depfile.
-    let depfile_path = matches.value_of("depfile").unwrap();
-    let depfile = File::open(depfile_path)?;
-    let expected_hash = compute_file_hash(depfile)?;
-
-    // Extract the name of the environment
-    let depfile2 = File::open(depfile_path)?;
-    let doc = read_conda_yaml_data(depfile2)?;
-    let env_name = doc["name"].as_str().unwrap();
-    info!("env name: {}", env_name);
-
-    let root = env::var("CONDA_ROOT").unwrap();
-    let lockfile_path: PathBuf = [&root, "envs", env_name, "deps.yml.lock"].iter().collect();
-    info!("lockfile_path: {}", lockfile_path.to_str().unwrap());
-
-    let lockfile = File::open(lockfile_path)?;
-    let found_hash = read_sigil_hash(lockfile)?;
-
-    if found_hash == expected_hash {
-        Ok(())
-    } else {
-        error!("Hashes do not match (expected, found): {} {}", expected_hash, found_hash);
-        Err(ioError::new(ioErrorKind::Other, "Hashes do not match").into())
+    if report.held != Hold::Builds {
+        println!("Held other packages at: {}", report.held);
     }
+    print!("{}", report.changes.to_text());
+    if !report.unrequested.is_empty() {
+        println!("Also changed: {}", report.unrequested.join(", "));
+    }
+    Ok(())
 }
 
-fn find_lockfiles() -> Vec<PathBuf> {
-    let glob_paths: Vec<PathBuf> = glob("deps.yml.*.lock")
-        .expect("Failed to read glob pattern")
-        .map(|x| x.unwrap())
-        .collect();
-    glob_paths
-}
-
-fn handle_checklocks(matches: &ArgMatches) -> Result<()> {
-    let depfile_path = matches.value_of("depfile").unwrap();
-    let depfile = File::open(depfile_path)?;
-    let expected_hash = compute_file_hash(depfile)?;
-
-    let lockfiles = match matches.values_of("lockfiles") {
-        Some(files) => files.map(|p| PathBuf::from(p)).collect(),
-        None => find_lockfiles(),
+fn handle_outdated(matches: &ArgMatches) -> Result<()> {
+    let platform = matches.value_of("platform").unwrap().parse::<Platform>()?;
+    let lockfile_path = match matches.value_of("lockfile") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(default_lockfile(platform)),
     };
+    let depfile = Depfile::open(matches.value_of("depfile").unwrap())?;
+    let lockfile = Lockfile::open_platform(&lockfile_path, platform)?;
+    let packages = outdated_packages(
+        &depfile.spec,
+        &lockfile,
+        platform,
+        matches.value_of("repodata").map(Path::new),
+    )?;
+    let format = matches.value_of("format").unwrap().parse::<OutdatedFormat>().unwrap();
+    print!("{}", render_outdated(&packages, format));
+    Ok(())
+}
 
-    let mut success = true;
-    for lockfile_path in lockfiles {
-        let lockfile = File::open(&lockfile_path)?;
-        let found_hash = read_sigil_hash(lockfile)?;
-        if found_hash != expected_hash {
-            success = false;
-            error!(
-                "Hashes do not match {:?}, {:?}",
-                depfile_path, lockfile_path
-            );
-            error!("lock    hash: {}", found_hash);
-            error!("depfile hash: {}", expected_hash);
-        }
-    }
-
-    if success {
-        Ok(())
-    } else {
-        Err(ioError::new(ioErrorKind::Other, "Hashes do not match").into())
-    }
+fn handle_diff(matches: &ArgMatches) -> Result<()> {
+    let platform = matches.value_of("platform").unwrap().parse::<Platform>()?;
+    let old = load_lockfile(matches.value_of("old").unwrap(), platform)?;
+    let new = load_lockfile(matches.value_of("new").unwrap(), platform)?;
+    let format = matches.value_of("format").unwrap().parse::<DiffFormat>().unwrap();
+    print!("{}", diff_specs(&old, &new)?.render(format));
+    Ok(())
 }
 
 #[cfg(test)]
@@ -548,23 +528,27 @@ mod tests {
     #[test]
     fn freeze_defaults() {
         let execution_platform = "Testing-Platform";
-        let app = get_app(&execution_platform);
+        let app = get_app(execution_platform);
         let matches = app.get_matches_from(["conda-lockfile", "freeze"].iter());
         let (name, sub_matches) = matches.subcommand();
         let sub_matches = sub_matches.unwrap();
         assert_eq!(name, "freeze");
         assert_eq!(sub_matches.value_of("depfile").unwrap(), "deps.yml");
-        assert_eq!(sub_matches.value_of("lockfile").unwrap(), "deps.yml");
+        // The default lockfile depends on the target platform.
+        assert_eq!(sub_matches.value_of("lockfile"), None);
         assert_eq!(
             sub_matches.value_of("platform").unwrap(),
             execution_platform
         );
+        assert_eq!(sub_matches.value_of("hash-mode").unwrap(), "raw");
+        assert_eq!(sub_matches.value_of("method").unwrap(), "install");
+        assert_eq!(sub_matches.value_of("format").unwrap(), "env");
     }
 
     #[test]
     fn freeze_options() {
         let execution_platform = "Testing-Platform";
-        let app = get_app(&execution_platform);
+        let app = get_app(execution_platform);
         let matches = app.get_matches_from(
             [
                 "conda-lockfile",
@@ -575,6 +559,8 @@ mod tests {
                 "custom_lockfile",
                 "--platform",
                 "custom_platform",
+                "--hash-mode",
+                "canonical",
             ]
                 .iter(),
         );
@@ -584,12 +570,24 @@ mod tests {
         assert_eq!(sub_matches.value_of("depfile").unwrap(), "custom_depfile");
         assert_eq!(sub_matches.value_of("lockfile").unwrap(), "custom_lockfile");
         assert_eq!(sub_matches.value_of("platform").unwrap(), "custom_platform");
+        assert_eq!(sub_matches.value_of("hash-mode").unwrap(), "canonical");
+    }
+
+    #[test]
+    fn freeze_several_platforms() {
+        let app = get_app("Testing-Platform");
+        let matches = app.get_matches_from(
+            ["conda-lockfile", "freeze", "--platform", "linux-64", "--platform", "osx-arm64"].iter(),
+        );
+        let sub_matches = matches.subcommand_matches("freeze").unwrap();
+        let platforms: Vec<&str> = sub_matches.values_of("platform").unwrap().collect();
+        assert_eq!(platforms, ["linux-64", "osx-arm64"]);
     }
 
     #[test]
     fn checklogs_files() {
         let execution_platform = "Testing-Platform";
-        let app = get_app(&execution_platform);
+        let app = get_app(execution_platform);
         let matches = app.get_matches_from(["conda-lockfile", "checklocks", "foo", "bar"].iter());
         let (name, sub_matches) = matches.subcommand();
         let sub_matches = sub_matches.unwrap();
@@ -597,5 +595,76 @@ mod tests {
         assert_eq!(sub_matches.value_of("depfile").unwrap(), "deps.yml");
         let dep_files: Vec<&str> = sub_matches.values_of("lockfiles").unwrap().collect();
         assert_eq!(dep_files, ["foo", "bar"]);
+        assert!(!sub_matches.is_present("rewrite-headers"));
+    }
+
+    #[test]
+    fn import_options() {
+        let app = get_app("Testing-Platform");
+        let matches = app.get_matches_from(
+            ["conda-lockfile", "import", "--from", "pixi", "pixi.lock", "--platform", "linux-64"]
+                .iter(),
+        );
+        let sub_matches = matches.subcommand_matches("import").unwrap();
+        assert_eq!(sub_matches.value_of("from").unwrap(), "pixi");
+        assert_eq!(sub_matches.value_of("file").unwrap(), "pixi.lock");
+        assert_eq!(sub_matches.value_of("depfile").unwrap(), "deps.yml");
+        assert_eq!(sub_matches.value_of("format").unwrap(), "explicit");
+    }
+
+    #[test]
+    fn diff_options() {
+        let app = get_app("linux-64");
+        let matches = app.get_matches_from(
+            ["conda-lockfile", "diff", "git:HEAD:deps.yml.linux-64.lock", "deps.yml.linux-64.lock"]
+                .iter(),
+        );
+        let sub_matches = matches.subcommand_matches("diff").unwrap();
+        assert_eq!(sub_matches.value_of("old").unwrap(), "git:HEAD:deps.yml.linux-64.lock");
+        assert_eq!(sub_matches.value_of("format").unwrap(), "text");
+        assert_eq!(sub_matches.value_of("platform").unwrap(), "linux-64");
+    }
+
+    #[test]
+    fn update_options() {
+        let app = get_app("linux-64");
+        let matches = app.get_matches_from(["conda-lockfile", "update", "numpy", "pandas"].iter());
+        let sub_matches = matches.subcommand_matches("update").unwrap();
+        let packages: Vec<&str> = sub_matches.values_of("packages").unwrap().collect();
+        assert_eq!(packages, ["numpy", "pandas"]);
+        assert_eq!(sub_matches.value_of("method").unwrap(), "install");
+        assert!(sub_matches.value_of("lockfile").is_none());
+    }
+
+    #[test]
+    fn verify_options() {
+        let app = get_app("linux-64");
+        let matches = app.get_matches_from(["conda-lockfile", "verify", "--files"].iter());
+        let sub_matches = matches.subcommand_matches("verify").unwrap();
+        assert!(sub_matches.is_present("files"));
+        assert!(!sub_matches.is_present("reinstall"));
+
+        let app = get_app("linux-64");
+        let args = ["conda-lockfile", "verify", "--reinstall"];
+        assert!(app.get_matches_from_safe(args.iter()).is_err());
+    }
+
+    #[test]
+    fn env_selection() {
+        let app = get_app("linux-64");
+        let matches = app.get_matches_from(["conda-lockfile", "checkenv"].iter());
+        assert_eq!(env_target(matches.subcommand_matches("checkenv").unwrap()), None);
+
+        let app = get_app("linux-64");
+        let args = ["conda-lockfile", "create", "--prefix", "/opt/app/env"];
+        let matches = app.get_matches_from(args.iter());
+        assert_eq!(
+            env_target(matches.subcommand_matches("create").unwrap()),
+            Some(EnvTarget::Prefix(PathBuf::from("/opt/app/env")))
+        );
+
+        let app = get_app("linux-64");
+        let args = ["conda-lockfile", "checkenv", "--name", "foo", "--prefix", "/opt/app/env"];
+        assert!(app.get_matches_from_safe(args.iter()).is_err());
     }
 }
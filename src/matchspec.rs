@@ -0,0 +1,442 @@
+//! Conda match specifications, the strings listed under `dependencies:`.
+//!
+//! Supported forms include:
+//!
+//! * `numpy`, `numpy=1.15`, `numpy=1.15.4=py37_0`, `numpy==1.15.4`
+//! * `numpy>=1.15`, `python<3.8`, `numpy >=1.15,<2`
+//! * `numpy 1.15.* py37_0`
+//! * `conda-forge::numpy`, `conda-forge/linux-64::numpy`
+//! * `numpy[version='>=1.15', build=py37_0]`
+//! * `https://conda.anaconda.org/conda-forge/linux-64/numpy-1.15.4-py37_0.tar.bz2`
+
+use std::fmt;
+use std::str::FromStr;
+
+use conda_version::VersionSpec;
+
+/// The conda subdirs a channel may be split into.
+pub const KNOWN_SUBDIRS: &[&str] = &[
+    "noarch",
+    "linux-32",
+    "linux-64",
+    "linux-aarch64",
+    "linux-armv6l",
+    "linux-armv7l",
+    "linux-ppc64",
+    "linux-ppc64le",
+    "linux-s390x",
+    "osx-64",
+    "osx-arm64",
+    "win-32",
+    "win-64",
+    "win-arm64",
+    "zos-z",
+];
+
+/// Characters that start a version constraint.
+const OPERATOR_CHARS: &[char] = &['<', '>', '=', '!', '~', ',', '|'];
+
+/// A parsed conda match specification.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatchSpec {
+    pub name: String,
+    /// The version constraint, in conda's version spec syntax (e.g. `>=1.15,<2`, `1.15.*`).
+    pub version: Option<String>,
+    pub build: Option<String>,
+    pub channel: Option<String>,
+    pub subdir: Option<String>,
+    /// Checksum given by a bracket option or the fragment of a package URL.
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    /// The package URL, for specs given as one.
+    pub url: Option<String>,
+}
+
+/// Does `s` match the glob `pattern`, where `*` matches any run of characters?
+pub fn glob_matches(pattern: &str, s: &str) -> bool {
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or("");
+    if !s.starts_with(first) {
+        return false;
+    }
+    let mut rest = &s[first.len()..];
+    let pieces: Vec<&str> = pieces.collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        if i + 1 == pieces.len() {
+            return rest.ends_with(piece);
+        }
+        match rest.find(piece) {
+            Some(idx) => rest = &rest[idx + piece.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchSpecError {
+    pub spec: String,
+    pub msg: String,
+}
+
+impl fmt::Display for MatchSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid match spec {:?}: {}", self.spec, self.msg)
+    }
+}
+
+impl MatchSpec {
+    /// The parsed version constraint.
+    pub fn version_spec(&self) -> Option<VersionSpec> {
+        // Versions are checked when the spec is parsed.
+        self.version.as_ref().and_then(|v| VersionSpec::parse(v).ok())
+    }
+
+    /// The version of a locked package.  Lockfiles without builds pin `numpy=1.15.4`, which
+    /// reads as `1.15.4*`, so the `*` is dropped.
+    pub fn locked_version(&self) -> Option<&str> {
+        let version = self.version.as_ref()?;
+        Some(version.trim_end_matches('*').trim_end_matches('.'))
+    }
+
+    pub fn parse(spec: &str) -> Result<MatchSpec, MatchSpecError> {
+        let err = |msg: &str| MatchSpecError {
+            spec: spec.to_string(),
+            msg: msg.to_string(),
+        };
+
+        let trimmed = spec.trim();
+        if is_package_url(trimmed) {
+            return parse_url(trimmed).ok_or_else(|| err("unrecognized package file name"));
+        }
+
+        // Comments
+        let mut rest = match trimmed.find('#') {
+            Some(idx) => trimmed[..idx].trim(),
+            None => trimmed,
+        };
+
+        let mut ms = MatchSpec::default();
+
+        // Bracket options, e.g. `numpy[version='>=1.15', build=py37_0]`
+        let mut brackets = Vec::new();
+        if rest.ends_with(']') {
+            let open = rest.find('[').ok_or_else(|| err("unbalanced brackets"))?;
+            brackets = parse_brackets(&rest[open + 1..rest.len() - 1]).map_err(|msg| err(&msg))?;
+            rest = rest[..open].trim();
+        }
+
+        // Channel and subdir, e.g. `conda-forge/linux-64::numpy`
+        if let Some(idx) = rest.rfind("::") {
+            let (channel, subdir) = split_channel(&rest[..idx]);
+            ms.channel = channel;
+            ms.subdir = subdir;
+            rest = &rest[idx + 2..];
+        }
+
+        let rest = collapse_operator_whitespace(rest);
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || OPERATOR_CHARS.contains(&c))
+            .unwrap_or(rest.len());
+        ms.name = rest[..name_end].to_string();
+        if ms.name.is_empty() {
+            return Err(err("missing package name"));
+        }
+
+        let constraint = rest[name_end..].trim();
+        if constraint.contains(char::is_whitespace) {
+            // `numpy 1.15.* py37_0`
+            let mut parts = constraint.split_whitespace();
+            ms.version = parts.next().map(|s| s.to_string());
+            ms.build = parts.next().map(|s| s.to_string());
+            if parts.next().is_some() {
+                return Err(err("too many fields"));
+            }
+        } else if constraint.starts_with('=') && !constraint.starts_with("==") {
+            // `numpy=1.15` is a fuzzy match on 1.15.*, `numpy=1.15.4=py37_0` is exact.
+            let mut parts = constraint[1..].splitn(2, '=');
+            let version = parts.next().unwrap_or("");
+            match parts.next() {
+                Some(build) => {
+                    ms.version = Some(version.to_string());
+                    ms.build = Some(build.to_string());
+                }
+                None => ms.version = Some(fuzzy_version(version)),
+            }
+        } else if !constraint.is_empty() {
+            ms.version = Some(constraint.to_string());
+        }
+        if ms.version.as_ref().is_some_and(|v| v.is_empty()) {
+            return Err(err("empty version"));
+        }
+
+        for (key, value) in brackets {
+            match key.as_str() {
+                "version" => ms.version = Some(value),
+                "build" => ms.build = Some(value),
+                "channel" => ms.channel = Some(value),
+                "subdir" => ms.subdir = Some(value),
+                "md5" => ms.md5 = Some(value),
+                "sha256" => ms.sha256 = Some(value),
+                "url" => ms.url = Some(value),
+                // Other options (build_number, license, ...) don't affect what we check.
+                _ => {}
+            }
+        }
+        if let Some(ref version) = ms.version {
+            VersionSpec::parse(version).map_err(|e| err(&e.msg))?;
+        }
+        Ok(ms)
+    }
+}
+
+impl FromStr for MatchSpec {
+    type Err = MatchSpecError;
+
+    fn from_str(s: &str) -> Result<MatchSpec, MatchSpecError> {
+        MatchSpec::parse(s)
+    }
+}
+
+impl fmt::Display for MatchSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref channel) = self.channel {
+            match self.subdir {
+                Some(ref subdir) => write!(f, "{}/{}::", channel, subdir)?,
+                None => write!(f, "{}::", channel)?,
+            }
+        }
+        write!(f, "{}", self.name)?;
+        match (&self.version, &self.build) {
+            (Some(version), Some(build)) => write!(f, " {} {}", version, build),
+            (Some(version), None) => write!(f, " {}", version),
+            (None, Some(build)) => write!(f, " * {}", build),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+fn fuzzy_version(version: &str) -> String {
+    if version.ends_with('*') {
+        version.to_string()
+    } else {
+        format!("{}.*", version)
+    }
+}
+
+fn is_package_url(spec: &str) -> bool {
+    let spec = spec.split('#').next().unwrap_or("");
+    spec.contains("://") || spec.ends_with(".tar.bz2") || spec.ends_with(".conda")
+}
+
+/// Split `channel[/subdir]`.
+fn split_channel(channel: &str) -> (Option<String>, Option<String>) {
+    if let Some(idx) = channel.rfind('/') {
+        let subdir = &channel[idx + 1..];
+        if KNOWN_SUBDIRS.contains(&subdir) {
+            return (Some(channel[..idx].to_string()), Some(subdir.to_string()));
+        }
+    }
+    (Some(channel.to_string()), None)
+}
+
+/// Split a package file name, e.g. `numpy-1.15.4-py37_0.tar.bz2`, into name, version and build.
+pub fn split_package_filename(filename: &str) -> Option<(String, String, String)> {
+    let stem = filename
+        .strip_suffix(".tar.bz2")
+        .or_else(|| filename.strip_suffix(".conda"))?;
+    let mut parts = stem.rsplitn(3, '-');
+    let build = parts.next()?;
+    let version = parts.next()?;
+    let name = parts.next()?;
+    if name.is_empty() || version.is_empty() || build.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), version.to_string(), build.to_string()))
+}
+
+fn parse_url(spec: &str) -> Option<MatchSpec> {
+    let mut parts = spec.splitn(2, '#');
+    let url = parts.next()?;
+    let md5 = parts.next().map(|s| s.trim().to_string());
+
+    let mut components: Vec<&str> = url.rsplitn(3, '/').collect();
+    components.reverse();
+    let (channel, subdir, filename) = match components.len() {
+        3 => (Some(components[0]), Some(components[1]), components[2]),
+        _ => (None, None, *components.last()?),
+    };
+    let (name, version, build) = split_package_filename(filename)?;
+    Some(MatchSpec {
+        name,
+        version: Some(version),
+        build: Some(build),
+        channel: channel.map(|s| s.to_string()),
+        subdir: subdir.map(|s| s.to_string()),
+        md5,
+        sha256: None,
+        url: Some(url.to_string()),
+    })
+}
+
+/// Parse the inside of `[key=value, key='value']`.
+fn parse_brackets(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut options = Vec::new();
+    let mut chars = contents.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace() || *c == ',') {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+        let key: String = chars
+            .by_ref()
+            .take_while(|c| *c != '=')
+            .collect::<String>()
+            .trim()
+            .to_string();
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let value = match chars.peek() {
+            Some(&quote) if quote == '\'' || quote == '"' => {
+                chars.next();
+                let value: String = chars.by_ref().take_while(|c| *c != quote).collect();
+                value
+            }
+            _ => chars
+                .by_ref()
+                .take_while(|c| *c != ',')
+                .collect::<String>()
+                .trim()
+                .to_string(),
+        };
+        if key.is_empty() {
+            return Err("bracket option without a key".to_string());
+        }
+        options.push((key, value));
+    }
+    Ok(options)
+}
+
+/// `numpy >= 1.15 , < 2` -> `numpy >=1.15,<2`
+fn collapse_operator_whitespace(spec: &str) -> String {
+    let mut out = String::new();
+    let mut pending_space = false;
+    for c in spec.trim().chars() {
+        if c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space {
+            let after_operator = out.ends_with(|p: char| OPERATOR_CHARS.contains(&p));
+            if !after_operator {
+                out.push(' ');
+            }
+            pending_space = false;
+        }
+        out.push(c);
+    }
+    // Drop whitespace between an operator and the preceding version, e.g. `1.15 ,<2`.
+    out.replace(" ,", ",").replace(" |", "|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(spec: &str) -> MatchSpec {
+        MatchSpec::parse(spec).unwrap()
+    }
+
+    fn version(spec: &str) -> Option<String> {
+        ms(spec).version
+    }
+
+    #[test]
+    fn names() {
+        for spec in &[
+            "numpy",
+            "numpy=1.15",
+            "numpy>=1.15",
+            "numpy >=1.15,<2",
+            "numpy 1.15.* py37_0",
+            "numpy=1.15.4=py37_0",
+            "numpy==1.15.4",
+            "conda-forge::numpy",
+            "conda-forge/linux-64::numpy>=1.15",
+            "numpy[version='>=1.15']",
+            "numpy # a comment",
+            "https://conda.anaconda.org/conda-forge/linux-64/numpy-1.15.4-py37_0.tar.bz2",
+        ] {
+            assert_eq!(ms(spec).name, "numpy", "{}", spec);
+        }
+        assert_eq!(ms("python<3.8").name, "python");
+        assert_eq!(ms("scikit-learn~=0.20").name, "scikit-learn");
+    }
+
+    #[test]
+    fn locked_versions() {
+        assert_eq!(ms("numpy=1.15.4").locked_version(), Some("1.15.4"));
+        assert_eq!(ms("numpy=1.15.4=py37_0").locked_version(), Some("1.15.4"));
+        assert_eq!(ms("numpy").locked_version(), None);
+    }
+
+    #[test]
+    fn versions_and_builds() {
+        assert_eq!(version("numpy"), None);
+        assert_eq!(version("numpy=1.15"), Some("1.15.*".to_string()));
+        assert_eq!(version("numpy=1.15.*"), Some("1.15.*".to_string()));
+        assert_eq!(version("numpy>=1.15"), Some(">=1.15".to_string()));
+        assert_eq!(version("numpy >= 1.15 , <2"), Some(">=1.15,<2".to_string()));
+        assert_eq!(version("python<3.8"), Some("<3.8".to_string()));
+        assert_eq!(version("numpy[version='>=1.15']"), Some(">=1.15".to_string()));
+
+        let exact = ms("numpy=1.15.4=py37_0");
+        assert_eq!(exact.version, Some("1.15.4".to_string()));
+        assert_eq!(exact.build, Some("py37_0".to_string()));
+
+        let spaced = ms("numpy 1.15.* py37_0");
+        assert_eq!(spaced.version, Some("1.15.*".to_string()));
+        assert_eq!(spaced.build, Some("py37_0".to_string()));
+    }
+
+    #[test]
+    fn channels_and_subdirs() {
+        let spec = ms("conda-forge/linux-64::numpy>=1.15");
+        assert_eq!(spec.channel, Some("conda-forge".to_string()));
+        assert_eq!(spec.subdir, Some("linux-64".to_string()));
+
+        let spec = ms("https://conda.anaconda.org/conda-forge/osx-arm64/numpy-1.15.4-py37_0.conda#abc");
+        assert_eq!(spec.channel, Some("https://conda.anaconda.org/conda-forge".to_string()));
+        assert_eq!(spec.subdir, Some("osx-arm64".to_string()));
+        assert_eq!(spec.version, Some("1.15.4".to_string()));
+        assert_eq!(spec.build, Some("py37_0".to_string()));
+        assert_eq!(spec.md5, Some("abc".to_string()));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(ms("conda-forge::numpy>=1.15").to_string(), "conda-forge::numpy >=1.15");
+        assert_eq!(ms("numpy=1.15.4=py37_0").to_string(), "numpy 1.15.4 py37_0");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(MatchSpec::parse("").is_err());
+        assert!(MatchSpec::parse(">=1.15").is_err());
+        assert!(MatchSpec::parse("numpy 1.15 py37_0 extra").is_err());
+        assert!(MatchSpec::parse("numpy>=1.15,").is_err());
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_matches("py37*", "py37_0"));
+        assert!(glob_matches("*_0", "py37_0"));
+        assert!(glob_matches("py*_*", "py37_0"));
+        assert!(glob_matches("py37_0", "py37_0"));
+        assert!(!glob_matches("py36*", "py37_0"));
+        assert!(!glob_matches("py37", "py37_0"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Comparing a lockfile's packages with the newest in its channels.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use conda_version::CondaVersion;
+use lockfile::Lockfile;
+use platform::Platform;
+use repodata::{channel_name, channel_path, default_cache_dir, Index};
+use spec::DepSpec;
+use {Error, Result};
+
+/// A locked conda package and the newest version of it available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutdatedPackage {
+    pub name: String,
+    pub locked: String,
+    /// The newest version and build in the lock's channels.  `None` if they don't have it.
+    pub latest: Option<(String, String)>,
+    /// The newest version is newer than the locked one.
+    pub outdated: bool,
+    /// The newest version satisfies the depfile's constraints on the package.
+    pub allowed: bool,
+}
+
+/// How `outdated` prints its report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutdatedFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for OutdatedFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<OutdatedFormat, String> {
+        match s {
+            "table" => Ok(OutdatedFormat::Table),
+            "json" => Ok(OutdatedFormat::Json),
+            _ => Err(format!("unknown report format {:?}", s)),
+        }
+    }
+}
+
+/// The local directory to read `channel` from: the channel itself if it's local, otherwise
+/// its mirror in `mirror_dir`, named after the channel's last path segment.
+fn local_channel(channel: &str, mirror_dir: Option<&Path>) -> Result<String> {
+    if channel_path(channel).is_some() {
+        return Ok(channel.to_string());
+    }
+    match mirror_dir {
+        Some(dir) => Ok(dir.join(channel_name(channel)).display().to_string()),
+        None => {
+            let msg = format!("{} is not a local channel and no mirror was given", channel);
+            Err(Error::BadRepodata(msg))
+        }
+    }
+}
+
+/// Check every conda package locked for `platform` against the newest in the lock's channels.
+///
+/// Repodata is read from local channels, or from a mirror of each remote channel in
+/// `mirror_dir`, so nothing is downloaded.  Pip packages aren't in repodata and are skipped.
+pub fn outdated_packages(
+    depfile: &DepSpec,
+    lockfile: &Lockfile,
+    platform: Platform,
+    mirror_dir: Option<&Path>,
+) -> Result<Vec<OutdatedPackage>> {
+    let bad_lockfile =
+        |e: String| Error::BadLockfile(format!("{}: {}", lockfile.path.display(), e));
+    let channels = lockfile
+        .spec
+        .channels
+        .iter()
+        .map(|channel| local_channel(channel, mirror_dir))
+        .collect::<Result<Vec<String>>>()?;
+    if channels.is_empty() {
+        return Err(bad_lockfile("no channels to check against".to_string()));
+    }
+    let cache_dir = default_cache_dir();
+    let index = Index::load(&channels, platform, cache_dir.as_deref())?;
+    let constraints = depfile.conda_specs().map_err(|e| Error::BadDepfile(e.to_string()))?;
+
+    let mut packages = Vec::new();
+    for locked in lockfile.spec.conda_specs().map_err(|e| bad_lockfile(e.to_string()))? {
+        let version = locked.locked_version().unwrap_or("").to_string();
+        let latest = index.latest(&locked.name);
+        let outdated = latest.is_some_and(|latest| {
+            let parse = |v: &str| CondaVersion::parse(v).ok();
+            parse(&latest.version) > parse(&version)
+        });
+        let allowed = latest.is_some_and(|latest| {
+            constraints
+                .iter()
+                .filter(|spec| spec.name == locked.name)
+                .all(|spec| latest.matches(spec))
+        });
+        packages.push(OutdatedPackage {
+            name: locked.name,
+            locked: version,
+            latest: latest.map(|latest| (latest.version.clone(), latest.build.clone())),
+            outdated,
+            allowed,
+        });
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packages)
+}
+
+/// Render the report as an aligned table or a JSON array.
+pub fn render_outdated(packages: &[OutdatedPackage], format: OutdatedFormat) -> String {
+    match format {
+        OutdatedFormat::Table => outdated_table(packages),
+        OutdatedFormat::Json => format!("{}\n", outdated_json(packages)),
+    }
+}
+
+fn outdated_table(packages: &[OutdatedPackage]) -> String {
+    let mut rows = vec![[
+        "Package".to_string(),
+        "Locked".to_string(),
+        "Latest".to_string(),
+        "Depfile".to_string(),
+    ]];
+    for package in packages {
+        let (latest, depfile) = match package.latest {
+            Some((ref version, ref build)) => {
+                let depfile = if package.allowed { "allows" } else { "excludes" };
+                (format!("{} {}", version, build), depfile.to_string())
+            }
+            None => ("-".to_string(), "-".to_string()),
+        };
+        rows.push([package.name.clone(), package.locked.clone(), latest, depfile]);
+    }
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut out = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn outdated_json(packages: &[OutdatedPackage]) -> Value {
+    let packages = packages
+        .iter()
+        .map(|package| {
+            let mut entry = Map::new();
+            entry.insert("name".to_string(), Value::from(package.name.as_str()));
+            entry.insert("locked".to_string(), Value::from(package.locked.as_str()));
+            let (version, build) = match package.latest {
+                Some((ref version, ref build)) => (Some(version.clone()), Some(build.clone())),
+                None => (None, None),
+            };
+            entry.insert("latest".to_string(), Value::from(version));
+            entry.insert("latest_build".to_string(), Value::from(build));
+            entry.insert("outdated".to_string(), Value::from(package.outdated));
+            entry.insert("allowed".to_string(), Value::from(package.allowed));
+            Value::Object(entry)
+        }).collect();
+    Value::Array(packages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn mirrored_channel() {
+        let dir = tempdir().unwrap();
+        let subdir = dir.path().join("main").join("linux-64");
+        create_dir_all(&subdir).unwrap();
+        write(
+            subdir.join("repodata.json"),
+            r#"{"packages": {
+              "numpy-1.15.4-py37_0.tar.bz2":
+                {"name": "numpy", "version": "1.15.4", "build": "py37_0", "build_number": 0},
+              "numpy-1.16.0-py37_0.tar.bz2":
+                {"name": "numpy", "version": "1.16.0", "build": "py37_0", "build_number": 0},
+              "python-3.7.3-h0_0.tar.bz2":
+                {"name": "python", "version": "3.7.3", "build": "h0_0", "build_number": 0}
+            }}"#,
+        ).unwrap();
+        let lockfile = Lockfile::parse(
+            Path::new("deps.yml.linux-64.lock"),
+            "# ENVHASH: 1234\nname: foo\nchannels:\n  - https://repo.anaconda.com/pkgs/main\n\
+             dependencies:\n  - numpy=1.15.4=py37_0\n  - python=3.7.3=h0_0\n  - zlib=1.2.11=h0_0\n",
+            Platform::Linux64,
+        ).unwrap();
+        let depfile = DepSpec::parse("name: foo\ndependencies:\n  - numpy <1.16\n").unwrap();
+
+        assert!(outdated_packages(&depfile, &lockfile, Platform::Linux64, None).is_err());
+        let packages =
+            outdated_packages(&depfile, &lockfile, Platform::Linux64, Some(dir.path())).unwrap();
+        let numpy = &packages[0];
+        assert_eq!(numpy.latest, Some(("1.16.0".to_string(), "py37_0".to_string())));
+        assert!(numpy.outdated);
+        assert!(!numpy.allowed);
+        assert!(!packages[1].outdated);
+        assert!(packages[1].allowed);
+        assert_eq!(packages[2].latest, None);
+
+        assert_eq!(
+            render_outdated(&packages, OutdatedFormat::Table),
+            "Package  Locked  Latest         Depfile\n\
+             numpy    1.15.4  1.16.0 py37_0  excludes\n\
+             python   3.7.3   3.7.3 h0_0     allows\n\
+             zlib     1.2.11  -              -\n"
+        );
+        let json = outdated_json(&packages);
+        assert_eq!(json[0]["latest"], "1.16.0");
+        assert_eq!(json[0]["allowed"], false);
+        assert_eq!(json[2]["latest"], Value::Null);
+    }
+}
@@ -0,0 +1,466 @@
+//! PEP 440 versions and version specifiers, as used by pip.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionError {
+    pub version: String,
+    pub msg: String,
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid version {:?}: {}", self.version, self.msg)
+    }
+}
+
+/// The kind of a pre-release, in sort order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PreKind {
+    Alpha,
+    Beta,
+    Rc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum LocalPart {
+    // Declaration order is sort order: letters sort before numbers.
+    Str(String),
+    Num(u64),
+}
+
+/// A PEP 440 version.
+#[derive(Debug, Clone)]
+pub struct Version {
+    source: String,
+    epoch: u64,
+    release: Vec<u64>,
+    pre: Option<(PreKind, u64)>,
+    post: Option<u64>,
+    dev: Option<u64>,
+    local: Vec<LocalPart>,
+}
+
+/// Consumes a version string from the front.
+struct Scanner<'a> {
+    s: &'a str,
+}
+
+impl Scanner<'_> {
+    fn eat(&mut self, prefix: &str) -> bool {
+        if self.s.starts_with(prefix) {
+            self.s = &self.s[prefix.len()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_separator(&mut self) -> bool {
+        self.eat(".") || self.eat("-") || self.eat("_")
+    }
+
+    fn number(&mut self) -> Option<u64> {
+        let end = self.s.find(|c: char| !c.is_ascii_digit()).unwrap_or(self.s.len());
+        if end == 0 {
+            return None;
+        }
+        let n = self.s[..end].parse().unwrap_or(u64::MAX);
+        self.s = &self.s[end..];
+        Some(n)
+    }
+
+    /// Try each spelling in turn, longest first, optionally preceded by a separator.
+    fn eat_word(&mut self, words: &[&str]) -> bool {
+        let saved = self.s;
+        self.eat_separator();
+        if words.iter().any(|word| self.eat(word)) {
+            return true;
+        }
+        self.s = saved;
+        false
+    }
+
+    /// The number following a pre/post/dev marker, which may be implicit.
+    fn implicit_number(&mut self) -> u64 {
+        let saved = self.s;
+        self.eat_separator();
+        match self.number() {
+            Some(n) => n,
+            None => {
+                self.s = saved;
+                0
+            }
+        }
+    }
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Result<Version, VersionError> {
+        let err = |msg: &str| VersionError {
+            version: version.to_string(),
+            msg: msg.to_string(),
+        };
+        let lowered = version.trim().to_lowercase();
+        let mut scanner = Scanner { s: &lowered };
+        scanner.eat("v");
+
+        let mut epoch = 0;
+        let mut release = vec![scanner.number().ok_or_else(|| err("expected a release number"))?];
+        if scanner.eat("!") {
+            epoch = release[0];
+            release = vec![scanner.number().ok_or_else(|| err("expected a release number"))?];
+        }
+        while scanner.s.starts_with('.') && scanner.s[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            scanner.eat(".");
+            release.extend(scanner.number());
+        }
+
+        let mut pre = None;
+        let kinds = [
+            (PreKind::Alpha, &["alpha", "a"][..]),
+            (PreKind::Beta, &["beta", "b"][..]),
+            (PreKind::Rc, &["preview", "pre", "rc", "c"][..]),
+        ];
+        for &(kind, words) in &kinds {
+            if scanner.eat_word(words) {
+                pre = Some((kind, scanner.implicit_number()));
+                break;
+            }
+        }
+
+        let mut post = None;
+        if scanner.eat_word(&["post", "rev", "r"]) {
+            post = Some(scanner.implicit_number());
+        } else if scanner.s.starts_with('-') && scanner.s[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            // Implicit post release: 1.0-1
+            scanner.eat("-");
+            post = scanner.number();
+        }
+
+        let mut dev = None;
+        if scanner.eat_word(&["dev"]) {
+            dev = Some(scanner.implicit_number());
+        }
+
+        let mut local = Vec::new();
+        if scanner.eat("+") {
+            for part in scanner.s.split(['.', '-', '_']) {
+                if part.is_empty() || !part.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(err("invalid local version"));
+                }
+                local.push(match part.parse() {
+                    Ok(n) => LocalPart::Num(n),
+                    Err(_) => LocalPart::Str(part.to_string()),
+                });
+            }
+            scanner.s = "";
+        }
+
+        if !scanner.s.is_empty() {
+            return Err(err(&format!("unexpected {:?}", scanner.s)));
+        }
+        Ok(Version {
+            source: version.to_string(),
+            epoch,
+            release,
+            pre,
+            post,
+            dev,
+            local,
+        })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        self.pre.is_some() || self.dev.is_some()
+    }
+
+    /// The release segment with trailing zeros removed, so 1.0 == 1.0.0.
+    fn trimmed_release(&self) -> &[u64] {
+        let len = self
+            .release
+            .iter()
+            .rposition(|&n| n != 0)
+            .map_or(0, |idx| idx + 1);
+        &self.release[..len]
+    }
+
+    /// Compare everything but the local version.
+    fn cmp_public(&self, other: &Version) -> Ordering {
+        // A dev release of a final release sorts before its pre-releases: 1.0.dev0 < 1.0a0.
+        // Option orders None first, so wrap the keys to put "no pre-release" and "no dev" last.
+        let pre_key = |v: &Version| match (v.pre, v.post, v.dev) {
+            (None, None, Some(_)) => (0, None),
+            (Some(pre), _, _) => (1, Some(pre)),
+            (None, _, _) => (2, None),
+        };
+        let dev_key = |v: &Version| match v.dev {
+            Some(dev) => (0, dev),
+            None => (1, 0),
+        };
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| self.trimmed_release().cmp(other.trimmed_release()))
+            .then_with(|| pre_key(self).cmp(&pre_key(other)))
+            .then_with(|| self.post.cmp(&other.post))
+            .then_with(|| dev_key(self).cmp(&dev_key(other)))
+    }
+
+    fn without_local(&self) -> Version {
+        Version {
+            local: Vec::new(),
+            ..self.clone()
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.cmp_public(other).then_with(|| self.local.cmp(&other.local))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for Version {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Version, VersionError> {
+        Version::parse(s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Specifier {
+    Compatible(Version),
+    Eq(Version),
+    EqPrefix(Vec<u64>, u64),
+    Ne(Version),
+    NePrefix(Vec<u64>, u64),
+    Le(Version),
+    Ge(Version),
+    Lt(Version),
+    Gt(Version),
+    Arbitrary(String),
+}
+
+/// A comma separated list of PEP 440 specifiers, e.g. `>=2,<3`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecifierSet {
+    source: String,
+    specifiers: Vec<Specifier>,
+}
+
+impl SpecifierSet {
+    pub fn parse(spec: &str) -> Result<SpecifierSet, VersionError> {
+        let err = |msg: &str| VersionError {
+            version: spec.to_string(),
+            msg: msg.to_string(),
+        };
+        let mut specifiers = Vec::new();
+        for clause in spec.split(',') {
+            let clause: String = clause.chars().filter(|c| !c.is_whitespace()).collect();
+            let operators = ["===", "~=", "==", "!=", "<=", ">=", "<", ">"];
+            let op = operators
+                .iter()
+                .find(|op| clause.starts_with(**op))
+                .ok_or_else(|| err("missing operator"))?;
+            let operand = &clause[op.len()..];
+            let version = |s: &str| Version::parse(s).map_err(|e| err(&e.msg));
+            let prefix = |s: &str| -> Result<(Vec<u64>, u64), VersionError> {
+                let v = version(s)?;
+                if v.pre.is_some() || v.post.is_some() || v.dev.is_some() || !v.local.is_empty() {
+                    return Err(err("wildcards only apply to release numbers"));
+                }
+                Ok((v.release, v.epoch))
+            };
+            specifiers.push(match *op {
+                "===" => Specifier::Arbitrary(operand.to_string()),
+                "==" | "!=" if operand.ends_with(".*") => {
+                    let (release, epoch) = prefix(&operand[..operand.len() - 2])?;
+                    if *op == "==" {
+                        Specifier::EqPrefix(release, epoch)
+                    } else {
+                        Specifier::NePrefix(release, epoch)
+                    }
+                }
+                "==" => Specifier::Eq(version(operand)?),
+                "!=" => Specifier::Ne(version(operand)?),
+                "~=" => {
+                    let v = version(operand)?;
+                    if v.release.len() < 2 {
+                        return Err(err("~= needs at least two release numbers"));
+                    }
+                    Specifier::Compatible(v)
+                }
+                "<=" => Specifier::Le(version(operand)?),
+                ">=" => Specifier::Ge(version(operand)?),
+                "<" => Specifier::Lt(version(operand)?),
+                _ => Specifier::Gt(version(operand)?),
+            });
+        }
+        Ok(SpecifierSet {
+            source: spec.to_string(),
+            specifiers,
+        })
+    }
+
+    /// Is `version` allowed by every specifier?
+    ///
+    /// Pre-releases are never excluded for being pre-releases: we check versions that are
+    /// already installed, not choose which one to install.
+    pub fn contains(&self, version: &Version) -> bool {
+        self.specifiers.iter().all(|spec| spec.contains(version))
+    }
+}
+
+fn prefix_matches(version: &Version, release: &[u64], epoch: u64) -> bool {
+    let padded = (0..release.len()).map(|i| version.release.get(i).cloned().unwrap_or(0));
+    version.epoch == epoch && padded.eq(release.iter().cloned())
+}
+
+impl Specifier {
+    fn contains(&self, v: &Version) -> bool {
+        match *self {
+            Specifier::Compatible(ref spec) => {
+                let prefix = &spec.release[..spec.release.len() - 1];
+                v >= spec && prefix_matches(v, prefix, spec.epoch)
+            }
+            // Without a local version in the specifier, local versions of the candidate are ignored.
+            Specifier::Eq(ref spec) if spec.local.is_empty() => v.without_local() == *spec,
+            Specifier::Eq(ref spec) => v == spec,
+            Specifier::Ne(ref spec) => !Specifier::Eq(spec.clone()).contains(v),
+            Specifier::EqPrefix(ref release, epoch) => prefix_matches(v, release, epoch),
+            Specifier::NePrefix(ref release, epoch) => !prefix_matches(v, release, epoch),
+            Specifier::Le(ref spec) => v.cmp_public(spec) != Ordering::Greater,
+            Specifier::Ge(ref spec) => v.cmp_public(spec) != Ordering::Less,
+            Specifier::Lt(ref spec) => {
+                // <V excludes pre-releases of V itself, unless V is a pre-release.
+                let same_release = v.trimmed_release() == spec.trimmed_release() && v.epoch == spec.epoch;
+                v.cmp_public(spec) == Ordering::Less
+                    && (spec.is_prerelease() || !(same_release && v.is_prerelease()))
+            }
+            Specifier::Gt(ref spec) => {
+                // >V excludes post-releases of V itself, unless V is a post-release.
+                let same_release = v.trimmed_release() == spec.trimmed_release() && v.epoch == spec.epoch;
+                v.cmp_public(spec) == Ordering::Greater
+                    && (spec.post.is_some() || !(same_release && v.post.is_some()))
+            }
+            Specifier::Arbitrary(ref s) => v.source.trim().eq_ignore_ascii_case(s),
+        }
+    }
+}
+
+impl fmt::Display for SpecifierSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for SpecifierSet {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<SpecifierSet, VersionError> {
+        SpecifierSet::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    fn contains(spec: &str, version: &str) -> bool {
+        SpecifierSet::parse(spec).unwrap().contains(&v(version))
+    }
+
+    #[test]
+    fn ordering() {
+        let ordered = [
+            "1.0.dev456",
+            "1.0a1",
+            "1.0a2.dev456",
+            "1.0a12.dev456",
+            "1.0a12",
+            "1.0b1.dev456",
+            "1.0b2",
+            "1.0b2.post345.dev456",
+            "1.0b2.post345",
+            "1.0rc1.dev456",
+            "1.0rc1",
+            "1.0",
+            "1.0+abc.5",
+            "1.0+abc.7",
+            "1.0+5",
+            "1.0.post456.dev34",
+            "1.0.post456",
+            "1.1.dev1",
+            "1!0.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(v("1.0"), v("1.0.0"));
+        assert_eq!(v("1.0alpha1"), v("1.0a1"));
+        assert_eq!(v("1.0-RC1"), v("1.0rc1"));
+        assert_eq!(v("1.0c1"), v("1.0rc1"));
+        assert_eq!(v("1.0-1"), v("1.0.post1"));
+        assert_eq!(v("1.0.post"), v("1.0.post0"));
+        assert_eq!(v("v2.19.1"), v("2.19.1"));
+        assert!(Version::parse("1.0 beta").is_err());
+        assert!(Version::parse("banana").is_err());
+    }
+
+    #[test]
+    fn specifiers() {
+        assert!(contains(">=2", "2.19.1"));
+        assert!(!contains(">=2", "1.9"));
+        assert!(contains(">=2,<3", "2.0"));
+        assert!(!contains(">=2,<3", "3.0"));
+        assert!(contains("==1.0", "1.0.0"));
+        assert!(contains("==1.0", "1.0+local"));
+        assert!(!contains("==1.0+other", "1.0+local"));
+        assert!(contains("==1.1.*", "1.1.post1"));
+        assert!(!contains("==1.1.*", "1.10"));
+        assert!(contains("!=1.1.*", "1.2"));
+        assert!(contains("~=2.2", "2.9"));
+        assert!(!contains("~=2.2", "3.0"));
+        assert!(contains("~=1.4.5", "1.4.9"));
+        assert!(!contains("~=1.4.5", "1.5.0"));
+        assert!(!contains("<1.0", "1.0rc1"));
+        assert!(contains("<1.0rc2", "1.0rc1"));
+        assert!(!contains(">1.0", "1.0.post1"));
+        assert!(contains(">1.0.post1", "1.0.post2"));
+        assert!(contains("===1.0", "1.0"));
+        assert!(!contains("===1.0", "1.0.0"));
+        assert!(SpecifierSet::parse("=1.0").is_err());
+        assert!(SpecifierSet::parse("~=1").is_err());
+    }
+}
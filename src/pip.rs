@@ -0,0 +1,305 @@
+//! Pip requirement lines, the entries of the `pip:` section of an environment.
+//!
+//! Each entry is a line of a pip requirements file: a PEP 508 requirement
+//! (`requests>=2`, `foo[extra]==1.0; python_version < "3.8"`, `name @ https://...`),
+//! an editable install (`-e ./local`), a bare URL (`git+https://...#egg=name`),
+//! or an option (`-r requirements.txt`, `--constraint constraints.txt`, `--index-url ...`).
+
+use std::fmt;
+
+use pep440::SpecifierSet;
+
+/// A single line of the pip section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipLine {
+    Requirement(Requirement),
+    /// `-r <file>`: include another requirements file.
+    Include(String),
+    /// `-c <file>`: constrain versions with another requirements file.
+    Constraint(String),
+    /// Any other option, e.g. `--index-url https://...`.
+    Option(String),
+}
+
+/// A requirement on a package.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Requirement {
+    /// The project name, as written.  `None` for URLs and paths that don't say what they provide.
+    pub name: Option<String>,
+    pub extras: Vec<String>,
+    /// The version specifier with whitespace removed, e.g. `>=2,<3`.
+    pub specifier: Option<String>,
+    /// A direct reference: `name @ <url>`, a VCS URL or a local path.
+    pub url: Option<String>,
+    /// The environment marker following `;`.
+    pub marker: Option<String>,
+    /// Installed with `-e`.
+    pub editable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipLineError {
+    pub line: String,
+    pub msg: String,
+}
+
+impl fmt::Display for PipLineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pip requirement {:?}: {}", self.line, self.msg)
+    }
+}
+
+/// Normalize a project name as described in PEP 503, so `Foo_Bar` and `foo-bar` compare equal.
+pub fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut in_separator = false;
+    for c in name.chars() {
+        if c == '-' || c == '_' || c == '.' {
+            in_separator = true;
+            continue;
+        }
+        if in_separator && !normalized.is_empty() {
+            normalized.push('-');
+        }
+        in_separator = false;
+        normalized.extend(c.to_lowercase());
+    }
+    normalized
+}
+
+impl Requirement {
+    /// The PEP 503 normalized project name.
+    pub fn normalized_name(&self) -> Option<String> {
+        self.name.as_ref().map(|name| normalize_name(name))
+    }
+
+    /// The parsed version specifier.
+    pub fn specifier_set(&self) -> Option<SpecifierSet> {
+        // Specifiers are checked when the requirement is parsed.
+        self.specifier.as_ref().and_then(|s| SpecifierSet::parse(s).ok())
+    }
+}
+
+impl PipLine {
+    pub fn parse(line: &str) -> Result<PipLine, PipLineError> {
+        let err = |msg: &str| PipLineError {
+            line: line.to_string(),
+            msg: msg.to_string(),
+        };
+        let stripped = strip_comment(line).trim();
+        if stripped.is_empty() {
+            return Err(err("empty requirement"));
+        }
+
+        if stripped.starts_with('-') {
+            let (option, value) = split_option(stripped);
+            let value = || value.ok_or_else(|| err(&format!("{} requires a value", option)));
+            return match option {
+                "-r" | "--requirement" => Ok(PipLine::Include(value()?)),
+                "-c" | "--constraint" => Ok(PipLine::Constraint(value()?)),
+                "-e" | "--editable" => {
+                    let mut req = parse_direct_reference(&value()?);
+                    req.editable = true;
+                    Ok(PipLine::Requirement(req))
+                }
+                _ => Ok(PipLine::Option(stripped.to_string())),
+            };
+        }
+
+        if is_direct_reference(stripped) {
+            return Ok(PipLine::Requirement(parse_direct_reference(stripped)));
+        }
+        parse_pep508(stripped).map(PipLine::Requirement).map_err(|msg| err(&msg))
+    }
+
+    /// The requirement on this line, if it is one.
+    pub fn requirement(&self) -> Option<&Requirement> {
+        match *self {
+            PipLine::Requirement(ref req) => Some(req),
+            _ => None,
+        }
+    }
+}
+
+/// Drop a trailing `# comment`.  A `#` that isn't preceded by whitespace is part of a URL.
+fn strip_comment(line: &str) -> &str {
+    if line.starts_with('#') {
+        return "";
+    }
+    match line.find(" #").or_else(|| line.find("\t#")) {
+        Some(idx) => &line[..idx],
+        None => line,
+    }
+}
+
+/// Split `-r file`, `--requirement file` or `--requirement=file`.
+fn split_option(line: &str) -> (&str, Option<String>) {
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let option = &line[..end];
+    let value = line[end..].trim_start_matches('=').trim();
+    if value.is_empty() {
+        (option, None)
+    } else {
+        (option, Some(value.to_string()))
+    }
+}
+
+fn is_direct_reference(s: &str) -> bool {
+    // `name @ https://...` is a PEP 508 requirement that happens to contain a URL.
+    let named = s.split("://").next().is_some_and(|before| before.contains('@'));
+    !named && s.contains("://")
+        || s.starts_with("./")
+        || s.starts_with("../")
+        || s.starts_with('/')
+        || s == "."
+        || s.starts_with("file:")
+}
+
+/// A URL or path, with the name taken from an `#egg=name` fragment if it has one.
+fn parse_direct_reference(url: &str) -> Requirement {
+    let name = url.find("#egg=").map(|idx| {
+        let egg = &url[idx + "#egg=".len()..];
+        egg.split('&').next().unwrap_or(egg).to_string()
+    });
+    Requirement {
+        name: name.filter(|n| !n.is_empty()),
+        url: Some(url.to_string()),
+        ..Requirement::default()
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
+}
+
+/// Parse `name [extras] (specifier | @ url) [; marker]`.
+fn parse_pep508(line: &str) -> Result<Requirement, String> {
+    let mut req = Requirement::default();
+
+    let (line, marker) = match line.find(';') {
+        Some(idx) => (line[..idx].trim(), Some(line[idx + 1..].trim())),
+        None => (line, None),
+    };
+    if let Some(marker) = marker {
+        if marker.is_empty() {
+            return Err("empty environment marker".to_string());
+        }
+        req.marker = Some(marker.to_string());
+    }
+
+    let name_end = line.find(|c: char| !is_name_char(c)).unwrap_or(line.len());
+    let name = &line[..name_end];
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric());
+    if !valid_name {
+        return Err("invalid project name".to_string());
+    }
+    req.name = Some(name.to_string());
+
+    let mut rest = line[name_end..].trim_start();
+    if rest.starts_with('[') {
+        let close = rest.find(']').ok_or_else(|| "unclosed extras".to_string())?;
+        for extra in rest[1..close].split(',') {
+            let extra = extra.trim();
+            if extra.is_empty() || !extra.chars().all(is_name_char) {
+                return Err(format!("invalid extra {:?}", extra));
+            }
+            req.extras.push(extra.to_string());
+        }
+        rest = rest[close + 1..].trim_start();
+    }
+
+    if let Some(url) = rest.strip_prefix('@') {
+        let url = url.trim();
+        if url.is_empty() || url.contains(char::is_whitespace) {
+            return Err("invalid URL".to_string());
+        }
+        req.url = Some(url.to_string());
+        return Ok(req);
+    }
+
+    let specifier = rest.trim_start_matches('(').trim_end_matches(')');
+    let specifier: String = specifier.chars().filter(|c| !c.is_whitespace()).collect();
+    if !specifier.is_empty() {
+        SpecifierSet::parse(&specifier).map_err(|e| e.to_string())?;
+        req.specifier = Some(specifier);
+    }
+    Ok(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(line: &str) -> Requirement {
+        match PipLine::parse(line).unwrap() {
+            PipLine::Requirement(req) => req,
+            other => panic!("{:?} is not a requirement", other),
+        }
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize_name("Foo_Bar"), "foo-bar");
+        assert_eq!(normalize_name("foo.__-bar"), "foo-bar");
+        assert_eq!(normalize_name("requests"), "requests");
+    }
+
+    #[test]
+    fn pep508() {
+        let r = req("requests>=2");
+        assert_eq!(r.name, Some("requests".to_string()));
+        assert_eq!(r.specifier, Some(">=2".to_string()));
+
+        let r = req("Foo_Bar[extra, other] == 1.0 ; python_version < \"3.8\"");
+        assert_eq!(r.normalized_name(), Some("foo-bar".to_string()));
+        assert_eq!(r.extras, ["extra", "other"]);
+        assert_eq!(r.specifier, Some("==1.0".to_string()));
+        assert_eq!(r.marker, Some("python_version < \"3.8\"".to_string()));
+
+        let r = req("pip @ https://github.com/pypa/pip/archive/1.3.1.zip");
+        assert_eq!(r.name, Some("pip".to_string()));
+        assert_eq!(r.url, Some("https://github.com/pypa/pip/archive/1.3.1.zip".to_string()));
+
+        assert_eq!(req("name (>=1,<2)").specifier, Some(">=1,<2".to_string()));
+        assert_eq!(req("requests==2.19.1  # pinned").specifier, Some("==2.19.1".to_string()));
+    }
+
+    #[test]
+    fn direct_references() {
+        let r = req("-e ./local");
+        assert!(r.editable);
+        assert_eq!(r.name, None);
+        assert_eq!(r.url, Some("./local".to_string()));
+
+        let r = req("git+https://github.com/org/repo.git@v1#egg=my_pkg");
+        assert_eq!(r.name, Some("my_pkg".to_string()));
+        assert!(!r.editable);
+
+        assert!(req("--editable=git+https://x.org/r.git#egg=r").editable);
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(PipLine::parse("-r requirements.txt").unwrap(), PipLine::Include("requirements.txt".to_string()));
+        assert_eq!(
+            PipLine::parse("--constraint=constraints.txt").unwrap(),
+            PipLine::Constraint("constraints.txt".to_string())
+        );
+        assert_eq!(
+            PipLine::parse("--index-url https://example.com").unwrap(),
+            PipLine::Option("--index-url https://example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(PipLine::parse("").is_err());
+        assert!(PipLine::parse("-r").is_err());
+        assert!(PipLine::parse("requests=2").is_err());
+        assert!(PipLine::parse("foo[bar").is_err());
+        assert!(PipLine::parse("_foo").is_err());
+    }
+}
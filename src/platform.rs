@@ -0,0 +1,219 @@
+//! Platforms lockfiles can be built for.
+//!
+//! Platforms are named by their conda subdir, e.g. `linux-64` or `osx-arm64`.  Lockfiles used
+//! to be named after the OS alone (`deps.yml.Linux.lock`, `deps.yml.Darwin.lock`); those names
+//! are still understood, as the x86-64 platform of that OS.
+
+use std::env::consts;
+use std::fmt;
+use std::str::FromStr;
+
+use {Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Platform {
+    Linux64,
+    LinuxAarch64,
+    Osx64,
+    OsxArm64,
+    Win64,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 5] = [
+        Platform::Linux64,
+        Platform::LinuxAarch64,
+        Platform::Osx64,
+        Platform::OsxArm64,
+        Platform::Win64,
+    ];
+
+    /// The conda subdir holding packages for this platform.
+    pub fn subdir(self) -> &'static str {
+        match self {
+            Platform::Linux64 => "linux-64",
+            Platform::LinuxAarch64 => "linux-aarch64",
+            Platform::Osx64 => "osx-64",
+            Platform::OsxArm64 => "osx-arm64",
+            Platform::Win64 => "win-64",
+        }
+    }
+
+    /// The platform for an OS and architecture, named as in `std::env::consts`.
+    pub fn from_os_arch(os: &str, arch: &str) -> Option<Platform> {
+        match (os, arch) {
+            ("linux", "x86_64") => Some(Platform::Linux64),
+            ("linux", "aarch64") => Some(Platform::LinuxAarch64),
+            ("macos", "x86_64") => Some(Platform::Osx64),
+            ("macos", "aarch64") => Some(Platform::OsxArm64),
+            ("windows", "x86_64") => Some(Platform::Win64),
+            _ => None,
+        }
+    }
+
+    /// The platform this binary is running on.
+    pub fn current() -> Result<Platform> {
+        Platform::from_os_arch(consts::OS, consts::ARCH).ok_or_else(|| {
+            Error::UnsupportedPlatform(format!("Unknown platform {}-{}", consts::OS, consts::ARCH))
+        })
+    }
+
+    /// The pre-subdir name lockfiles for this platform had, if there was one.
+    pub fn legacy_name(self) -> Option<&'static str> {
+        match self {
+            Platform::Linux64 => Some("Linux"),
+            Platform::Osx64 => Some("Darwin"),
+            _ => None,
+        }
+    }
+
+    /// Parse a subdir, or a legacy OS name.
+    fn parse(s: &str) -> Option<Platform> {
+        Platform::ALL
+            .iter()
+            .cloned()
+            .find(|p| p.subdir() == s || p.legacy_name() == Some(s))
+    }
+
+    pub fn is_linux(self) -> bool {
+        matches!(self, Platform::Linux64 | Platform::LinuxAarch64)
+    }
+
+    pub fn is_osx(self) -> bool {
+        matches!(self, Platform::Osx64 | Platform::OsxArm64)
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.subdir())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Platform> {
+        Platform::parse(s).ok_or_else(|| {
+            let known: Vec<&str> = Platform::ALL.iter().map(|p| p.subdir()).collect();
+            Error::UnsupportedPlatform(format!("{} is not one of {}", s, known.join(", ")))
+        })
+    }
+}
+
+/// A virtual package the target machine is assumed to provide, e.g. `__glibc=2.17`.
+///
+/// Conda derives virtual packages from the machine it runs on.  When freezing for another
+/// machine they are overridden with conda's `CONDA_OVERRIDE_*` variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualPackage {
+    pub name: String,
+    /// Empty to declare a package absent, e.g. no `__cuda` driver.
+    pub version: String,
+}
+
+impl VirtualPackage {
+    /// A virtual package for `platform`, checking that the platform can have it and that conda
+    /// can be told about it.
+    pub fn new(
+        name: &str,
+        version: &str,
+        platform: Platform,
+    ) -> ::std::result::Result<VirtualPackage, String> {
+        let applies = match name {
+            "__glibc" => platform.is_linux(),
+            "__osx" => platform.is_osx(),
+            "__unix" | "__win" => {
+                return Err(format!("{} follows from the platform and can't be overridden", name))
+            }
+            "__cuda" => !platform.is_osx(),
+            "__archspec" => true,
+            _ => return Err(format!("unknown virtual package {}", name)),
+        };
+        if !applies {
+            return Err(format!("{} does not apply to {}", name, platform));
+        }
+        Ok(VirtualPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+        })
+    }
+
+    /// Parse `name=version`, as written in lockfile headers.
+    pub fn parse(s: &str) -> Option<VirtualPackage> {
+        let (name, version) = match s.find('=') {
+            Some(idx) => (&s[..idx], &s[idx + 1..]),
+            None => (s, ""),
+        };
+        if !name.starts_with("__") {
+            return None;
+        }
+        Some(VirtualPackage {
+            name: name.trim().to_string(),
+            version: version.trim().to_string(),
+        })
+    }
+
+    /// The variable conda reads to override this package, if it has one.
+    ///
+    /// `__unix` and `__win` follow from `CONDA_SUBDIR` and can't be overridden.
+    pub fn override_var(&self) -> Option<String> {
+        match self.name.as_str() {
+            "__unix" | "__win" => None,
+            name => {
+                let name = name.trim_start_matches('_').to_uppercase();
+                Some(format!("CONDA_OVERRIDE_{}", name))
+            }
+        }
+    }
+}
+
+impl fmt::Display for VirtualPackage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.version)
+    }
+}
+
+/// The platform this binary is running on.
+pub fn get_platform() -> Result<Platform> {
+    Platform::current()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subdirs_round_trip() {
+        for platform in &Platform::ALL {
+            assert_eq!(platform.subdir().parse::<Platform>().unwrap(), *platform);
+        }
+        assert!("linux-32".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn legacy_names() {
+        assert_eq!("Linux".parse::<Platform>().unwrap(), Platform::Linux64);
+        assert_eq!("Darwin".parse::<Platform>().unwrap(), Platform::Osx64);
+        assert_eq!(Platform::OsxArm64.legacy_name(), None);
+    }
+
+    #[test]
+    fn virtual_packages() {
+        let glibc = VirtualPackage::new("__glibc", "2.17", Platform::Linux64).unwrap();
+        assert_eq!(glibc.override_var(), Some("CONDA_OVERRIDE_GLIBC".to_string()));
+        assert_eq!(VirtualPackage::parse(&glibc.to_string()), Some(glibc));
+        assert_eq!(VirtualPackage::parse("__unix").unwrap().override_var(), None);
+        assert!(VirtualPackage::new("__unix", "", Platform::OsxArm64).is_err());
+        assert!(VirtualPackage::new("__glibc", "2.17", Platform::OsxArm64).is_err());
+        assert!(VirtualPackage::new("__cuda", "11.8", Platform::Osx64).is_err());
+        assert!(VirtualPackage::new("__gpu", "1", Platform::Linux64).is_err());
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(Platform::from_os_arch("macos", "aarch64"), Some(Platform::OsxArm64));
+        assert_eq!(Platform::from_os_arch("linux", "aarch64"), Some(Platform::LinuxAarch64));
+        assert_eq!(Platform::from_os_arch("freebsd", "x86_64"), None);
+    }
+}
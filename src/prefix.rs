@@ -0,0 +1,295 @@
+//! Reading what is installed in a conda environment.
+//!
+//! Conda records every package it links in `<prefix>/conda-meta/<name>-<version>-<build>.json`.
+//! Python packages, whether installed by conda or pip, leave a `*.dist-info` directory in
+//! `site-packages`; its `INSTALLER` file says which tool put it there.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use {Error, Result};
+
+/// A package conda has linked into a prefix, from its `conda-meta` record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CondaRecord {
+    pub name: String,
+    pub version: String,
+    pub build: String,
+    pub url: Option<String>,
+    pub md5: Option<String>,
+    /// The files the package installed, relative to the prefix.
+    pub files: Vec<String>,
+    /// What conda recorded about each file, if it did.  Older records only list `files`.
+    pub paths: Vec<PathEntry>,
+}
+
+/// An entry of a `conda-meta` record's `paths_data`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathEntry {
+    /// Relative to the prefix.
+    pub path: String,
+    /// `hardlink`, `softlink`, `directory`, or a file conda generated, e.g. `pyc_file`.
+    pub path_type: String,
+    /// The checksum of the file as installed, when known.
+    pub sha256: Option<String>,
+    /// The size of the file as installed, when known.
+    pub size: Option<u64>,
+}
+
+impl PathEntry {
+    /// Parse an entry of `paths_data.paths`.
+    ///
+    /// Files with a prefix placeholder were rewritten on install, so their recorded size and
+    /// `sha256` are those of the package; only `sha256_in_prefix` describes the installed file.
+    fn parse(entry: &Value) -> Option<PathEntry> {
+        let in_prefix = string(entry, "sha256_in_prefix");
+        let (sha256, size) = if entry.get("prefix_placeholder").is_some() {
+            (in_prefix, None)
+        } else {
+            let size = entry.get("size_in_bytes").and_then(Value::as_u64);
+            (in_prefix.or_else(|| string(entry, "sha256")), size)
+        };
+        Some(PathEntry {
+            path: string(entry, "_path")?,
+            path_type: string(entry, "path_type").unwrap_or_else(|| "hardlink".to_string()),
+            sha256,
+            size,
+        })
+    }
+}
+
+impl CondaRecord {
+    /// The package as `name version build`.
+    pub fn describe(&self) -> String {
+        format!("{} {} {}", self.name, self.version, self.build)
+    }
+
+    /// The package as a match spec for exactly this build, `name=version=build`.
+    pub fn spec(&self) -> String {
+        format!("{}={}={}", self.name, self.version, self.build)
+    }
+}
+
+/// A Python distribution installed in a prefix, from its `*.dist-info` directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Distribution {
+    pub name: String,
+    pub version: String,
+    /// The contents of the `INSTALLER` file, e.g. `pip` or `conda`.
+    pub installer: Option<String>,
+    /// The dist-info directory, relative to the prefix.
+    pub path: PathBuf,
+}
+
+fn string(record: &Value, key: &str) -> Option<String> {
+    record.get(key).and_then(Value::as_str).map(|s| s.to_string())
+}
+
+/// Parse a `conda-meta` record.
+pub fn parse_conda_record(data: &str) -> ::std::result::Result<CondaRecord, String> {
+    let record: Value = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    let field = |key| string(&record, key).ok_or_else(|| format!("no {}", key));
+    Ok(CondaRecord {
+        name: field("name")?,
+        version: field("version")?,
+        build: field("build")?,
+        url: string(&record, "url"),
+        md5: string(&record, "md5"),
+        files: match record.get("files") {
+            Some(Value::Array(files)) => files
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|file| file.to_string())
+                .collect(),
+            _ => Vec::new(),
+        },
+        paths: match record.get("paths_data").and_then(|data| data.get("paths")) {
+            Some(Value::Array(paths)) => paths.iter().filter_map(PathEntry::parse).collect(),
+            _ => Vec::new(),
+        },
+    })
+}
+
+/// The conda packages installed in `prefix`, by name.
+pub fn conda_records(prefix: &Path) -> Result<Vec<CondaRecord>> {
+    let meta_dir = prefix.join("conda-meta");
+    if !meta_dir.is_dir() {
+        let msg = format!("{} is not a conda environment", prefix.display());
+        return Err(Error::Conda(msg));
+    }
+    let mut records = Vec::new();
+    for entry in fs::read_dir(&meta_dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let record = parse_conda_record(&fs::read_to_string(&path)?)
+            .map_err(|e| Error::Conda(format!("{}: {}", path.display(), e)))?;
+        records.push(record);
+    }
+    records.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(records)
+}
+
+/// The `site-packages` directories of `prefix`, relative to it: `lib/python3.X/site-packages`
+/// on Unix and `Lib/site-packages` on Windows.
+fn site_packages(prefix: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let windows = Path::new("Lib").join("site-packages");
+    if prefix.join(&windows).is_dir() {
+        dirs.push(windows);
+    }
+    let lib = prefix.join("lib");
+    if lib.is_dir() {
+        for entry in fs::read_dir(&lib)? {
+            let name = entry?.file_name();
+            let site = Path::new("lib").join(&name).join("site-packages");
+            if name.to_string_lossy().starts_with("python") && prefix.join(&site).is_dir() {
+                dirs.push(site);
+            }
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Read the `Name` and `Version` headers of a dist-info `METADATA` file.
+fn parse_metadata(data: &str) -> Option<(String, String)> {
+    let mut name = None;
+    let mut version = None;
+    // The headers end at the first blank line; the description follows.
+    for line in data.lines().take_while(|line| !line.trim().is_empty()) {
+        if let Some(value) = line.strip_prefix("Name:") {
+            name = Some(value.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("Version:") {
+            version = Some(value.trim().to_string());
+        }
+    }
+    Some((name?, version?))
+}
+
+/// The Python distributions installed in `prefix`, by directory.
+pub fn distributions(prefix: &Path) -> Result<Vec<Distribution>> {
+    let mut dists = Vec::new();
+    for site in site_packages(prefix)? {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(prefix.join(&site))? {
+            entries.push(entry?.file_name());
+        }
+        entries.sort();
+        for name in entries {
+            if !name.to_string_lossy().ends_with(".dist-info") {
+                continue;
+            }
+            let path = site.join(&name);
+            let metadata = match fs::read_to_string(prefix.join(&path).join("METADATA")) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    warn!("Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let (name, version) = match parse_metadata(&metadata) {
+                Some(parsed) => parsed,
+                None => {
+                    warn!("Skipping {}: no name and version in METADATA", path.display());
+                    continue;
+                }
+            };
+            let installer = fs::read_to_string(prefix.join(&path).join("INSTALLER"))
+                .ok()
+                .map(|installer| installer.trim().to_string());
+            dists.push(Distribution {
+                name,
+                version,
+                installer,
+                path,
+            });
+        }
+    }
+    Ok(dists)
+}
+
+/// The distributions not installed by conda: those whose `INSTALLER` isn't conda and whose
+/// files no conda package claims.
+pub fn pip_distributions<'a>(
+    dists: &'a [Distribution],
+    records: &[CondaRecord],
+) -> Vec<&'a Distribution> {
+    let conda_files: HashSet<&str> =
+        records.iter().flat_map(|r| r.files.iter()).map(String::as_str).collect();
+    dists
+        .iter()
+        .filter(|dist| dist.installer.as_deref() != Some("conda"))
+        .filter(|dist| {
+            let metadata = dist.path.join("METADATA");
+            // conda-meta records always use forward slashes.
+            let metadata = metadata.to_string_lossy().replace('\\', "/");
+            !conda_files.contains(metadata.as_str())
+        }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn installed_packages() {
+        let prefix = tempdir().unwrap();
+        let prefix = prefix.path();
+        create_dir_all(prefix.join("conda-meta")).unwrap();
+        write(
+            prefix.join("conda-meta").join("six-1.12.0-py_0.json"),
+            r#"{"name": "six", "version": "1.12.0", "build": "py_0", "md5": "abcd",
+                "files": ["lib/python3.7/site-packages/six-1.12.0.dist-info/METADATA",
+                          "lib/python3.7/site-packages/six.py"],
+                "paths_data": {"paths_version": 1, "paths": [
+                  {"_path": "lib/python3.7/site-packages/six.py", "path_type": "hardlink",
+                   "sha256": "aaaa", "size_in_bytes": 32452},
+                  {"_path": "bin/six-config", "path_type": "hardlink", "sha256": "bbbb",
+                   "sha256_in_prefix": "cccc", "size_in_bytes": 100,
+                   "prefix_placeholder": "/opt/anaconda1anaconda2anaconda3"}]}}"#,
+        ).unwrap();
+        write(prefix.join("conda-meta").join("history"), "").unwrap();
+        let site = prefix.join("lib").join("python3.7").join("site-packages");
+        for (dist, name, version, installer) in &[
+            ("six-1.12.0.dist-info", "six", "1.12.0", None),
+            ("requests-2.21.0.dist-info", "requests", "2.21.0", Some("pip")),
+            ("certifi-2018.11.29.dist-info", "certifi", "2018.11.29", Some("conda")),
+        ] {
+            create_dir_all(site.join(dist)).unwrap();
+            let metadata = format!(
+                "Metadata-Version: 2.1\nName: {}\nVersion: {}\n\nName: not-a-header\n",
+                name, version
+            );
+            write(site.join(dist).join("METADATA"), metadata).unwrap();
+            if let Some(installer) = installer {
+                write(site.join(dist).join("INSTALLER"), format!("{}\n", installer)).unwrap();
+            }
+        }
+
+        let records = conda_records(prefix).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].describe(), "six 1.12.0 py_0");
+        assert_eq!(records[0].md5.as_deref(), Some("abcd"));
+        assert_eq!(records[0].paths[0].sha256.as_deref(), Some("aaaa"));
+        assert_eq!(records[0].paths[0].size, Some(32452));
+        assert_eq!(records[0].paths[1].sha256.as_deref(), Some("cccc"));
+        assert_eq!(records[0].paths[1].size, None);
+
+        let dists = distributions(prefix).unwrap();
+        assert_eq!(dists.len(), 3);
+        assert_eq!(dists[0].name, "certifi");
+        let pip: Vec<&str> =
+            pip_distributions(&dists, &records).iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(pip, ["requests"]);
+
+        assert!(conda_records(&prefix.join("lib")).is_err());
+    }
+}
@@ -0,0 +1,648 @@
+//! Reading channel repodata: the index of the packages a channel provides.
+//!
+//! A channel is a directory (or URL) holding one directory per subdir, each with a
+//! `repodata.json` listing its packages:
+//!
+//! ```text
+//! channel/
+//!   linux-64/repodata.json
+//!   linux-64/numpy-1.15.4-py37_0.tar.bz2
+//!   noarch/repodata.json
+//! ```
+//!
+//! Local channels may also be plain directories of package files, or have only a
+//! `current_repodata.json`.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use md5::{Digest, Md5};
+use serde_json::{self, Map, Value};
+use tempfile::NamedTempFile;
+
+use conda_version::CondaVersion;
+use hash::HashAlgorithm;
+use matchspec::{glob_matches, split_package_filename, MatchSpec};
+use platform::Platform;
+use {Error, Result};
+
+/// One package build, as described by repodata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageRecord {
+    pub name: String,
+    pub version: String,
+    pub build: String,
+    pub build_number: u64,
+    /// Match specs of the packages this one needs.
+    pub depends: Vec<String>,
+    /// Match specs other packages must satisfy, if they are installed alongside this one.
+    pub constrains: Vec<String>,
+    /// The channel URL, e.g. `file:///srv/channel`.
+    pub channel: String,
+    pub subdir: String,
+    /// The package file name, e.g. `numpy-1.15.4-py37_0.tar.bz2`.
+    pub filename: String,
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+    pub timestamp: u64,
+}
+
+impl PackageRecord {
+    /// Where the package file can be downloaded from.
+    pub fn url(&self) -> String {
+        format!("{}/{}/{}", self.channel.trim_end_matches('/'), self.subdir, self.filename)
+    }
+
+    /// The package as a lockfile entry, `name=version=build`.
+    pub fn spec(&self) -> String {
+        format!("{}={}={}", self.name, self.version, self.build)
+    }
+
+    /// Is this package a candidate for `spec`?
+    pub fn matches(&self, spec: &MatchSpec) -> bool {
+        if self.name != spec.name {
+            return false;
+        }
+        if let Some(version_spec) = spec.version_spec() {
+            match CondaVersion::parse(&self.version) {
+                Ok(ref version) if version_spec.matches(version) => {}
+                _ => return false,
+            }
+        }
+        let field_matches = |wanted: &Option<String>, actual: &str| match *wanted {
+            Some(ref wanted) => glob_matches(wanted, actual),
+            None => true,
+        };
+        let checksum_matches = |wanted: &Option<String>, actual: &Option<String>| match *wanted {
+            Some(ref wanted) => actual.as_ref() == Some(wanted),
+            None => true,
+        };
+        field_matches(&spec.build, &self.build)
+            && field_matches(&spec.subdir, &self.subdir)
+            && spec.channel.as_ref().is_none_or(|c| channel_name(c) == channel_name(&self.channel))
+            && checksum_matches(&spec.md5, &self.md5)
+            && checksum_matches(&spec.sha256, &self.sha256)
+    }
+}
+
+/// The last path segment of a channel, so `conda-forge` matches
+/// `https://conda.anaconda.org/conda-forge`.
+pub fn channel_name(channel: &str) -> &str {
+    let trimmed = channel.trim_end_matches('/');
+    trimmed.rsplit('/').next().unwrap_or(trimmed)
+}
+
+/// The directory of a local channel: a `file://` URL or a path.  `None` for remote channels.
+pub fn channel_path(channel: &str) -> Option<PathBuf> {
+    if let Some(path) = channel.strip_prefix("file://") {
+        return Some(PathBuf::from(path));
+    }
+    if channel.contains("://") {
+        return None;
+    }
+    let path = Path::new(channel);
+    // A bare name like `conda-forge` is a channel on anaconda.org, not a directory.
+    if path.is_absolute() || channel.starts_with('.') || path.is_dir() {
+        Some(path.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// The URL records from the channel at `path` are given.
+fn channel_url(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    format!("file://{}", path.display())
+}
+
+fn string_list(record: &Map<String, Value>, key: &str) -> Vec<String> {
+    match record.get(key) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(|value| value.to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Parse the records in a `repodata.json` of the `subdir` of `channel`.
+pub fn parse_repodata(
+    data: &str,
+    channel: &str,
+    subdir: &str,
+) -> ::std::result::Result<Vec<PackageRecord>, String> {
+    let repodata: Value = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    repodata_records(&repodata, channel, subdir)
+}
+
+fn repodata_records(
+    repodata: &Value,
+    channel: &str,
+    subdir: &str,
+) -> ::std::result::Result<Vec<PackageRecord>, String> {
+    let mut records = Vec::new();
+    for key in &["packages", "packages.conda"] {
+        let packages = match repodata.get(*key) {
+            Some(Value::Object(packages)) => packages,
+            Some(_) => return Err(format!("{} is not a mapping", key)),
+            None => continue,
+        };
+        for (filename, record) in packages {
+            let record = record
+                .as_object()
+                .ok_or_else(|| format!("{} is not a mapping", filename))?;
+            let field = |name: &str| {
+                record
+                    .get(name)
+                    .and_then(Value::as_str)
+                    .map(|value| value.to_string())
+                    .ok_or_else(|| format!("{} has no {}", filename, name))
+            };
+            let optional =
+                |name: &str| record.get(name).and_then(Value::as_str).map(|v| v.to_string());
+            records.push(PackageRecord {
+                name: field("name")?,
+                version: field("version")?,
+                build: field("build")?,
+                build_number: record.get("build_number").and_then(Value::as_u64).unwrap_or(0),
+                depends: string_list(record, "depends"),
+                constrains: string_list(record, "constrains"),
+                channel: channel.to_string(),
+                subdir: optional("subdir").unwrap_or_else(|| subdir.to_string()),
+                filename: filename.clone(),
+                md5: optional("md5"),
+                sha256: optional("sha256"),
+                size: record.get("size").and_then(Value::as_u64),
+                timestamp: record.get("timestamp").and_then(Value::as_u64).unwrap_or(0),
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// The inverse of `parse_repodata`, for caching.
+fn to_repodata(records: &[PackageRecord]) -> Value {
+    let mut packages = Map::new();
+    let mut conda_packages = Map::new();
+    for record in records {
+        let mut entry = Map::new();
+        let strings = |values: &[String]| {
+            Value::Array(values.iter().map(|v| Value::from(v.as_str())).collect())
+        };
+        entry.insert("name".to_string(), Value::from(record.name.as_str()));
+        entry.insert("version".to_string(), Value::from(record.version.as_str()));
+        entry.insert("build".to_string(), Value::from(record.build.as_str()));
+        entry.insert("build_number".to_string(), Value::from(record.build_number));
+        entry.insert("depends".to_string(), strings(&record.depends));
+        entry.insert("constrains".to_string(), strings(&record.constrains));
+        entry.insert("subdir".to_string(), Value::from(record.subdir.as_str()));
+        entry.insert("timestamp".to_string(), Value::from(record.timestamp));
+        if let Some(ref md5) = record.md5 {
+            entry.insert("md5".to_string(), Value::from(md5.as_str()));
+        }
+        if let Some(ref sha256) = record.sha256 {
+            entry.insert("sha256".to_string(), Value::from(sha256.as_str()));
+        }
+        if let Some(size) = record.size {
+            entry.insert("size".to_string(), Value::from(size));
+        }
+        let section = if record.filename.ends_with(".conda") {
+            &mut conda_packages
+        } else {
+            &mut packages
+        };
+        section.insert(record.filename.clone(), Value::Object(entry));
+    }
+    let mut repodata = Map::new();
+    repodata.insert("packages".to_string(), Value::Object(packages));
+    repodata.insert("packages.conda".to_string(), Value::Object(conda_packages));
+    Value::Object(repodata)
+}
+
+/// Describe a package file found in a channel directory without an entry in its repodata.
+///
+/// Name, version and build come from the file name; the checksums are computed.  The
+/// package's dependencies are inside the archive, which isn't read, so they are left empty.
+fn package_file_record(
+    path: &Path,
+    channel: &str,
+    subdir: &str,
+) -> Result<Option<PackageRecord>> {
+    let filename = match path.file_name().and_then(|name| name.to_str()) {
+        Some(filename) => filename.to_string(),
+        None => return Ok(None),
+    };
+    let (name, version, build) = match split_package_filename(&filename) {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let data = fs::read(path)?;
+    let metadata = fs::metadata(path)?;
+    let build_number = build.rsplit('_').next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    Ok(Some(PackageRecord {
+        name,
+        version,
+        build,
+        build_number,
+        depends: Vec::new(),
+        constrains: Vec::new(),
+        channel: channel.to_string(),
+        subdir: subdir.to_string(),
+        filename,
+        md5: Some(format!("{:x}", Md5::digest(&data))),
+        sha256: Some(HashAlgorithm::Sha256.digest(&data)),
+        size: Some(metadata.len()),
+        timestamp: modified_millis(&metadata),
+    }))
+}
+
+fn modified_millis(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|age| age.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn is_package_file(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.ends_with(".conda") || path.ends_with(".tar.bz2"))
+}
+
+/// The files in a subdir that records are read from: repodata and package files.
+fn source_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        if name == "repodata.json" || name == "current_repodata.json" || is_package_file(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Summarize the name, size and modification time of every source file.  Records read from a
+/// subdir are still good as long as this doesn't change.
+fn fingerprint(files: &[PathBuf]) -> Result<String> {
+    let mut summary = String::new();
+    for path in files {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_nanos())
+            .unwrap_or(0);
+        summary.push_str(&format!("{}\0{}\0{}\n", path.display(), metadata.len(), modified));
+    }
+    Ok(HashAlgorithm::Sha256.digest(summary.as_bytes()))
+}
+
+/// Read the records of a subdir from scratch.
+fn read_subdir(
+    dir: &Path,
+    files: &[PathBuf],
+    channel: &str,
+    subdir: &str,
+) -> Result<Vec<PackageRecord>> {
+    // `current_repodata.json` only lists the latest versions, so it's a fallback.
+    let repodata_path = [dir.join("repodata.json"), dir.join("current_repodata.json")]
+        .iter()
+        .find(|path| files.contains(path))
+        .cloned();
+    let mut records = match repodata_path {
+        Some(ref path) => parse_repodata(&fs::read_to_string(path)?, channel, subdir)
+            .map_err(|e| Error::BadRepodata(format!("{}: {}", path.display(), e)))?,
+        None => Vec::new(),
+    };
+    let listed: HashSet<String> = records.iter().map(|r| r.filename.clone()).collect();
+    for path in files.iter().filter(|path| is_package_file(path)) {
+        let unlisted = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| !listed.contains(name));
+        if unlisted {
+            if let Some(record) = package_file_record(path, channel, subdir)? {
+                warn!("{} is not in repodata; its dependencies are unknown", path.display());
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Where parsed repodata is cached by default: `$XDG_CACHE_HOME/conda-lockfile/repodata`,
+/// falling back to `~/.cache`.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache_home.join("conda-lockfile").join("repodata"))
+}
+
+/// Read the records of a subdir, reusing what's in `cache_dir` if the subdir hasn't changed.
+///
+/// `None` if the subdir doesn't exist or has neither repodata nor packages.
+fn load_subdir(
+    dir: &Path,
+    channel: &str,
+    subdir: &str,
+    cache_dir: Option<&Path>,
+) -> Result<Option<Vec<PackageRecord>>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let files = source_files(dir)?;
+    if files.is_empty() {
+        return Ok(None);
+    }
+    let cache_dir = match cache_dir {
+        Some(cache_dir) => cache_dir,
+        None => return read_subdir(dir, &files, channel, subdir).map(Some),
+    };
+
+    let key = HashAlgorithm::Sha256.digest(format!("{}/{}", channel, subdir).as_bytes());
+    let cache_path = cache_dir.join(format!("{}.json", &key[..16]));
+    let fingerprint = fingerprint(&files)?;
+    let cached: Option<Value> = fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok());
+    if let Some(cached) = cached {
+        if cached.get("fingerprint").and_then(Value::as_str) == Some(&fingerprint) {
+            let records = cached
+                .get("repodata")
+                .map(|repodata| repodata_records(repodata, channel, subdir));
+            if let Some(Ok(records)) = records {
+                debug!("Using cached repodata for {}/{}", channel, subdir);
+                return Ok(Some(records));
+            }
+        }
+    }
+
+    let records = read_subdir(dir, &files, channel, subdir)?;
+    let mut entry = Map::new();
+    entry.insert("fingerprint".to_string(), Value::from(fingerprint));
+    entry.insert("repodata".to_string(), to_repodata(&records));
+    if let Err(e) = write_cache(cache_dir, &cache_path, &Value::Object(entry)) {
+        warn!("Unable to cache repodata in {}: {}", cache_path.display(), e);
+    }
+    Ok(Some(records))
+}
+
+fn write_cache(cache_dir: &Path, cache_path: &Path, entry: &Value) -> Result<()> {
+    fs::create_dir_all(cache_dir)?;
+    let mut tmp = NamedTempFile::new_in(cache_dir)?;
+    tmp.write_all(entry.to_string().as_bytes())?;
+    tmp.persist(cache_path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Load the packages a local channel provides for `platform`, including `noarch` packages.
+///
+/// Each subdir is read from its `repodata.json`, or `current_repodata.json`, plus any package
+/// files they don't list.
+pub fn load_channel(
+    channel: &str,
+    platform: Platform,
+    cache_dir: Option<&Path>,
+) -> Result<Vec<PackageRecord>> {
+    let path = channel_path(channel).ok_or_else(|| {
+        Error::BadRepodata(format!("{} is not a local channel", channel))
+    })?;
+    let url = channel_url(&path);
+    let mut records = Vec::new();
+    let mut found = false;
+    for subdir in &[platform.subdir(), "noarch"] {
+        if let Some(subdir_records) = load_subdir(&path.join(subdir), &url, subdir, cache_dir)? {
+            found = true;
+            records.extend(subdir_records);
+        }
+    }
+    if !found {
+        let msg = format!("{} has no packages for {} or noarch", channel, platform);
+        return Err(Error::BadRepodata(msg));
+    }
+    Ok(records)
+}
+
+/// The packages available from several channels.
+///
+/// Channels are strictly prioritized: a package found in an earlier channel hides that
+/// package in later ones, unless a match spec asks for a channel by name.
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+    records: Vec<PackageRecord>,
+    /// For each package name, its records in order of preference: by channel priority, then
+    /// newest first.
+    by_name: HashMap<String, Vec<usize>>,
+}
+
+impl Index {
+    /// Index `records`, which are in channel priority order.
+    pub fn new(records: Vec<PackageRecord>) -> Index {
+        let mut priorities: HashMap<&str, usize> = HashMap::new();
+        for record in &records {
+            let next = priorities.len();
+            priorities.entry(&record.channel).or_insert(next);
+        }
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            by_name.entry(record.name.clone()).or_default().push(i);
+        }
+        for indices in by_name.values_mut() {
+            indices.sort_by(|&a, &b| {
+                let (a, b) = (&records[a], &records[b]);
+                let version = |r: &PackageRecord| CondaVersion::parse(&r.version).ok();
+                priorities[a.channel.as_str()]
+                    .cmp(&priorities[b.channel.as_str()])
+                    .then_with(|| version(b).cmp(&version(a)))
+                    .then(b.build_number.cmp(&a.build_number))
+                    .then(b.timestamp.cmp(&a.timestamp))
+            });
+        }
+        Index { records, by_name }
+    }
+
+    /// Load local channels, highest priority first, caching parsed repodata in `cache_dir`.
+    pub fn load(
+        channels: &[String],
+        platform: Platform,
+        cache_dir: Option<&Path>,
+    ) -> Result<Index> {
+        let mut records = Vec::new();
+        for channel in channels {
+            records.extend(load_channel(channel, platform, cache_dir)?);
+        }
+        Ok(Index::new(records))
+    }
+
+    /// Every record, in channel priority order.
+    pub fn records(&self) -> &[PackageRecord] {
+        &self.records
+    }
+
+    /// The records of `name` from the highest priority channel that has it, newest first.
+    pub fn by_name(&self, name: &str) -> Vec<&PackageRecord> {
+        let mut records = self.all_by_name(name);
+        if let Some(channel) = records.first().map(|record| record.channel.clone()) {
+            records.retain(|record| record.channel == channel);
+        }
+        records
+    }
+
+    /// The newest record of `name`, from the highest priority channel that has it.
+    pub fn latest(&self, name: &str) -> Option<&PackageRecord> {
+        self.by_name(name).into_iter().next()
+    }
+
+    /// The records matching `spec`, most preferred first.
+    pub fn query(&self, spec: &MatchSpec) -> Vec<&PackageRecord> {
+        let candidates = if spec.channel.is_some() {
+            self.all_by_name(&spec.name)
+        } else {
+            self.by_name(&spec.name)
+        };
+        candidates.into_iter().filter(|record| record.matches(spec)).collect()
+    }
+
+    fn all_by_name(&self, name: &str) -> Vec<&PackageRecord> {
+        self.by_name
+            .get(name)
+            .map(|indices| indices.iter().map(|&i| &self.records[i]).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPODATA: &str = r#"{
+      "info": {"subdir": "linux-64"},
+      "packages": {
+        "numpy-1.15.4-py37_0.tar.bz2": {
+          "name": "numpy", "version": "1.15.4", "build": "py37_0", "build_number": 0,
+          "depends": ["python >=3.7,<3.8.0a0"], "md5": "0123", "timestamp": 1540000000
+        }
+      },
+      "packages.conda": {
+        "python-3.7.1-h0371630_7.conda": {
+          "name": "python", "version": "3.7.1", "build": "h0371630_7", "build_number": 7,
+          "depends": [], "constrains": ["python_abi 3.7.* *_cp37m"]
+        }
+      }
+    }"#;
+
+    #[test]
+    fn parse() {
+        let records = parse_repodata(REPODATA, "file:///srv/chan", "linux-64").unwrap();
+        assert_eq!(records.len(), 2);
+        let numpy = &records[0];
+        assert_eq!(numpy.spec(), "numpy=1.15.4=py37_0");
+        assert_eq!(numpy.depends, ["python >=3.7,<3.8.0a0"]);
+        assert_eq!(numpy.url(), "file:///srv/chan/linux-64/numpy-1.15.4-py37_0.tar.bz2");
+        assert_eq!(records[1].filename, "python-3.7.1-h0371630_7.conda");
+        assert_eq!(records[1].constrains, ["python_abi 3.7.* *_cp37m"]);
+    }
+
+    #[test]
+    fn matching() {
+        let records = parse_repodata(REPODATA, "file:///srv/chan", "linux-64").unwrap();
+        let numpy = &records[0];
+        let matches = |spec: &str| numpy.matches(&MatchSpec::parse(spec).unwrap());
+        assert!(matches("numpy"));
+        assert!(matches("numpy>=1.15"));
+        assert!(matches("numpy 1.15.* py37*"));
+        assert!(matches("chan::numpy"));
+        assert!(!matches("numpy<1.15"));
+        assert!(!matches("numpy * py36*"));
+        assert!(!matches("conda-forge::numpy"));
+        assert!(!matches("scipy"));
+    }
+
+    #[test]
+    fn channel_paths() {
+        assert_eq!(channel_path("file:///srv/chan"), Some(PathBuf::from("/srv/chan")));
+        assert_eq!(channel_path("./chan"), Some(PathBuf::from("./chan")));
+        assert_eq!(channel_path("conda-forge"), None);
+        assert_eq!(channel_path("https://conda.anaconda.org/conda-forge"), None);
+        assert_eq!(channel_name("https://conda.anaconda.org/conda-forge/"), "conda-forge");
+    }
+
+    fn record(channel: &str, name: &str, version: &str) -> PackageRecord {
+        let mut record = parse_repodata(REPODATA, channel, "linux-64").unwrap().remove(0);
+        record.name = name.to_string();
+        record.version = version.to_string();
+        record
+    }
+
+    #[test]
+    fn index_priority() {
+        let index = Index::new(vec![
+            record("file:///a", "numpy", "1.15.4"),
+            record("file:///a", "numpy", "1.16.0"),
+            record("file:///b", "numpy", "1.17.0"),
+            record("file:///b", "scipy", "1.2.0"),
+        ]);
+        let versions = |records: Vec<&PackageRecord>| -> Vec<String> {
+            records.iter().map(|r| r.version.clone()).collect()
+        };
+        assert_eq!(versions(index.by_name("numpy")), ["1.16.0", "1.15.4"]);
+        assert_eq!(index.latest("scipy").unwrap().version, "1.2.0");
+        assert!(index.latest("pandas").is_none());
+        let query = |spec: &str| versions(index.query(&MatchSpec::parse(spec).unwrap()));
+        assert_eq!(query("numpy<1.16"), ["1.15.4"]);
+        assert!(query("numpy>=1.17").is_empty());
+        assert_eq!(query("b::numpy"), ["1.17.0"]);
+    }
+
+    #[test]
+    fn local_channel_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let linux = dir.path().join("linux-64");
+        fs::create_dir(&linux).unwrap();
+        fs::write(linux.join("current_repodata.json"), REPODATA).unwrap();
+        fs::write(linux.join("numpy-1.15.4-py37_0.tar.bz2"), b"listed").unwrap();
+        fs::write(linux.join("scipy-1.2.0-py37_3.conda"), b"unlisted").unwrap();
+
+        let channel = dir.path().to_str().unwrap();
+        let records = load_channel(channel, Platform::Linux64, None).unwrap();
+        assert_eq!(records.len(), 3);
+        let scipy = &records[2];
+        assert_eq!(scipy.spec(), "scipy=1.2.0=py37_3");
+        assert_eq!(scipy.build_number, 3);
+        assert_eq!(scipy.size, Some(8));
+        assert_eq!(scipy.md5.as_deref(), Some("075317898e2c554f4b766599472489c7"));
+        assert!(load_channel(channel, Platform::Osx64, None).is_err());
+    }
+
+    #[test]
+    fn cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let linux = dir.path().join("channel").join("linux-64");
+        fs::create_dir_all(&linux).unwrap();
+        fs::write(linux.join("repodata.json"), REPODATA).unwrap();
+
+        let channel = dir.path().join("channel");
+        let channel = channel.to_str().unwrap();
+        let load = || load_channel(channel, Platform::Linux64, Some(&cache_dir)).unwrap();
+        let records = load();
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 1);
+        assert_eq!(load(), records);
+
+        // A changed channel isn't served from the cache.
+        fs::write(linux.join("repodata.json"), r#"{"packages": {}}"#).unwrap();
+        assert!(load().is_empty());
+    }
+}
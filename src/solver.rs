@@ -0,0 +1,264 @@
+//! A small backtracking solver over channel repodata, for freezing without conda.
+//!
+//! Candidates for each package are tried in the index's order of preference: channel
+//! priority, then newest first.  That is conda's preference order too, so for simple
+//! environments the solution is the one conda would pick.
+
+use std::collections::BTreeMap;
+
+use conda_version::CondaVersion;
+use matchspec::MatchSpec;
+use platform::{Platform, VirtualPackage};
+use repodata::{Index, PackageRecord};
+use {Error, Result};
+
+/// Give up rather than search forever on hard problems.
+const MAX_STEPS: usize = 100_000;
+
+struct Solver<'a> {
+    index: &'a Index,
+    platform: Platform,
+    virtual_packages: &'a [VirtualPackage],
+    steps: usize,
+    /// A spec nothing in the channels provides, for the error message.
+    missing: Option<String>,
+}
+
+/// Pick a version of every package needed to satisfy `specs`.
+///
+/// Virtual packages (`__glibc` and friends) are taken from `virtual_packages`, or else assumed
+/// from `platform`.  The solution is sorted by name.
+pub fn solve(
+    index: &Index,
+    specs: &[MatchSpec],
+    platform: Platform,
+    virtual_packages: &[VirtualPackage],
+) -> Result<Vec<PackageRecord>> {
+    let mut solver = Solver {
+        index,
+        platform,
+        virtual_packages,
+        steps: 0,
+        missing: None,
+    };
+    // Pending specs are a stack; reverse so they're handled in the order given.
+    let mut pending: Vec<MatchSpec> = specs.iter().rev().cloned().collect();
+    let mut chosen = BTreeMap::new();
+    if solver.search(&mut pending, &mut chosen) {
+        return Ok(chosen.into_values().cloned().collect());
+    }
+
+    let requested: Vec<String> = specs.iter().map(|s| s.to_string()).collect();
+    let msg = if solver.steps > MAX_STEPS {
+        format!("gave up after {} steps solving {}", MAX_STEPS, requested.join(", "))
+    } else if let Some(missing) = solver.missing {
+        format!("nothing provides {} (solving {})", missing, requested.join(", "))
+    } else {
+        format!("conflicting requirements in {}", requested.join(", "))
+    };
+    Err(Error::Unsatisfiable(msg))
+}
+
+impl<'a> Solver<'a> {
+    /// Satisfy every pending spec, extending `chosen`.  On failure both are left as they were.
+    fn search(
+        &mut self,
+        pending: &mut Vec<MatchSpec>,
+        chosen: &mut BTreeMap<&'a str, &'a PackageRecord>,
+    ) -> bool {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return false;
+        }
+        let spec = match pending.pop() {
+            Some(spec) => spec,
+            None => return true,
+        };
+
+        let satisfied = if spec.name.starts_with("__") {
+            Some(self.virtual_package_matches(&spec))
+        } else {
+            chosen.get(spec.name.as_str()).map(|record| record.matches(&spec))
+        };
+        if let Some(satisfied) = satisfied {
+            if satisfied && self.search(pending, chosen) {
+                return true;
+            }
+            pending.push(spec);
+            return false;
+        }
+
+        let candidates = self.index.query(&spec);
+        if candidates.is_empty() && self.missing.is_none() {
+            self.missing = Some(spec.to_string());
+        }
+        for candidate in candidates {
+            let depends = match parse_specs(&candidate.depends) {
+                Some(depends) if self.compatible(candidate, chosen) => depends,
+                _ => continue,
+            };
+            let depth = pending.len();
+            pending.extend(depends.into_iter().rev());
+            chosen.insert(&candidate.name, candidate);
+            if self.search(pending, chosen) {
+                return true;
+            }
+            chosen.remove(candidate.name.as_str());
+            pending.truncate(depth);
+        }
+        pending.push(spec);
+        false
+    }
+
+    /// Check `candidate` against the `constrains` of what's chosen, and vice versa.
+    fn compatible(
+        &self,
+        candidate: &PackageRecord,
+        chosen: &BTreeMap<&'a str, &'a PackageRecord>,
+    ) -> bool {
+        let constrains = match parse_specs(&candidate.constrains) {
+            Some(constrains) => constrains,
+            None => return false,
+        };
+        let satisfies_candidate = constrains.iter().all(|constraint| {
+            chosen
+                .get(constraint.name.as_str())
+                .is_none_or(|record| record.matches(constraint))
+        });
+        satisfies_candidate
+            && chosen.values().all(|record| {
+                parse_specs(&record.constrains).is_some_and(|constrains| {
+                    constrains
+                        .iter()
+                        .filter(|constraint| constraint.name == candidate.name)
+                        .all(|constraint| candidate.matches(constraint))
+                })
+            })
+    }
+
+    fn virtual_package_matches(&self, spec: &MatchSpec) -> bool {
+        let declared = self.virtual_packages.iter().find(|p| p.name == spec.name);
+        if let Some(package) = declared {
+            if package.name == "__unix" || package.name == "__win" {
+                return true;
+            }
+            if package.version.is_empty() {
+                // Declared absent.
+                return false;
+            }
+            let version = CondaVersion::parse(&package.version);
+            return match (spec.version_spec(), version) {
+                (None, _) => true,
+                (Some(version_spec), Ok(ref version)) => version_spec.matches(version),
+                (Some(_), Err(_)) => false,
+            };
+        }
+        // Undeclared: assume what every machine of the platform has.
+        match spec.name.as_str() {
+            "__unix" => self.platform != Platform::Win64,
+            "__win" => self.platform == Platform::Win64,
+            "__glibc" => self.platform.is_linux(),
+            "__osx" => self.platform.is_osx(),
+            _ => false,
+        }
+    }
+}
+
+fn parse_specs(specs: &[String]) -> Option<Vec<MatchSpec>> {
+    let mut parsed = Vec::new();
+    for spec in specs {
+        match MatchSpec::parse(spec) {
+            Ok(spec) => parsed.push(spec),
+            Err(e) => {
+                warn!("Skipping package with {}", e);
+                return None;
+            }
+        }
+    }
+    Some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        channel: &str,
+        name: &str,
+        version: &str,
+        build_number: u64,
+        depends: &[&str],
+    ) -> PackageRecord {
+        PackageRecord {
+            name: name.to_string(),
+            version: version.to_string(),
+            build: format!("h_{}", build_number),
+            build_number,
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            constrains: Vec::new(),
+            channel: channel.to_string(),
+            subdir: "linux-64".to_string(),
+            filename: format!("{}-{}-h_{}.tar.bz2", name, version, build_number),
+            md5: None,
+            sha256: None,
+            size: None,
+            timestamp: 0,
+        }
+    }
+
+    fn records() -> Vec<PackageRecord> {
+        vec![
+            record("file:///a", "python", "3.6.8", 0, &[]),
+            record("file:///a", "python", "3.7.3", 0, &["__glibc >=2.17"]),
+            record("file:///a", "numpy", "1.15.4", 0, &["python >=3.6,<3.7.0a0"]),
+            record("file:///a", "numpy", "1.16.0", 0, &["python >=3.7,<3.8.0a0"]),
+            record("file:///a", "numpy", "1.16.0", 1, &["python >=3.7,<3.8.0a0"]),
+            record("file:///b", "numpy", "1.17.0", 0, &["python"]),
+            record("file:///b", "pandas", "0.24.0", 0, &["numpy >=1.16"]),
+        ]
+    }
+
+    fn solved(specs: &[&str]) -> Result<Vec<String>> {
+        solved_with(specs, &[])
+    }
+
+    fn solved_with(specs: &[&str], virtual_packages: &[VirtualPackage]) -> Result<Vec<String>> {
+        let specs: Vec<MatchSpec> = specs.iter().map(|s| MatchSpec::parse(s).unwrap()).collect();
+        let solution = solve(&Index::new(records()), &specs, Platform::Linux64, virtual_packages)?;
+        Ok(solution.iter().map(PackageRecord::spec).collect())
+    }
+
+    #[test]
+    fn newest_compatible() {
+        // numpy 1.17 is in a lower priority channel.
+        assert_eq!(solved(&["numpy"]).unwrap(), ["numpy=1.16.0=h_1", "python=3.7.3=h_0"]);
+        assert_eq!(
+            solved(&["python=3.6", "numpy"]).unwrap(),
+            ["numpy=1.15.4=h_0", "python=3.6.8=h_0"]
+        );
+        assert_eq!(
+            solved(&["pandas"]).unwrap(),
+            ["numpy=1.16.0=h_1", "pandas=0.24.0=h_0", "python=3.7.3=h_0"]
+        );
+    }
+
+    #[test]
+    fn virtual_packages() {
+        let old_glibc = [VirtualPackage::new("__glibc", "2.12", Platform::Linux64).unwrap()];
+        assert_eq!(
+            solved_with(&["python"], &old_glibc).unwrap(),
+            ["python=3.6.8=h_0"]
+        );
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        match solved(&["scipy"]) {
+            Err(Error::Unsatisfiable(msg)) => {
+                assert!(msg.contains("nothing provides scipy"), "{}", msg)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(solved(&["python=3.6", "pandas"]).is_err());
+    }
+}
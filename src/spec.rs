@@ -0,0 +1,437 @@
+//! The contents of conda environment files.
+//!
+//! Depfiles and lockfiles share the format written by `conda env export`:
+//!
+//! ```yaml
+//! name: myenv
+//! channels:
+//!   - defaults
+//! dependencies:
+//!   - python=3.6
+//!   - pip:
+//!     - requests==2.19.1
+//! variables:
+//!   SOME_VAR: value
+//! prefix: /opt/conda/envs/myenv
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+use yaml_rust::{Yaml, YamlLoader};
+
+use matchspec::{MatchSpec, MatchSpecError};
+use pip::{PipLine, PipLineError};
+
+/// A parsed environment file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvSpec {
+    pub name: String,
+    pub channels: Vec<String>,
+    /// Conda dependencies, as written.
+    pub conda_deps: Vec<String>,
+    /// Entries of the `pip:` section of the dependencies, as written.
+    pub pip_deps: Vec<String>,
+    /// Environment variables set on activation, in file order.
+    pub variables: Vec<(String, String)>,
+    pub prefix: Option<String>,
+}
+
+/// The requested environment, as read from a depfile.
+pub type DepSpec = EnvSpec;
+
+/// The resolved environment, as read from a lockfile.
+pub type LockSpec = EnvSpec;
+
+/// Why an environment file could not be parsed, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// 1-based line.
+    pub line: usize,
+    /// 1-based column.
+    pub col: usize,
+    pub msg: String,
+}
+
+impl ParseError {
+    fn at(mark: Marker, msg: String) -> ParseError {
+        ParseError {
+            line: mark.line(),
+            col: mark.col() + 1,
+            msg,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} column {}: {}", self.line, self.col, self.msg)
+    }
+}
+
+impl EnvSpec {
+    /// Parse the first document of an environment file.
+    pub fn parse(data: &str) -> Result<EnvSpec, ParseError> {
+        let root = load_first_node(data)?;
+        let entries = match root.value {
+            Value::Map(entries) => entries,
+            _ => return Err(ParseError::at(root.mark, "expected a mapping".to_string())),
+        };
+
+        let mut spec = EnvSpec::default();
+        let mut name = None;
+        for (key, value) in entries {
+            let key_mark = key.mark;
+            let key_name = key.scalar("key")?;
+            match key_name.as_str() {
+                "name" => name = Some(value.scalar("name")?),
+                "channels" => {
+                    for channel in value.seq("channels")? {
+                        spec.channels.push(channel.scalar("channel")?);
+                    }
+                }
+                "dependencies" => {
+                    for dep in value.seq("dependencies")? {
+                        spec.parse_dependency(dep)?;
+                    }
+                }
+                "variables" => {
+                    for (var, var_value) in value.map("variables")? {
+                        let var = var.scalar("variable name")?;
+                        let var_value = match var_value.value {
+                            Value::Null => String::new(),
+                            _ => var_value.scalar("variable value")?,
+                        };
+                        spec.variables.push((var, var_value));
+                    }
+                }
+                "prefix" => spec.prefix = value.opt_scalar("prefix")?,
+                _ => {
+                    return Err(ParseError::at(
+                        key_mark,
+                        format!("unexpected key {:?}", key_name),
+                    ))
+                }
+            }
+        }
+        spec.name = match name {
+            Some(name) => name,
+            None => return Err(ParseError::at(root.mark, "missing name".to_string())),
+        };
+        Ok(spec)
+    }
+
+    fn parse_dependency(&mut self, dep: Node) -> Result<(), ParseError> {
+        let mark = dep.mark;
+        match dep.value {
+            Value::Scalar(conda_dep) => {
+                MatchSpec::parse(&conda_dep).map_err(|e| ParseError::at(mark, e.to_string()))?;
+                self.conda_deps.push(conda_dep)
+            }
+            Value::Map(entries) => {
+                for (key, value) in entries {
+                    let key_mark = key.mark;
+                    let key_name = key.scalar("key")?;
+                    if key_name != "pip" {
+                        return Err(ParseError::at(
+                            key_mark,
+                            format!("unexpected dependency section {:?}", key_name),
+                        ));
+                    }
+                    for pip_dep in value.seq("pip dependencies")? {
+                        let pip_mark = pip_dep.mark;
+                        let pip_dep = pip_dep.scalar("pip dependency")?;
+                        PipLine::parse(&pip_dep).map_err(|e| ParseError::at(pip_mark, e.to_string()))?;
+                        self.pip_deps.push(pip_dep);
+                    }
+                }
+            }
+            _ => {
+                return Err(ParseError::at(
+                    mark,
+                    "expected a dependency".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// The conda dependencies as match specs.
+    pub fn conda_specs(&self) -> Result<Vec<MatchSpec>, MatchSpecError> {
+        self.conda_deps.iter().map(|dep| MatchSpec::parse(dep)).collect()
+    }
+
+    /// The lines of the pip section, parsed.
+    pub fn pip_lines(&self) -> Result<Vec<PipLine>, PipLineError> {
+        self.pip_deps.iter().map(|dep| PipLine::parse(dep)).collect()
+    }
+
+    /// Serialize in the layout written by `conda env export`.
+    pub fn emit(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!("name: {}\n", quote(&self.name)));
+        if !self.channels.is_empty() {
+            out.push_str("channels:\n");
+            for channel in &self.channels {
+                out.push_str(&format!("  - {}\n", quote(channel)));
+            }
+        }
+        if !self.conda_deps.is_empty() || !self.pip_deps.is_empty() {
+            out.push_str("dependencies:\n");
+            for dep in &self.conda_deps {
+                out.push_str(&format!("  - {}\n", quote(dep)));
+            }
+            if !self.pip_deps.is_empty() {
+                out.push_str("  - pip:\n");
+                for dep in &self.pip_deps {
+                    out.push_str(&format!("    - {}\n", quote(dep)));
+                }
+            }
+        }
+        if !self.variables.is_empty() {
+            out.push_str("variables:\n");
+            for (var, value) in &self.variables {
+                out.push_str(&format!("  {}: {}\n", quote(var), quote(value)));
+            }
+        }
+        if let Some(ref prefix) = self.prefix {
+            out.push_str(&format!("prefix: {}\n", quote(prefix)));
+        }
+        out
+    }
+}
+
+/// Quote `s` if it would not read back as the same string when written plainly.
+pub fn quote(s: &str) -> String {
+    let plain_ok = !s.is_empty()
+        && !s.contains('\n')
+        && s.trim() == s
+        && match YamlLoader::load_from_str(s) {
+            Ok(ref docs) if docs.len() == 1 => docs[0] == Yaml::String(s.to_string()),
+            _ => false,
+        };
+    if plain_ok {
+        return s.to_string();
+    }
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// A YAML node that remembers where it came from.
+#[derive(Debug, Clone)]
+struct Node {
+    mark: Marker,
+    value: Value,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Null,
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+}
+
+impl Node {
+    fn scalar(self, what: &str) -> Result<String, ParseError> {
+        match self.value {
+            Value::Scalar(s) => Ok(s),
+            _ => Err(ParseError::at(self.mark, format!("expected {} to be a string", what))),
+        }
+    }
+
+    fn opt_scalar(self, what: &str) -> Result<Option<String>, ParseError> {
+        match self.value {
+            Value::Null => Ok(None),
+            _ => self.scalar(what).map(Some),
+        }
+    }
+
+    // An empty section is treated as an empty list.
+    fn seq(self, what: &str) -> Result<Vec<Node>, ParseError> {
+        match self.value {
+            Value::Null => Ok(Vec::new()),
+            Value::Seq(items) => Ok(items),
+            _ => Err(ParseError::at(self.mark, format!("expected {} to be a list", what))),
+        }
+    }
+
+    fn map(self, what: &str) -> Result<Vec<(Node, Node)>, ParseError> {
+        match self.value {
+            Value::Null => Ok(Vec::new()),
+            Value::Map(entries) => Ok(entries),
+            _ => Err(ParseError::at(self.mark, format!("expected {} to be a mapping", what))),
+        }
+    }
+}
+
+enum Frame {
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>, Option<Node>),
+}
+
+/// Builds a tree of `Node`s from parser events.
+#[derive(Default)]
+struct TreeBuilder {
+    stack: Vec<(Marker, usize, Frame)>,
+    anchors: HashMap<usize, Node>,
+    docs: Vec<Node>,
+}
+
+impl TreeBuilder {
+    fn insert(&mut self, node: Node, anchor_id: usize) {
+        if anchor_id > 0 {
+            self.anchors.insert(anchor_id, node.clone());
+        }
+        match self.stack.last_mut() {
+            None => self.docs.push(node),
+            Some(&mut (_, _, Frame::Seq(ref mut items))) => items.push(node),
+            Some(&mut (_, _, Frame::Map(ref mut entries, ref mut key))) => match key.take() {
+                None => *key = Some(node),
+                Some(key) => entries.push((key, node)),
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for TreeBuilder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, style, anchor_id, _) => {
+                let is_null = style == TScalarStyle::Plain
+                    && (value == "~" || value == "null" || value.is_empty());
+                let value = if is_null {
+                    Value::Null
+                } else {
+                    Value::Scalar(value)
+                };
+                self.insert(Node { mark, value }, anchor_id);
+            }
+            Event::SequenceStart(anchor_id) => {
+                self.stack.push((mark, anchor_id, Frame::Seq(Vec::new())))
+            }
+            Event::MappingStart(anchor_id) => {
+                self.stack
+                    .push((mark, anchor_id, Frame::Map(Vec::new(), None)))
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((mark, anchor_id, frame)) = self.stack.pop() {
+                    let value = match frame {
+                        Frame::Seq(items) => Value::Seq(items),
+                        Frame::Map(entries, _) => Value::Map(entries),
+                    };
+                    self.insert(Node { mark, value }, anchor_id);
+                }
+            }
+            Event::Alias(anchor_id) => {
+                let node = match self.anchors.get(&anchor_id) {
+                    Some(node) => node.clone(),
+                    None => Node {
+                        mark,
+                        value: Value::Null,
+                    },
+                };
+                self.insert(node, 0);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn load_first_node(data: &str) -> Result<Node, ParseError> {
+    let mut builder = TreeBuilder::default();
+    let mut parser = Parser::new(data.chars());
+    if let Err(e) = parser.load(&mut builder, false) {
+        // The scanner's message already ends with the location; don't repeat it.
+        let msg = e.to_string();
+        let msg = match msg.rfind(" at line ") {
+            Some(idx) => msg[..idx].to_string(),
+            None => msg,
+        };
+        return Err(ParseError::at(*e.marker(), msg));
+    }
+    match builder.docs.into_iter().next() {
+        Some(node) => Ok(node),
+        None => Err(ParseError {
+            line: 1,
+            col: 1,
+            msg: "empty document".to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORTED: &str = "name: foo
+channels:
+  - defaults
+dependencies:
+  - python=3.6.6=hc3d631a_0
+  - pip:
+    - requests==2.19.1
+variables:
+  FOO: bar
+prefix: /opt/conda/envs/foo
+";
+
+    #[test]
+    fn parse_all_fields() {
+        let spec = EnvSpec::parse(EXPORTED).unwrap();
+        assert_eq!(spec.name, "foo");
+        assert_eq!(spec.channels, ["defaults"]);
+        assert_eq!(spec.conda_deps, ["python=3.6.6=hc3d631a_0"]);
+        assert_eq!(spec.pip_deps, ["requests==2.19.1"]);
+        assert_eq!(spec.variables, [("FOO".to_string(), "bar".to_string())]);
+        assert_eq!(spec.prefix, Some("/opt/conda/envs/foo".to_string()));
+    }
+
+    #[test]
+    fn emit_round_trips() {
+        let spec = EnvSpec::parse(EXPORTED).unwrap();
+        assert_eq!(spec.emit(), EXPORTED);
+
+        let awkward = EnvSpec {
+            name: "yes".to_string(),
+            variables: vec![("A".to_string(), "x: y # z".to_string())],
+            ..EnvSpec::default()
+        };
+        assert_eq!(EnvSpec::parse(&awkward.emit()).unwrap(), awkward);
+    }
+
+    #[test]
+    fn unexpected_dependency_section() {
+        let err = EnvSpec::parse("name: foo\ndependencies:\n  - python\n  - npm:\n    - left-pad\n")
+            .unwrap_err();
+        assert_eq!((err.line, err.col), (4, 5));
+    }
+
+    #[test]
+    fn invalid_match_spec() {
+        let err = EnvSpec::parse("name: foo\ndependencies:\n  - python\n  - \">=3.6\"\n").unwrap_err();
+        assert_eq!((err.line, err.col), (4, 5));
+    }
+
+    #[test]
+    fn invalid_pip_requirement() {
+        let err = EnvSpec::parse("name: foo\ndependencies:\n  - pip:\n    - requests=2\n").unwrap_err();
+        assert_eq!((err.line, err.col), (4, 7));
+    }
+
+    #[test]
+    fn missing_name() {
+        assert!(EnvSpec::parse("dependencies:\n  - python\n").is_err());
+    }
+
+    #[test]
+    fn malformed_yaml() {
+        let err = EnvSpec::parse("name: foo\ndependencies: [python\n").unwrap_err();
+        assert_eq!(err.line, 3);
+    }
+}
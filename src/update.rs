@@ -0,0 +1,324 @@
+//! Upgrading a few packages of a lockfile while holding the rest where they are.
+//!
+//! The lockfile's packages are added to the depfile as extra constraints, and the result is
+//! frozen like any other depfile.  The new lockfile records the hash of the real depfile.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::prelude::*;
+use std::path::Path;
+
+use tempfile::{Builder, NamedTempFile};
+
+use depfile::Depfile;
+use diff::{diff_specs, LockDiff};
+use freeze::{freeze_platform, FreezeMethod, FreezeOptions};
+use lockfile::{rewrite_sigil_hash, LockFormat, Lockfile, StagedLockfile};
+use matchspec::MatchSpec;
+use pip::{normalize_name, Requirement};
+use platform::Platform;
+use {Error, Result};
+
+/// How tightly the packages that weren't asked for are held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Hold {
+    /// At their locked version and build.
+    Builds,
+    /// At their locked version, in any build.
+    Versions,
+    /// Not at all: the whole environment is frozen afresh.
+    Nothing,
+}
+
+impl fmt::Display for Hold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hold = match *self {
+            Hold::Builds => "locked builds",
+            Hold::Versions => "locked versions",
+            Hold::Nothing => "nothing",
+        };
+        write!(f, "{}", hold)
+    }
+}
+
+/// What `update_lockfile` changed, and what it had to give up to do so.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateReport {
+    pub held: Hold,
+    /// Why each tighter hold failed, tightest first.
+    pub fallbacks: Vec<(Hold, String)>,
+    pub changes: LockDiff,
+    /// Packages that changed without being asked for.
+    pub unrequested: Vec<String>,
+}
+
+/// The constraint holding a locked conda package, e.g. `numpy ==1.15.4 py37_0`.
+fn hold_spec(locked: &MatchSpec, hold: Hold) -> Option<String> {
+    let version = locked.locked_version()?;
+    let build = locked.build.as_ref();
+    match (hold, build) {
+        (Hold::Builds, Some(build)) => Some(format!("{} =={} {}", locked.name, version, build)),
+        (Hold::Builds, None) | (Hold::Versions, _) => {
+            Some(format!("{} =={}", locked.name, version))
+        }
+        (Hold::Nothing, _) => None,
+    }
+}
+
+/// The constraint moving a requested conda package past its locked version.
+fn upgrade_spec(locked: &MatchSpec) -> Option<String> {
+    let version = locked.locked_version()?;
+    Some(format!("{} >{}", locked.name, version))
+}
+
+/// The requirement holding a locked pip package, e.g. `requests==2.21.0`.  Packages locked to
+/// a URL rather than a version aren't held.
+fn pip_hold_spec(locked: &Requirement, hold: Hold) -> Option<String> {
+    let name = locked.name.as_ref()?;
+    let version = locked.specifier.as_ref()?.strip_prefix("==")?;
+    match hold {
+        Hold::Builds | Hold::Versions => Some(format!("{}=={}", name, version)),
+        Hold::Nothing => None,
+    }
+}
+
+/// A copy of the depfile, next to it so includes still resolve, with the extra conda and pip
+/// specs.
+fn held_depfile(
+    depfile: &Depfile,
+    holds: &[String],
+    pip_holds: &[String],
+) -> Result<NamedTempFile> {
+    let mut spec = depfile.spec.clone();
+    spec.conda_deps.extend(holds.iter().cloned());
+    spec.pip_deps.extend(pip_holds.iter().cloned());
+    let dir = match depfile.path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // conda only reads environment files named like YAML.
+    let mut tmp_depfile = Builder::new().suffix(".yml").tempfile_in(dir)?;
+    tmp_depfile.write_all(spec.emit().as_bytes())?;
+    tmp_depfile.flush()?;
+    Ok(tmp_depfile)
+}
+
+/// Re-freeze the lockfile at `lockfile_path` for `platform`, letting only `packages` change.
+///
+/// Every other conda package is held at its locked build, or failing that its locked version,
+/// or failing that not held at all; the report says which.  While anything is held, the
+/// requested conda packages must move past their locked versions.  Pip packages are held at
+/// their locked versions too when installing; the other methods don't resolve pip packages.
+///
+/// Only a hold that can't be satisfied moves on to the next; any other failure, like conda
+/// being unable to reach a channel, is returned as it is.
+///
+/// The lockfile keeps its format, hash mode and virtual packages, and is only replaced once
+/// the new one has been frozen.  It is left alone if even a fresh freeze wouldn't change the
+/// requested packages.
+pub fn update_lockfile(
+    depfile_path: &Path,
+    lockfile_path: &Path,
+    platform: Platform,
+    packages: &[String],
+    method: FreezeMethod,
+) -> Result<UpdateReport> {
+    let depfile = Depfile::open(depfile_path)?;
+    let lockfile = Lockfile::open_platform(lockfile_path, platform)?;
+    if lockfile.format == LockFormat::CondaLock {
+        let msg = format!("{}: update conda-lock lockfiles with freeze", lockfile_path.display());
+        return Err(Error::BadLockfile(msg));
+    }
+    let bad_lockfile =
+        |e: String| Error::BadLockfile(format!("{}: {}", lockfile_path.display(), e));
+    let locked = lockfile.spec.conda_specs().map_err(|e| bad_lockfile(e.to_string()))?;
+    let locked_pip: Vec<Requirement> = lockfile
+        .spec
+        .pip_lines()
+        .map_err(|e| bad_lockfile(e.to_string()))?
+        .iter()
+        .filter_map(|line| line.requirement().cloned())
+        .collect();
+    // Conda names are lowercase; pip names are compared PEP 503 normalized.
+    let is_requested_conda = |name: &str| packages.iter().any(|p| p.to_lowercase() == name);
+    let is_requested_pip =
+        |name: &str| packages.iter().any(|p| normalize_name(p) == normalize_name(name));
+    for package in packages {
+        let is_locked = locked.iter().any(|ms| ms.name == package.to_lowercase())
+            || locked_pip
+                .iter()
+                .any(|req| req.normalized_name() == Some(normalize_name(package)));
+        if !is_locked {
+            return Err(bad_lockfile(format!("{} is not locked", package)));
+        }
+    }
+
+    let mut virtual_packages = BTreeMap::new();
+    virtual_packages.insert(platform, lockfile.header.virtual_packages.clone());
+    let options = FreezeOptions {
+        hash_mode: lockfile.header.hash.mode,
+        method,
+        format: lockfile.format,
+        virtual_packages,
+    };
+
+    let mut fallbacks = Vec::new();
+    for &hold in &[Hold::Builds, Hold::Versions, Hold::Nothing] {
+        let mut holds: Vec<String> = locked
+            .iter()
+            .filter(|ms| !is_requested_conda(&ms.name))
+            .filter_map(|ms| hold_spec(ms, hold))
+            .collect();
+        if hold != Hold::Nothing {
+            // Otherwise the requested packages could stay put to satisfy the holds.
+            let requested = locked.iter().filter(|ms| is_requested_conda(&ms.name));
+            holds.extend(requested.filter_map(upgrade_spec));
+        }
+        let pip_holds: Vec<String> = if method == FreezeMethod::Install {
+            locked_pip
+                .iter()
+                .filter(|req| !req.normalized_name().is_some_and(|name| is_requested_pip(&name)))
+                .filter_map(|req| pip_hold_spec(req, hold))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let tmp_depfile = held_depfile(&depfile, &holds, &pip_holds)?;
+        let staged_lockfile = StagedLockfile::new(lockfile_path)?;
+        let tmp_lockfile = staged_lockfile.path();
+        info!("Updating {} holding {}", lockfile_path.display(), hold);
+        match freeze_platform(tmp_depfile.path(), &tmp_lockfile, platform, &options) {
+            Ok(()) => {}
+            Err(e @ Error::Unsatisfiable(_)) if hold != Hold::Nothing => {
+                warn!("Could not hold {}: {}", hold, e);
+                fallbacks.push((hold, e.to_string()));
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+
+        // The lockfile was frozen from the held copy; record the real depfile.
+        rewrite_sigil_hash(&tmp_lockfile, &depfile.hash_like(&lockfile.header.hash)?)?;
+        let updated = Lockfile::open_platform(&tmp_lockfile, platform)?;
+        let changes = diff_specs(&lockfile.spec, &updated.spec)?;
+        let unrequested_conda = changes.conda.iter().filter(|c| !is_requested_conda(&c.name));
+        let unrequested_pip = changes.pip.iter().filter(|c| !is_requested_pip(&c.name));
+        let unrequested: Vec<String> = unrequested_conda
+            .chain(unrequested_pip)
+            .map(|change| change.name.clone())
+            .collect();
+        let upgraded = changes.conda.iter().chain(changes.pip.iter()).count()
+            > unrequested.len();
+        if hold == Hold::Nothing && !upgraded {
+            // Already as new as the depfile allows; don't churn everything else for nothing.
+            return Ok(UpdateReport {
+                held: hold,
+                fallbacks,
+                changes: LockDiff::default(),
+                unrequested: Vec::new(),
+            });
+        }
+        staged_lockfile.persist()?;
+        return Ok(UpdateReport {
+            held: hold,
+            fallbacks,
+            changes,
+            unrequested,
+        });
+    }
+    unreachable!("holding nothing either succeeds or returns its error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir, read_to_string, write};
+
+    use tempfile::tempdir;
+
+    use diff::ChangeKind;
+    use hash::HashMode;
+    use pip::PipLine;
+
+    const REPODATA: &str = r#"{"packages": {
+      "python-3.7.2-h0_0.tar.bz2":
+        {"name": "python", "version": "3.7.2", "build": "h0_0", "build_number": 0},
+      "python-3.7.3-h0_0.tar.bz2":
+        {"name": "python", "version": "3.7.3", "build": "h0_0", "build_number": 0},
+      "six-1.11.0-py_0.tar.bz2":
+        {"name": "six", "version": "1.11.0", "build": "py_0", "build_number": 0},
+      "six-1.12.0-py_0.tar.bz2":
+        {"name": "six", "version": "1.12.0", "build": "py_0", "build_number": 0},
+      "numpy-1.15.4-py37_0.tar.bz2":
+        {"name": "numpy", "version": "1.15.4", "build": "py37_0", "build_number": 0,
+         "depends": ["python >=3.7,<3.8.0a0"]},
+      "numpy-1.16.0-py37_0.tar.bz2":
+        {"name": "numpy", "version": "1.16.0", "build": "py37_0", "build_number": 0,
+         "depends": ["python >=3.7.3,<3.8.0a0"]}
+    }}"#;
+
+    #[test]
+    fn pip_holds() {
+        let req = |line: &str| PipLine::parse(line).unwrap().requirement().cloned().unwrap();
+        let requests = req("requests==2.21.0");
+        assert_eq!(pip_hold_spec(&requests, Hold::Versions).as_deref(), Some("requests==2.21.0"));
+        assert_eq!(pip_hold_spec(&requests, Hold::Nothing), None);
+        assert_eq!(pip_hold_spec(&req("six @ https://example.com/six.whl"), Hold::Builds), None);
+    }
+
+    #[test]
+    fn holds_other_packages() {
+        let dir = tempdir().unwrap();
+        let channel = dir.path().join("channel");
+        create_dir(&channel).unwrap();
+        create_dir(channel.join("linux-64")).unwrap();
+        write(channel.join("linux-64").join("repodata.json"), REPODATA).unwrap();
+        let depfile = dir.path().join("deps.yml");
+        write(
+            &depfile,
+            format!(
+                "name: foo\nchannels:\n  - file://{}\ndependencies:\n  - numpy\n  - six\n",
+                channel.display()
+            ),
+        ).unwrap();
+        let header = Depfile::open(&depfile).unwrap().lock_header(HashMode::Raw).unwrap();
+        let lockfile = dir.path().join("deps.yml.linux-64.lock");
+        write(
+            &lockfile,
+            format!(
+                "{}name: foo\ndependencies:\n  - numpy=1.15.4=py37_0\n  - python=3.7.2=h0_0\n  \
+                 - six=1.11.0=py_0\n",
+                header.emit()
+            ),
+        ).unwrap();
+
+        let update = |package: &str| {
+            let packages = [package.to_string()];
+            update_lockfile(&depfile, &lockfile, Platform::Linux64, &packages, FreezeMethod::Native)
+        };
+
+        // six upgrades alone.
+        let report = update("six").unwrap();
+        assert_eq!(report.held, Hold::Builds);
+        assert_eq!(report.changes.conda.len(), 1);
+        assert_eq!(report.changes.conda[0].kind, ChangeKind::Upgraded);
+        assert!(report.unrequested.is_empty());
+        let locked = read_to_string(&lockfile).unwrap();
+        assert!(locked.starts_with(&header.emit()), "{}", locked);
+        assert!(locked.contains("numpy=1.15.4=py37_0"), "{}", locked);
+
+        // numpy 1.16 needs a newer python, so nothing can be held.
+        let report = update("numpy").unwrap();
+        assert_eq!(report.held, Hold::Nothing);
+        assert_eq!(report.fallbacks.len(), 2);
+        assert_eq!(report.unrequested, ["python"]);
+
+        // Already the newest: nothing else is re-solved.
+        let before = read_to_string(&lockfile).unwrap();
+        let report = update("numpy").unwrap();
+        assert!(report.changes.is_empty());
+        assert_eq!(read_to_string(&lockfile).unwrap(), before);
+
+        assert!(update("pandas").is_err());
+    }
+}
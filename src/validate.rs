@@ -0,0 +1,313 @@
+//! Checking that a lockfile actually provides what its depfile asks for.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use conda_version::CondaVersion;
+use matchspec::{glob_matches, MatchSpec};
+use pep440::Version;
+use pip::{PipLine, Requirement};
+use spec::{DepSpec, LockSpec};
+use {Error, Result};
+
+/// A package whose locked version falls outside what the depfile asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub name: String,
+    /// The depfile entry, e.g. `numpy >=1.15`.
+    pub requested: String,
+    /// The lockfile entry, e.g. `numpy 1.10.4 py36_0`.
+    pub locked: String,
+}
+
+/// Everything wrong with a lockfile, compared to its depfile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Conda packages requested by the depfile but not locked.
+    pub missing_conda: Vec<String>,
+    /// Pip packages requested by the depfile but not locked, by normalized name.
+    pub missing_pip: Vec<String>,
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.missing_conda.is_empty() && self.missing_pip.is_empty() && self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_valid() {
+            return write!(f, "lockfile satisfies the depfile");
+        }
+        let mut lines = Vec::new();
+        if !self.missing_conda.is_empty() {
+            lines.push(format!("missing conda packages: {}", self.missing_conda.join(", ")));
+        }
+        if !self.missing_pip.is_empty() {
+            lines.push(format!("missing pip packages: {}", self.missing_pip.join(", ")));
+        }
+        for violation in &self.violations {
+            lines.push(format!("{} does not satisfy {}", violation.locked, violation.requested));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Compare a lockfile against its depfile: is every requested package locked, at a version
+/// the depfile allows?
+///
+/// Conda versions are compared with conda's ordering, pip versions with PEP 440.
+pub fn validate_lockfile(dep_spec: &DepSpec, lock_spec: &LockSpec) -> Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+
+    let requested_conda = dep_spec.conda_specs().map_err(|e| Error::BadDepfile(e.to_string()))?;
+    let locked_conda = lock_spec.conda_specs().map_err(|e| Error::BadLockfile(e.to_string()))?;
+    let locked_conda: HashMap<&str, &MatchSpec> =
+        locked_conda.iter().map(|ms| (ms.name.as_str(), ms)).collect();
+    for requested in &requested_conda {
+        match locked_conda.get(requested.name.as_str()) {
+            None => report.missing_conda.push(requested.name.clone()),
+            Some(locked) if !conda_satisfies(requested, locked) => {
+                report.violations.push(Violation {
+                    name: requested.name.clone(),
+                    requested: requested.to_string(),
+                    locked: locked.to_string(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    let requested_pip = dep_spec.pip_lines().map_err(|e| Error::BadDepfile(e.to_string()))?;
+    let locked_pip = lock_spec.pip_lines().map_err(|e| Error::BadLockfile(e.to_string()))?;
+    let locked_pip = locked_pip_versions(&locked_pip);
+    for requested in requested_pip.iter().filter_map(PipLine::requirement) {
+        let name = match requested.normalized_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        match locked_pip.get(&name) {
+            // We can't evaluate environment markers, so a requirement with one may
+            // legitimately be missing.
+            None if requested.marker.is_some() => {}
+            None => report.missing_pip.push(name),
+            Some(locked) if !pip_satisfies(requested, locked.as_ref()) => {
+                let locked = locked.as_ref().map(|v| v.to_string()).unwrap_or_default();
+                report.violations.push(Violation {
+                    requested: format!("{}{}", name, requested.specifier.as_deref().unwrap_or("")),
+                    locked: format!("{}=={}", name, locked),
+                    name,
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(report)
+}
+
+/// Does the lockfile contain every package requested by the depfile, at a version the
+/// depfile allows?
+pub fn lockfile_is_valid(dep_spec: &DepSpec, lock_spec: &LockSpec) -> Result<bool> {
+    validate_lockfile(dep_spec, lock_spec).map(|report| report.is_valid())
+}
+
+/// The version of a locked conda package.
+fn locked_conda_version(locked: &MatchSpec) -> Option<CondaVersion> {
+    CondaVersion::parse(locked.locked_version()?).ok()
+}
+
+fn conda_satisfies(requested: &MatchSpec, locked: &MatchSpec) -> bool {
+    if let Some(version_spec) = requested.version_spec() {
+        match locked_conda_version(locked) {
+            Some(version) if version_spec.matches(&version) => {}
+            _ => return false,
+        }
+    }
+    if let Some(ref build) = requested.build {
+        match locked.build {
+            Some(ref locked_build) if glob_matches(build, locked_build) => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Map each locked pip package to its pinned version, if it is pinned with `==`.
+fn locked_pip_versions(lines: &[PipLine]) -> HashMap<String, Option<Version>> {
+    lines
+        .iter()
+        .filter_map(PipLine::requirement)
+        .filter_map(|req| {
+            let name = req.normalized_name()?;
+            let version = req
+                .specifier
+                .as_ref()
+                .and_then(|s| s.strip_prefix("=="))
+                .and_then(|v| Version::parse(v).ok());
+            Some((name, version))
+        }).collect()
+}
+
+fn pip_satisfies(requested: &Requirement, locked: Option<&Version>) -> bool {
+    match (requested.specifier_set(), locked) {
+        (Some(specifier), Some(version)) => specifier.contains(version),
+        // Nothing to check, or a package installed from a URL or path.
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPFILE: &str = "
+name: foo
+dependencies:
+  - python=3.6
+  - pip:
+    - requests
+";
+
+    #[test]
+    fn valid_lockfile() {
+        let lockfile = "
+name: foo
+dependencies:
+  - python=3.6.6=hc3d631a_0
+  - openssl=1.0.2p=h14c3975_0
+  - pip:
+    - requests==2.19.1
+";
+        let dep_spec = DepSpec::parse(DEPFILE).unwrap();
+        assert!(lockfile_is_valid(&dep_spec, &LockSpec::parse(lockfile).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn missing_pip_package() {
+        let lockfile = "
+name: foo
+dependencies:
+  - python=3.6.6=hc3d631a_0
+";
+        let dep_spec = DepSpec::parse(DEPFILE).unwrap();
+        assert!(!lockfile_is_valid(&dep_spec, &LockSpec::parse(lockfile).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn pip_names_are_normalized() {
+        let dep_spec = DepSpec::parse("
+name: foo
+dependencies:
+  - pip:
+    - Foo_Bar[extra]>=1.0
+    - requests>=2; python_version >= \"3\"
+    - -e ./local
+").unwrap();
+        let lock_spec = LockSpec::parse("
+name: foo
+dependencies:
+  - pip:
+    - foo-bar==1.2
+    - requests==2.19.1
+").unwrap();
+        assert!(lockfile_is_valid(&dep_spec, &lock_spec).unwrap());
+    }
+
+    #[test]
+    fn conda_names_from_match_specs() {
+        let dep_spec = DepSpec::parse("
+name: foo
+dependencies:
+  - conda-forge::numpy>=1.15
+  - python<3.8
+  - pandas 0.23.* py36_0
+").unwrap();
+        let lock_spec = LockSpec::parse("
+name: foo
+dependencies:
+  - numpy=1.15.4=py36_0
+  - python=3.6.6=hc3d631a_0
+  - pandas=0.23.4=py36_0
+").unwrap();
+        assert!(lockfile_is_valid(&dep_spec, &lock_spec).unwrap());
+    }
+
+    fn valid(depfile: &str, lockfile: &str) -> bool {
+        let dep_spec = DepSpec::parse(depfile).unwrap();
+        let lock_spec = LockSpec::parse(lockfile).unwrap();
+        lockfile_is_valid(&dep_spec, &lock_spec).unwrap()
+    }
+
+    #[test]
+    fn conda_version_constraints() {
+        let lockfile = "
+name: foo
+dependencies:
+  - numpy=1.10.4=py36_0
+  - python=3.6.6=hc3d631a_0
+";
+        assert!(!valid("name: foo\ndependencies:\n  - numpy>=1.15\n", lockfile));
+        assert!(valid("name: foo\ndependencies:\n  - numpy>=1.10,<1.11\n", lockfile));
+        assert!(valid("name: foo\ndependencies:\n  - numpy=1.10\n", lockfile));
+        assert!(!valid("name: foo\ndependencies:\n  - python=3.7\n", lockfile));
+        assert!(valid("name: foo\ndependencies:\n  - numpy 1.10.* py36*\n", lockfile));
+        assert!(!valid("name: foo\ndependencies:\n  - numpy 1.10.* py37*\n", lockfile));
+    }
+
+    #[test]
+    fn pip_version_constraints() {
+        let lockfile = "
+name: foo
+dependencies:
+  - pip:
+    - requests==2.19.1
+    - my-pkg==1.0rc1
+";
+        assert!(valid("name: foo\ndependencies:\n  - pip:\n    - requests>=2\n", lockfile));
+        assert!(!valid("name: foo\ndependencies:\n  - pip:\n    - requests>=2.20\n", lockfile));
+        assert!(!valid("name: foo\ndependencies:\n  - pip:\n    - My_Pkg>=1.0\n", lockfile));
+        assert!(valid("name: foo\ndependencies:\n  - pip:\n    - My_Pkg~=1.0rc1\n", lockfile));
+        assert!(valid(
+            "name: foo\ndependencies:\n  - pip:\n    - pywin32; sys_platform == 'win32'\n",
+            lockfile
+        ));
+    }
+
+    #[test]
+    fn report_lists_every_problem() {
+        let dep_spec = DepSpec::parse("
+name: foo
+dependencies:
+  - numpy>=1.15
+  - scipy
+  - python=3.6
+  - pip:
+    - requests>=2.20
+    - Flask
+").unwrap();
+        let lock_spec = LockSpec::parse("
+name: foo
+dependencies:
+  - numpy=1.10.4=py36_0
+  - python=3.6.6=hc3d631a_0
+  - pip:
+    - requests==2.19.1
+").unwrap();
+        let report = validate_lockfile(&dep_spec, &lock_spec).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.missing_conda, ["scipy"]);
+        assert_eq!(report.missing_pip, ["flask"]);
+        assert_eq!(report.violations.len(), 2);
+        assert_eq!(report.violations[0].name, "numpy");
+        assert_eq!(report.violations[1].name, "requests");
+        assert_eq!(
+            report.to_string(),
+            "missing conda packages: scipy\n\
+             missing pip packages: flask\n\
+             numpy 1.10.4 py36_0 does not satisfy numpy >=1.15\n\
+             requests==2.19.1 does not satisfy requests>=2.20"
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Checking that an installed environment still holds what its lockfile locked.
+//!
+//! `conda-lockfile create` copies the lockfile into the prefix as `deps.yml.lock`; anything
+//! installed, removed or changed in the environment since then shows up here.  Optionally,
+//! every file conda installed is checked against what conda recorded about it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use conda::{find_conda, force_reinstall};
+use conda_version::CondaVersion;
+use hash::HashAlgorithm;
+use lockfile::Lockfile;
+use pep440::Version;
+use pip::normalize_name;
+use prefix::{
+    conda_records, distributions, pip_distributions, CondaRecord, Distribution, PathEntry,
+};
+use {Error, Result};
+
+/// A package installed at a different version, build or checksum than locked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub name: String,
+    /// The lockfile entry, e.g. `1.15.4 py37_0`.
+    pub locked: String,
+    pub installed: String,
+}
+
+/// How the installed packages of one kind differ from the locked ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageReport {
+    /// Locked but not installed.
+    pub missing: Vec<String>,
+    /// Installed but not locked.
+    pub extra: Vec<String>,
+    pub mismatched: Vec<Mismatch>,
+}
+
+impl PackageReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.mismatched.is_empty()
+    }
+
+    fn lines(&self, kind: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.missing.is_empty() {
+            lines.push(format!("missing {} packages: {}", kind, self.missing.join(", ")));
+        }
+        if !self.extra.is_empty() {
+            lines.push(format!("extra {} packages: {}", kind, self.extra.join(", ")));
+        }
+        for mismatch in &self.mismatched {
+            lines.push(format!(
+                "{} is installed as {}, locked as {}",
+                mismatch.name, mismatch.installed, mismatch.locked
+            ));
+        }
+        lines
+    }
+}
+
+/// A conda package whose installed files no longer match what conda recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptPackage {
+    pub record: CondaRecord,
+    pub missing: Vec<String>,
+    /// Files whose size or checksum changed.
+    pub modified: Vec<String>,
+}
+
+impl fmt::Display for CorruptPackage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut problems = Vec::new();
+        if !self.missing.is_empty() {
+            problems.push(format!("missing {}", self.missing.join(", ")));
+        }
+        if !self.modified.is_empty() {
+            problems.push(format!("modified {}", self.modified.join(", ")));
+        }
+        write!(f, "{} has {}", self.record.describe(), problems.join("; "))
+    }
+}
+
+/// Everything installed in an environment that its lockfile doesn't account for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub conda: PackageReport,
+    pub pip: PackageReport,
+    /// Packages with damaged files.  Only checked when asked for.
+    pub corrupted: Vec<CorruptPackage>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.conda.is_clean() && self.pip.is_clean() && self.corrupted.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "environment matches its lockfile");
+        }
+        let mut lines = self.conda.lines("conda");
+        lines.extend(self.pip.lines("pip"));
+        lines.extend(self.corrupted.iter().map(|package| package.to_string()));
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+fn same_conda_version(a: &str, b: &str) -> bool {
+    match (CondaVersion::parse(a), CondaVersion::parse(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn same_pip_version(a: &str, b: &str) -> bool {
+    match (Version::parse(a), Version::parse(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn compare_conda(lockfile: &Lockfile, records: &[CondaRecord]) -> Result<PackageReport> {
+    let locked = lockfile
+        .spec
+        .conda_specs()
+        .map_err(|e| Error::BadLockfile(format!("{}: {}", lockfile.path.display(), e)))?;
+    // Explicit and conda-lock lockfiles know each package's checksum.
+    let checksums: BTreeMap<String, &str> = lockfile
+        .packages
+        .iter()
+        .filter_map(|package| Some((package.parts()?.0, package.md5.as_deref()?)))
+        .collect();
+    let mut installed: BTreeMap<&str, &CondaRecord> =
+        records.iter().map(|record| (record.name.as_str(), record)).collect();
+
+    let mut report = PackageReport::default();
+    for ms in &locked {
+        let version = ms.locked_version().unwrap_or("").to_string();
+        let describe = |build: Option<&String>| match build {
+            Some(build) => format!("{} {}", version, build),
+            None => version.clone(),
+        };
+        let record = match installed.remove(ms.name.as_str()) {
+            Some(record) => record,
+            None => {
+                report.missing.push(format!("{} {}", ms.name, describe(ms.build.as_ref())));
+                continue;
+            }
+        };
+        let md5 = checksums.get(&ms.name);
+        let matches = same_conda_version(&version, &record.version)
+            && ms.build.as_ref().is_none_or(|build| *build == record.build)
+            && md5.is_none_or(|md5| record.md5.as_deref().is_none_or(|m| m == *md5));
+        if !matches {
+            let mut mismatch = Mismatch {
+                name: ms.name.clone(),
+                locked: describe(ms.build.as_ref()),
+                installed: format!("{} {}", record.version, record.build),
+            };
+            if mismatch.locked == mismatch.installed {
+                // Same version and build, different file.
+                mismatch.locked.push_str(&format!(" (md5 {})", md5.unwrap_or(&"")));
+                let installed_md5 = record.md5.as_deref().unwrap_or("");
+                mismatch.installed.push_str(&format!(" (md5 {})", installed_md5));
+            }
+            report.mismatched.push(mismatch);
+        }
+    }
+    report.extra = installed.values().map(|record| record.describe()).collect();
+    Ok(report)
+}
+
+fn compare_pip(lockfile: &Lockfile, dists: &[&Distribution]) -> Result<PackageReport> {
+    let lines = lockfile
+        .spec
+        .pip_lines()
+        .map_err(|e| Error::BadLockfile(format!("{}: {}", lockfile.path.display(), e)))?;
+    let mut installed: BTreeMap<String, &Distribution> = dists
+        .iter()
+        .map(|dist| (normalize_name(&dist.name), *dist))
+        .collect();
+
+    let mut report = PackageReport::default();
+    for req in lines.iter().filter_map(|line| line.requirement()) {
+        // Requirements on a bare URL don't say which distribution they install.
+        let name = match req.normalized_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        let pinned = req.specifier.as_ref().and_then(|s| s.strip_prefix("=="));
+        let dist = match installed.remove(&name) {
+            Some(dist) => dist,
+            None => {
+                let locked = match pinned {
+                    Some(version) => format!("{} {}", name, version),
+                    None => name,
+                };
+                report.missing.push(locked);
+                continue;
+            }
+        };
+        if let Some(version) = pinned {
+            if !same_pip_version(version, &dist.version) {
+                report.mismatched.push(Mismatch {
+                    name,
+                    locked: version.to_string(),
+                    installed: dist.version.clone(),
+                });
+            }
+        }
+    }
+    report.extra = installed
+        .values()
+        .map(|dist| format!("{} {}", dist.name, dist.version))
+        .collect();
+    Ok(report)
+}
+
+/// Compare what's installed with a lockfile.
+pub fn verify_packages(
+    lockfile: &Lockfile,
+    records: &[CondaRecord],
+    dists: &[Distribution],
+) -> Result<VerifyReport> {
+    Ok(VerifyReport {
+        conda: compare_conda(lockfile, records)?,
+        pip: compare_pip(lockfile, &pip_distributions(dists, records))?,
+        corrupted: Vec::new(),
+    })
+}
+
+/// Is the file at `path` what `entry` says was installed?  `None` if it's missing.
+///
+/// Links, directories and files conda generates itself are only checked for existence.
+fn file_intact(path: &Path, entry: &PathEntry) -> Result<Option<bool>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if entry.path_type != "hardlink" {
+        return Ok(Some(true));
+    }
+    if entry.size.is_some_and(|size| size != metadata.len()) {
+        return Ok(Some(false));
+    }
+    match entry.sha256 {
+        Some(ref sha256) => {
+            let found = HashAlgorithm::Sha256.digest(&fs::read(path)?);
+            Ok(Some(found.eq_ignore_ascii_case(sha256)))
+        }
+        None => Ok(Some(true)),
+    }
+}
+
+/// Check every file of every conda package in `prefix` against its `conda-meta` record.
+pub fn verify_files(prefix: &Path, records: &[CondaRecord]) -> Result<Vec<CorruptPackage>> {
+    let mut corrupted = Vec::new();
+    for record in records {
+        // Records without `paths_data` only say which files exist.
+        let entries: Vec<PathEntry> = if record.paths.is_empty() {
+            record
+                .files
+                .iter()
+                .map(|file| PathEntry {
+                    path: file.clone(),
+                    path_type: "hardlink".to_string(),
+                    ..PathEntry::default()
+                }).collect()
+        } else {
+            record.paths.clone()
+        };
+        let mut package = CorruptPackage {
+            record: record.clone(),
+            missing: Vec::new(),
+            modified: Vec::new(),
+        };
+        for entry in &entries {
+            match file_intact(&prefix.join(&entry.path), entry)? {
+                Some(true) => {}
+                Some(false) => package.modified.push(entry.path.clone()),
+                None => package.missing.push(entry.path.clone()),
+            }
+        }
+        if !package.missing.is_empty() || !package.modified.is_empty() {
+            corrupted.push(package);
+        }
+    }
+    Ok(corrupted)
+}
+
+/// Compare the packages installed in `prefix` with the lockfile it was created from, and if
+/// `check_files`, their files with what conda recorded.
+pub fn verify_prefix(prefix: &Path, check_files: bool) -> Result<VerifyReport> {
+    let lockfile = Lockfile::open(prefix.join("deps.yml.lock"))?;
+    let records = conda_records(prefix)?;
+    let dists = distributions(prefix)?;
+    let mut report = verify_packages(&lockfile, &records, &dists)?;
+    if check_files {
+        report.corrupted = verify_files(prefix, &records)?;
+    }
+    Ok(report)
+}
+
+/// Reinstall the corrupted packages of `prefix` from where they were installed from.
+pub fn reinstall_corrupted(prefix: &Path, corrupted: &[CorruptPackage]) -> Result<()> {
+    let records: Vec<&CondaRecord> = corrupted.iter().map(|package| &package.record).collect();
+    // The exact package files if conda recorded them all, otherwise the exact builds.
+    let packages: Vec<String> = match records.iter().map(|r| r.url.clone()).collect() {
+        Some(urls) => urls,
+        None => records.iter().map(|record| record.spec()).collect(),
+    };
+    force_reinstall(&find_conda()?, prefix, &packages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use platform::Platform;
+
+    fn record(name: &str, version: &str, build: &str, md5: &str) -> CondaRecord {
+        CondaRecord {
+            name: name.to_string(),
+            version: version.to_string(),
+            build: build.to_string(),
+            md5: Some(md5.to_string()),
+            ..CondaRecord::default()
+        }
+    }
+
+    fn dist(name: &str, version: &str, installer: &str) -> Distribution {
+        Distribution {
+            name: name.to_string(),
+            version: version.to_string(),
+            installer: Some(installer.to_string()),
+            path: PathBuf::from(format!("lib/python3.7/site-packages/{}.dist-info", name)),
+        }
+    }
+
+    #[test]
+    fn environment_lockfile() {
+        let lockfile = Lockfile::parse(
+            Path::new("deps.yml.lock"),
+            "# ENVHASH: 1234\nname: foo\ndependencies:\n  - numpy=1.15.4=py37_0\n  \
+             - python=3.7.3=h0_0\n  - six=1.12.0=py_0\n  - pip:\n    - requests==2.21.0\n    \
+             - Flask==1.0\n",
+            Platform::Linux64,
+        ).unwrap();
+        let records = [
+            record("numpy", "1.16.0", "py37_0", "a"),
+            record("python", "3.7.3", "h0_0", "b"),
+            record("zlib", "1.2.11", "h0_0", "c"),
+        ];
+        let dists = [
+            dist("requests", "2.21", "pip"),
+            dist("urllib3", "1.24", "pip"),
+            dist("numpy", "1.16.0", "conda"),
+        ];
+        let report = verify_packages(&lockfile, &records, &dists).unwrap();
+        assert_eq!(report.conda.missing, ["six 1.12.0 py_0"]);
+        assert_eq!(report.conda.extra, ["zlib 1.2.11 h0_0"]);
+        assert_eq!(
+            report.conda.mismatched,
+            [Mismatch {
+                name: "numpy".to_string(),
+                locked: "1.15.4 py37_0".to_string(),
+                installed: "1.16.0 py37_0".to_string(),
+            }]
+        );
+        assert_eq!(report.pip.missing, ["flask 1.0"]);
+        assert_eq!(report.pip.extra, ["urllib3 1.24"]);
+        assert!(report.pip.mismatched.is_empty());
+        assert_eq!(
+            report.to_string().lines().next(),
+            Some("missing conda packages: six 1.12.0 py_0")
+        );
+    }
+
+    #[test]
+    fn corrupted_files() {
+        let prefix = tempfile::tempdir().unwrap();
+        let prefix = prefix.path();
+        fs::create_dir_all(prefix.join("lib")).unwrap();
+        fs::write(prefix.join("lib").join("a.so"), "intact").unwrap();
+        fs::write(prefix.join("lib").join("b.so"), "overwritten").unwrap();
+        fs::write(prefix.join("lib").join("c.txt"), "/opt/env").unwrap();
+        let entry = |path: &str, data: &str| PathEntry {
+            path: path.to_string(),
+            path_type: "hardlink".to_string(),
+            sha256: Some(HashAlgorithm::Sha256.digest(data.as_bytes())),
+            size: Some(data.len() as u64),
+        };
+        let mut rewritten = entry("lib/c.txt", "/opt/env");
+        rewritten.size = None;
+        let damaged = CondaRecord {
+            paths: vec![
+                entry("lib/a.so", "intact"),
+                entry("lib/b.so", "original"),
+                entry("lib/d.so", "deleted"),
+                rewritten,
+            ],
+            ..record("zlib", "1.2.11", "h0_0", "a")
+        };
+        let old_style = CondaRecord {
+            files: vec!["lib/a.so".to_string()],
+            ..record("six", "1.12.0", "py_0", "b")
+        };
+
+        let corrupted = verify_files(prefix, &[damaged, old_style]).unwrap();
+        assert_eq!(corrupted.len(), 1);
+        assert_eq!(corrupted[0].missing, ["lib/d.so"]);
+        assert_eq!(corrupted[0].modified, ["lib/b.so"]);
+        assert_eq!(
+            corrupted[0].to_string(),
+            "zlib 1.2.11 h0_0 has missing lib/d.so; modified lib/b.so"
+        );
+    }
+
+    #[test]
+    fn explicit_checksums() {
+        let lockfile = Lockfile::parse(
+            Path::new("deps.yml.lock"),
+            "# ENVHASH: 1234\n@EXPLICIT\n\
+             https://repo.anaconda.com/pkgs/main/linux-64/python-3.7.3-h0_0.tar.bz2#b\n\
+             https://repo.anaconda.com/pkgs/main/noarch/six-1.12.0-py_0.tar.bz2#c\n",
+            Platform::Linux64,
+        ).unwrap();
+        let python = record("python", "3.7.3", "h0_0", "b");
+        let records = [python.clone(), record("six", "1.12.0", "py_0", "x")];
+        let report = verify_packages(&lockfile, &records, &[]).unwrap();
+        assert!(report.conda.missing.is_empty() && report.conda.extra.is_empty());
+        assert_eq!(report.conda.mismatched[0].locked, "1.12.0 py_0 (md5 c)");
+        assert_eq!(report.conda.mismatched[0].installed, "1.12.0 py_0 (md5 x)");
+
+        let records = [python, record("six", "1.12.0", "py_0", "c")];
+        assert!(verify_packages(&lockfile, &records, &[]).unwrap().is_clean());
+    }
+}